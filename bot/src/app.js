@@ -34,6 +34,36 @@ const commands = [
   {
     name: 'subscribe',
     description: 'subscribe to a feed',
+    options: [{
+      name: 'feed',
+      description: 'url of the feed',
+      required: true,
+      type: 3,
+    }, {
+      name: 'edits',
+      description: 'send announcements again when they are edited (default: true)',
+      required: false,
+      type: 5,
+    }, {
+      name: 'token',
+      description: 'canvas access token, reads the course at `feed` through the canvas api',
+      required: false,
+      type: 3,
+    }]
+  },
+  {
+    name: 'subscriptions',
+    description: 'list the feeds this channel is subscribed to',
+    options: [{
+      name: 'server',
+      description: 'list the subscriptions of every channel in this server',
+      required: false,
+      type: 5,
+    }]
+  },
+  {
+    name: 'unsubscribe',
+    description: 'unsubscribe from a feed',
     options: [{
       name: 'feed',
       description: 'url of the feed',
@@ -58,10 +88,15 @@ const rest = new REST({ version: '9' }).setToken(TOKEN);
 })();
 
 const { Client, Intents, Interaction, MessageEmbed } = require('discord.js');
+const { postFeed } = require('./post');
 var bot = new Client({ intents: [Intents.FLAGS.GUILDS] });
 
 bot.on('ready', () => {
   console.log(`Logged in as ${bot.user.tag}!`);
+
+  watchAnnouncements();
+  warnFailingFeeds();
+  setInterval(warnFailingFeeds, 60 * 60 * 1000);
 });
 
 bot.on('interactionCreate', async (interaction) => {
@@ -73,6 +108,10 @@ bot.on('interactionCreate', async (interaction) => {
     await updateCommand(interaction);
   } else if (interaction.commandName === 'subscribe') {
     await subscribeCommand(interaction);
+  } else if (interaction.commandName === 'unsubscribe') {
+    await unsubscribeCommand(interaction);
+  } else if (interaction.commandName === 'subscriptions') {
+    await subscriptionsCommand(interaction);
   }
 });
 
@@ -83,6 +122,8 @@ async function subscribeCommand(interaction) {
   const guildid = interaction.guildId;
   const channelid = interaction.channelId;
   const feed = interaction.options.getString('feed');
+  const edits = interaction.options.getBoolean('edits') ?? true;
+  const token = interaction.options.getString('token') ?? '';
 
   var client = new canvasrss_proto.CanvasRss(TARGET, grpc.credentials.createInsecure());
 
@@ -94,6 +135,8 @@ async function subscribeCommand(interaction) {
   let subscribeRequest = {
     feed: feed,
     subscriber: subscriber,
+    skip_edits: !edits,
+    canvas_token: token,
   }
   client.subscribe(subscribeRequest, function(err, response) {
     if (response.success === true) {
@@ -104,35 +147,172 @@ async function subscribeCommand(interaction) {
   });
 }
 
+// Cursor of the last feed received from watchAnnouncements
+let watchCursor = 0;
+
 /**
-* @param {Interaction} interaction
+* Receive new announcements as soon as the server finds them,
+* reconnects and resumes from the last cursor when the stream breaks,
+* catches up with the announcements that were not acknowledged when it can not resume
 */
-async function updateCommand(interaction) {
+function watchAnnouncements() {
   var client = new canvasrss_proto.CanvasRss(TARGET, grpc.credentials.createInsecure());
 
-  let newAnnouncementsRequest = {}
-  let call = client.newAnnouncements(newAnnouncementsRequest);
+  let resuming = watchCursor !== 0;
+  let call = client.watchAnnouncements({ cursor: watchCursor });
+  call.on('metadata', function() {
+    // Without a cursor to resume from, what was found before is still waiting to be acknowledged
+    if (!resuming) {
+      catchUp(client);
+    }
+  });
   call.on('data', async function(feed) {
-    let channels = [];
-    for (let key in feed.subscribers) {
-      const subscriber = feed.subscribers[key];
-      const serverId = subscriber.serverId;
-      const channelId = subscriber.channelId
-      
-      const channel = await bot.channels.fetch(channelId);
-
-      channels.push(channel);
+    watchCursor = feed.cursor;
+    await postFeed(bot, client, feed);
+  });
+
+  call.on('error', function(error) {
+    console.error(error.details);
+    if (error.code === grpc.status.FAILED_PRECONDITION) {
+      // Scheduler disabled, only /update works
+      return;
+    }
+    if (error.code === grpc.status.OUT_OF_RANGE) {
+      watchCursor = 0;
     }
+    setTimeout(watchAnnouncements, 5000);
+  });
+}
 
-    for (let key in feed.announcements) {
-      const announcement = feed.announcements[key];
-      const embed = buildAnnouncementEmbed(announcement);
+/**
+* Post the announcements that were not acknowledged yet
+*/
+function catchUp(client) {
+  let call = client.newAnnouncements({});
+  call.on('data', async function(feed) {
+    await postFeed(bot, client, feed);
+  });
+
+  call.on('error', function(error) {
+    console.error(error.details);
+  });
+}
+
+// Guild, channel and feed triples that were already warned about their disabled feed
+var warned = new Set();
 
-      for (let key in channels) {
-        const channel = channels[key]
-        channel.send({ embeds: [embed] });
+/**
+* Warn the subscribed channels once when the server stopped polling their feed
+*/
+function warnFailingFeeds() {
+  var client = new canvasrss_proto.CanvasRss(TARGET, grpc.credentials.createInsecure());
+
+  client.feedStatus({ failing: true }, async function(err, response) {
+    if (err) {
+      console.error(err.details);
+      return;
+    }
+
+    const disabled = new Set();
+    for (let key in response.feeds) {
+      const status = response.feeds[key];
+      if (!status.disabled || !bot.guilds.cache.has(status.subscriber.serverId)) {
+        continue;
+      }
+
+      const id = `${status.subscriber.serverId} ${status.subscriber.channelId} ${status.id}`;
+      disabled.add(id);
+      if (warned.has(id)) {
+        continue;
       }
+
+      try {
+        const channel = await bot.channels.fetch(status.subscriber.channelId);
+        await channel.send(`${status.title} <${status.feed}> failed ${status.failures} times in a row `
+          + `and is no longer checked (${status.last_error}), subscribe to it again once it works`);
+        warned.add(id);
+      } catch (error) {
+        console.error(error);
+      }
+    }
+
+    // Warn again when a feed that was enabled in the meantime gets disabled again
+    warned = new Set([...warned].filter((id) => disabled.has(id)));
+  });
+}
+
+/**
+* @param {Interaction} interaction
+*/
+async function unsubscribeCommand(interaction) {
+  const guildid = interaction.guildId;
+  const channelid = interaction.channelId;
+  const feed = interaction.options.getString('feed');
+
+  var client = new canvasrss_proto.CanvasRss(TARGET, grpc.credentials.createInsecure());
+
+  let subscriber = {
+    serverId: guildid,
+    channelId: channelid,
+  }
+
+  let unsubscribeRequest = {
+    feed: feed,
+    subscriber: subscriber,
+  }
+  client.unsubscribe(unsubscribeRequest, function(err, response) {
+    if (response.success === true) {
+      interaction.reply(response.message);
+    } else {
+      interaction.reply({ content: response.message, ephemeral: true });
+    }
+  });
+}
+
+/**
+* @param {Interaction} interaction
+*/
+async function subscriptionsCommand(interaction) {
+  const server = interaction.options.getBoolean('server') ?? false;
+
+  var client = new canvasrss_proto.CanvasRss(TARGET, grpc.credentials.createInsecure());
+
+  let listSubscriptionsRequest = {
+    serverId: interaction.guildId,
+    channelId: server ? '' : interaction.channelId,
+  }
+  client.listSubscriptions(listSubscriptionsRequest, function(err, response) {
+    if (err) {
+      interaction.reply({ content: 'Oops something went wrong', ephemeral: true });
+      return;
+    }
+
+    if (response.subscriptions.length === 0) {
+      interaction.reply({ content: 'No subscriptions', ephemeral: true });
+      return;
     }
+
+    const lines = response.subscriptions.map((subscription) => {
+      const line = `<#${subscription.subscriber.channelId}> ${subscription.title} <${subscription.feed}>`;
+      if (subscription.sink_error) {
+        return `${line} (${subscription.sink_type} stopped: ${subscription.sink_error})`;
+      }
+      return line;
+    });
+    interaction.reply({ content: lines.join('\n'), ephemeral: true });
+  });
+}
+
+/**
+* @param {Interaction} interaction
+*/
+async function updateCommand(interaction) {
+  var client = new canvasrss_proto.CanvasRss(TARGET, grpc.credentials.createInsecure());
+
+  let newAnnouncementsRequest = {}
+  let call = client.newAnnouncements(newAnnouncementsRequest);
+  call.on('data', async function(feed) {
+    await postFeed(bot, client, feed);
   });
 
   call.on('end', function(feed) {
@@ -161,9 +341,9 @@ async function testCommand(interaction) {
   call.on('data', function(feed) {
     for (let key in feed.announcements) {
       const announcement = feed.announcements[key];
-      const embed = buildAnnouncementEmbed(announcement);
-      console.log(embed);
-      interaction.channel.send({ embeds: [embed] });
+      for (let message of announcement.messages) {
+        interaction.channel.send(JSON.parse(message));
+      }
     }
   });
 
@@ -173,33 +353,6 @@ async function testCommand(interaction) {
   });
 }
 
-const TurndownService = require('turndown')
-/**
-* @param {Announcement} announcement
-* @returns {MessageEmbed}
-*/
-function buildAnnouncementEmbed(announcement) {
-  const ts = new TurndownService();
-
-  let date = new Date(Date.UTC(1970, 0, 1)); // Epoch
-  date.setSeconds(announcement.published.seconds);
-
-  const embed = new MessageEmbed({
-    color: '#E63F30',
-    title: announcement.title,
-    url: announcement.link,
-    author: {
-      name: announcement.author,
-    },
-    description: ts.turndown(announcement.content),
-    footer: {
-      text: date.toString(),
-    }
-  });
-  return embed;
-}
-
-
 function main() {
   let client = new canvasrss_proto.CanvasRss(TARGET, grpc.credentials.createInsecure());
 
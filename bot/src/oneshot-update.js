@@ -18,34 +18,9 @@ var canvasrss_proto = grpc.loadPackageDefinition(packageDefinition).canvasrss;
 const TARGET = 'localhost:50051';
 
 const { Client, Intents, Interaction, MessageEmbed } = require('discord.js');
+const { postFeed } = require('./post');
 
 
-const TurndownService = require('turndown')
-/**
-* @param {Announcement} announcement
-* @returns {MessageEmbed}
-*/
-function buildAnnouncementEmbed(announcement) {
-  const ts = new TurndownService();
-
-  let date = new Date(Date.UTC(1970, 0, 1)); // Epoch
-  date.setSeconds(announcement.published.seconds);
-
-  const embed = new MessageEmbed({
-    color: '#E63F30',
-    title: announcement.title,
-    url: announcement.link,
-    author: {
-      name: announcement.author,
-    },
-    description: ts.turndown(announcement.content),
-    footer: {
-      text: date.toString(),
-    }
-  });
-  return embed;
-}
-
 var bot = new Client({ intents: [Intents.FLAGS.GUILDS] });
 
 bot.on('ready', () => {
@@ -63,26 +38,7 @@ bot.on('ready', () => {
 
       call.on('data', async function (feed) {
         dataTasks++;
-        let channels = [];
-        for (let key in feed.subscribers) {
-          const subscriber = feed.subscribers[key];
-          const serverId = subscriber.serverId;
-          const channelId = subscriber.channelId
-
-          const channel = await bot.channels.fetch(channelId);
-
-          channels.push(channel);
-        }
-
-        for (let key in feed.announcements) {
-          const announcement = feed.announcements[key];
-          const embed = buildAnnouncementEmbed(announcement);
-
-          for (let key in channels) {
-            const channel = channels[key]
-            channel.send({ embeds: [embed] });
-          }
-        }
+        await postFeed(bot, client, feed);
 
         dataTasks--;
         if(end && dataTasks == 0) {
@@ -0,0 +1,86 @@
+// Posting of the feeds the server sends, shared by the bot and the one-shot updater
+
+/**
+* Ping the mentions of a subscriber with the message when the announcement pings them
+*/
+function mention(message, mentions, id) {
+  if (!mentions || !mentions.announcements.includes(id)) {
+    return;
+  }
+
+  const pings = mentions.role_ids.map((role) => `<@&${role}>`)
+    .concat(mentions.user_ids.map((user) => `<@${user}>`));
+  if (mentions.here) {
+    pings.push('@here');
+  }
+
+  message.content = pings.join(' ');
+  message.allowedMentions = {
+    parse: mentions.here ? ['everyone'] : [],
+    roles: mentions.role_ids,
+    users: mentions.user_ids,
+  };
+}
+
+/**
+* Send the announcements of a feed to every subscribed channel
+* and acknowledge them once they are posted, resolves when every ack is answered
+*/
+async function postFeed(bot, client, feed) {
+  if (feed.error) {
+    console.error(`Failed to retrieve ${feed.id}: ${feed.error.message}`);
+    return;
+  }
+
+  const ids = feed.announcements.map((announcement) => announcement.id);
+
+  for (let key in feed.subscribers) {
+    const subscriber = feed.subscribers[key];
+
+    try {
+      const channel = await bot.channels.fetch(subscriber.channelId);
+      if (feed.digest) {
+        // One message listing every announcement of the window
+        const messages = feed.digest.messages.map(JSON.parse);
+        const pings = ids.find((id) => subscriber.mentions && subscriber.mentions.announcements.includes(id));
+        if (messages.length > 0 && pings) {
+          mention(messages[0], subscriber.mentions, pings);
+        }
+        for (let message of messages) {
+          await channel.send(message);
+        }
+      } else {
+        for (let announcement of feed.announcements) {
+          // Rendered by the server within the limits of discord
+          const messages = announcement.messages.map(JSON.parse);
+          if (messages.length > 0) {
+            mention(messages[0], subscriber.mentions, announcement.id);
+          }
+          for (let message of messages) {
+            await channel.send(message);
+          }
+        }
+      }
+    } catch (error) {
+      // Not acknowledged, the server sends it again on the next update
+      console.error(error);
+      continue;
+    }
+
+    let ackDeliveryRequest = {
+      subscriber: subscriber,
+      announcements: ids,
+      feed: feed.id,
+    }
+    await new Promise((resolve) => {
+      client.ackDelivery(ackDeliveryRequest, function(err, response) {
+        if (err) {
+          console.error(err.details);
+        }
+        resolve();
+      });
+    });
+  }
+}
+
+module.exports = { postFeed };
@@ -1,6 +1,11 @@
 use proto_canvas_rss::canvas_rss_client::CanvasRssClient;
 use proto_canvas_rss::{
-    HelloRequest, ListFeedsRequest, NewAnnouncementsRequest, SubscribeRequest, Subscriber,
+    AckDeliveryRequest, DeliveryMode, FeedStatusRequest, Filter, GetFilterRequest,
+    GetQuietHoursRequest, HelloRequest, ListAnnouncementsRequest, ListFeedsRequest,
+    ListSubscriptionsRequest, Mentions, NewAnnouncementsRequest, QuietHours,
+    SetDeliveryModeRequest, SetFilterRequest, SetMentionsRequest, SetPollIntervalRequest,
+    SetQuietHoursRequest, SetSchedulerRequest, SetSinkRequest, SubscribeRequest, Subscriber,
+    WatchAnnouncementsRequest,
 };
 use std::error::Error;
 use std::time::SystemTime;
@@ -33,6 +38,23 @@ async fn get_feeds(client: &mut CanvasRssClient<Channel>) -> Result<(), Box<dyn
     Ok(())
 }
 
+#[allow(dead_code)]
+async fn list_announcements(
+    feed: String,
+    client: &mut CanvasRssClient<Channel>,
+) -> Result<(), Box<dyn Error>> {
+    let list_announcements_request = ListAnnouncementsRequest { feed, after: None };
+
+    let response = client
+        .list_announcements(Request::new(list_announcements_request))
+        .await?
+        .into_inner();
+
+    dbg!(response);
+
+    Ok(())
+}
+
 #[allow(dead_code)]
 async fn get_new_announcements(
     client: &mut CanvasRssClient<Channel>,
@@ -57,6 +79,61 @@ async fn get_new_announcements(
     Ok(())
 }
 
+#[allow(dead_code)]
+async fn ack_delivery(
+    guild_id: String,
+    channel_id: String,
+    feed: String,
+    announcements: Vec<String>,
+    client: &mut CanvasRssClient<Channel>,
+) -> Result<(), Box<dyn Error>> {
+    let subscriber = Subscriber {
+        server_id: guild_id,
+        channel_id,
+        mentions: None,
+    };
+    let ack_delivery_request = AckDeliveryRequest {
+        subscriber: Some(subscriber),
+        announcements,
+        feed,
+    };
+
+    let response = client
+        .ack_delivery(Request::new(ack_delivery_request))
+        .await?
+        .into_inner();
+
+    dbg!(response);
+
+    Ok(())
+}
+
+#[allow(dead_code)]
+async fn watch_announcements(
+    cursor: u64,
+    client: &mut CanvasRssClient<Channel>,
+) -> Result<u64, Box<dyn Error>> {
+    let watch_announcements_request = WatchAnnouncementsRequest { cursor };
+
+    let mut stream = client
+        .watch_announcements(Request::new(watch_announcements_request))
+        .await?
+        .into_inner();
+
+    // Remember the cursor to resume from after reconnecting
+    let mut cursor = cursor;
+    while let Some(feed) = stream.message().await? {
+        println!(
+            "{} new announcements for {}",
+            feed.announcements.len(),
+            feed.id
+        );
+        cursor = feed.cursor;
+    }
+
+    Ok(cursor)
+}
+
 #[allow(dead_code)]
 async fn subscribe(
     guild_id: String,
@@ -66,11 +143,16 @@ async fn subscribe(
 ) -> Result<(), Box<dyn Error>> {
     let subscriber = Subscriber {
         server_id: guild_id,
-        channel_id: channel_id,
+        channel_id,
+        mentions: None,
     };
     let subscribe_request = SubscribeRequest {
         feed,
         subscriber: Some(subscriber),
+        skip_edits: false,
+        canvas_token: String::new(),
+        sink_type: String::new(),
+        sink_config: String::new(),
     };
 
     let response = client
@@ -83,6 +165,301 @@ async fn subscribe(
     Ok(())
 }
 
+#[allow(dead_code)]
+async fn list_subscriptions(
+    guild_id: String,
+    channel_id: String,
+    client: &mut CanvasRssClient<Channel>,
+) -> Result<(), Box<dyn Error>> {
+    let list_subscriptions_request = ListSubscriptionsRequest {
+        server_id: guild_id,
+        channel_id,
+    };
+
+    let response = client
+        .list_subscriptions(Request::new(list_subscriptions_request))
+        .await?
+        .into_inner();
+
+    dbg!(response);
+
+    Ok(())
+}
+
+#[allow(dead_code)]
+async fn feed_status(
+    guild_id: String,
+    channel_id: String,
+    failing: bool,
+    client: &mut CanvasRssClient<Channel>,
+) -> Result<(), Box<dyn Error>> {
+    let feed_status_request = FeedStatusRequest {
+        server_id: guild_id,
+        channel_id,
+        failing,
+    };
+
+    let response = client
+        .feed_status(Request::new(feed_status_request))
+        .await?
+        .into_inner();
+
+    dbg!(response);
+
+    Ok(())
+}
+
+#[allow(dead_code)]
+async fn unsubscribe(
+    guild_id: String,
+    channel_id: String,
+    feed: String,
+    client: &mut CanvasRssClient<Channel>,
+) -> Result<(), Box<dyn Error>> {
+    let subscriber = Subscriber {
+        server_id: guild_id,
+        channel_id,
+        mentions: None,
+    };
+    let unsubscribe_request = SubscribeRequest {
+        feed,
+        subscriber: Some(subscriber),
+        skip_edits: false,
+        canvas_token: String::new(),
+        sink_type: String::new(),
+        sink_config: String::new(),
+    };
+
+    let response = client
+        .unsubscribe(Request::new(unsubscribe_request))
+        .await?
+        .into_inner();
+
+    dbg!(response);
+
+    Ok(())
+}
+
+#[allow(dead_code)]
+async fn set_sink(
+    guild_id: String,
+    channel_id: String,
+    feed: String,
+    sink_type: String,
+    sink_config: String,
+    client: &mut CanvasRssClient<Channel>,
+) -> Result<(), Box<dyn Error>> {
+    let subscriber = Subscriber {
+        server_id: guild_id,
+        channel_id,
+        mentions: None,
+    };
+    let set_sink_request = SetSinkRequest {
+        feed,
+        subscriber: Some(subscriber),
+        sink_type,
+        sink_config,
+    };
+
+    let response = client
+        .set_sink(Request::new(set_sink_request))
+        .await?
+        .into_inner();
+
+    dbg!(response);
+
+    Ok(())
+}
+
+#[allow(dead_code)]
+async fn set_filter(
+    guild_id: String,
+    channel_id: String,
+    feed: String,
+    filter: Filter,
+    client: &mut CanvasRssClient<Channel>,
+) -> Result<(), Box<dyn Error>> {
+    let subscriber = Subscriber {
+        server_id: guild_id,
+        channel_id,
+        mentions: None,
+    };
+    let set_filter_request = SetFilterRequest {
+        feed,
+        subscriber: Some(subscriber),
+        filter: Some(filter),
+    };
+
+    let response = client
+        .set_filter(Request::new(set_filter_request))
+        .await?
+        .into_inner();
+
+    dbg!(response);
+
+    Ok(())
+}
+
+#[allow(dead_code)]
+async fn get_filter(
+    guild_id: String,
+    channel_id: String,
+    feed: String,
+    client: &mut CanvasRssClient<Channel>,
+) -> Result<(), Box<dyn Error>> {
+    let subscriber = Subscriber {
+        server_id: guild_id,
+        channel_id,
+        mentions: None,
+    };
+    let get_filter_request = GetFilterRequest {
+        feed,
+        subscriber: Some(subscriber),
+    };
+
+    let response = client
+        .get_filter(Request::new(get_filter_request))
+        .await?
+        .into_inner();
+
+    dbg!(response);
+
+    Ok(())
+}
+
+#[allow(dead_code)]
+async fn set_delivery_mode(
+    guild_id: String,
+    channel_id: String,
+    feed: String,
+    delivery_mode: DeliveryMode,
+    client: &mut CanvasRssClient<Channel>,
+) -> Result<(), Box<dyn Error>> {
+    let subscriber = Subscriber {
+        server_id: guild_id,
+        channel_id,
+        mentions: None,
+    };
+    let set_delivery_mode_request = SetDeliveryModeRequest {
+        feed,
+        subscriber: Some(subscriber),
+        delivery_mode: Some(delivery_mode),
+    };
+
+    let response = client
+        .set_delivery_mode(Request::new(set_delivery_mode_request))
+        .await?
+        .into_inner();
+
+    dbg!(response);
+
+    Ok(())
+}
+
+#[allow(dead_code)]
+async fn set_mentions(
+    guild_id: String,
+    channel_id: String,
+    feed: String,
+    mentions: Mentions,
+    client: &mut CanvasRssClient<Channel>,
+) -> Result<(), Box<dyn Error>> {
+    let subscriber = Subscriber {
+        server_id: guild_id,
+        channel_id,
+        mentions: None,
+    };
+    let set_mentions_request = SetMentionsRequest {
+        feed,
+        subscriber: Some(subscriber),
+        mentions: Some(mentions),
+    };
+
+    let response = client
+        .set_mentions(Request::new(set_mentions_request))
+        .await?
+        .into_inner();
+
+    dbg!(response);
+
+    Ok(())
+}
+
+#[allow(dead_code)]
+async fn set_quiet_hours(
+    guild_id: String,
+    quiet_hours: Option<QuietHours>,
+    client: &mut CanvasRssClient<Channel>,
+) -> Result<(), Box<dyn Error>> {
+    let set_quiet_hours_request = SetQuietHoursRequest {
+        server_id: guild_id,
+        quiet_hours,
+    };
+
+    let response = client
+        .set_quiet_hours(Request::new(set_quiet_hours_request))
+        .await?
+        .into_inner();
+
+    dbg!(response);
+
+    Ok(())
+}
+
+#[allow(dead_code)]
+async fn get_quiet_hours(
+    guild_id: String,
+    client: &mut CanvasRssClient<Channel>,
+) -> Result<(), Box<dyn Error>> {
+    let get_quiet_hours_request = GetQuietHoursRequest {
+        server_id: guild_id,
+    };
+
+    let response = client
+        .get_quiet_hours(Request::new(get_quiet_hours_request))
+        .await?
+        .into_inner();
+
+    dbg!(response);
+
+    Ok(())
+}
+
+#[allow(dead_code)]
+async fn set_scheduler(
+    paused: bool,
+    client: &mut CanvasRssClient<Channel>,
+) -> Result<(), Box<dyn Error>> {
+    let set_scheduler_request = SetSchedulerRequest { paused };
+
+    let response = client
+        .set_scheduler(Request::new(set_scheduler_request))
+        .await?
+        .into_inner();
+
+    dbg!(response);
+
+    Ok(())
+}
+
+#[allow(dead_code)]
+async fn set_poll_interval(
+    feed: String,
+    seconds: u32,
+    client: &mut CanvasRssClient<Channel>,
+) -> Result<(), Box<dyn Error>> {
+    let set_poll_interval_request = SetPollIntervalRequest { feed, seconds };
+
+    let response = client
+        .set_poll_interval(Request::new(set_poll_interval_request))
+        .await?
+        .into_inner();
+
+    dbg!(response);
+
+    Ok(())
+}
+
 #[allow(dead_code)]
 async fn hello(client: &mut CanvasRssClient<Channel>) -> Result<(), Box<dyn Error>> {
     let hello_request = HelloRequest {
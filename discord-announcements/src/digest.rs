@@ -0,0 +1,334 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use time::{OffsetDateTime, Time};
+use time_tz::{timezones, Offset, OffsetDateTimeExt, PrimitiveDateTimeExt, TimeZone, Tz};
+
+use crate::models::{Announcement, Content};
+use crate::sink::escape_html;
+
+/// When the announcements of a subscription are sent, stored as json in `subscriptions.delivery_mode`
+///
+/// The digests hold the announcements back and send the ones of a window at once when it ends.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum DeliveryMode {
+    /// Every announcement as soon as it is found
+    #[default]
+    Immediate,
+
+    /// One digest at the start of every hour
+    Hourly,
+
+    /// One digest a day at `at` (`HH:MM`) in `timezone` (`Europe/Brussels`)
+    Daily { at: String, timezone: String },
+}
+
+impl DeliveryMode {
+    /// Read the delivery mode of a subscription, `None` is immediate
+    pub fn from_db(delivery_mode: Option<&str>) -> Result<Self, serde_json::Error> {
+        match delivery_mode {
+            Some(delivery_mode) => serde_json::from_str(delivery_mode),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Json to store, `None` for immediate
+    pub fn to_db(&self) -> Option<String> {
+        match self {
+            Self::Immediate => None,
+            _ => serde_json::to_string(self).ok(),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Immediate => "immediate",
+            Self::Hourly => "hourly",
+            Self::Daily { .. } => "daily",
+        }
+    }
+
+    /// Check the time and the timezone before they are stored
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::Daily { at, timezone } => {
+                parse_time(at).ok_or_else(|| format!("invalid time '{at}', use HH:MM"))?;
+                timezones::get_by_name(timezone)
+                    .ok_or_else(|| format!("unknown timezone '{timezone}'"))?;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// End of the last window that ended at or before `now`,
+    /// the announcements queued before it are sent as one digest
+    ///
+    /// `None` when the announcements are sent right away, a daily digest that can not be read is.
+    pub fn window_end(&self, now: SystemTime) -> Option<SystemTime> {
+        match self {
+            Self::Immediate => None,
+            Self::Hourly => {
+                let secs = now.duration_since(UNIX_EPOCH).ok()?.as_secs();
+                Some(UNIX_EPOCH + Duration::from_secs(secs - secs % 3600))
+            }
+            Self::Daily { at, timezone } => {
+                let at = parse_time(at)?;
+                let tz = timezones::get_by_name(timezone)?;
+
+                let now = OffsetDateTime::from(now);
+                let today = now.to_timezone(tz).date();
+                let end = [today, today.previous_day()?]
+                    .into_iter()
+                    .map(|date| local(date.with_time(at), tz))
+                    .find(|end| *end <= now)?;
+
+                Some(end.into())
+            }
+        }
+    }
+}
+
+/// `HH:MM`
+pub(crate) fn parse_time(at: &str) -> Option<Time> {
+    let (hour, minute) = at.trim().split_once(':')?;
+    if minute.len() != 2 {
+        return None;
+    }
+
+    Time::from_hms(hour.parse().ok()?, minute.parse().ok()?, 0).ok()
+}
+
+/// A local time of `tz`, the first one when the clocks go back
+/// and moved forward by the skipped hour when the clocks skip it: 02:30 is 03:30
+pub(crate) fn local(datetime: time::PrimitiveDateTime, tz: &Tz) -> OffsetDateTime {
+    datetime
+        .assume_timezone(tz)
+        .take_first()
+        .unwrap_or_else(|| {
+            // The offset before the change, the clocks never change twice in a day
+            let before = tz.get_offset_utc(&(datetime.assume_utc() - time::Duration::DAY));
+            datetime.assume_offset(before.to_utc())
+        })
+}
+
+impl Announcement {
+    /// A single announcement listing `announcements`, sent instead of them at the end of a window
+    ///
+    /// `scope` is the feed or the subscription the digest is for, digests of different ones
+    /// never share an id.
+    pub fn digest(scope: &str, announcements: &[Announcement], window_end: SystemTime) -> Self {
+        let title = match announcements.len() {
+            1 => String::from("Digest of 1 announcement"),
+            n => format!("Digest of {n} announcements"),
+        };
+
+        let items: String = announcements
+            .iter()
+            .map(|announcement| {
+                let title = escape_html(&announcement.title);
+                let title = match announcement.href() {
+                    "" => title,
+                    href => format!("<a href=\"{}\">{title}</a>", escape_html(href)),
+                };
+                let edited = match announcement.edit {
+                    Some(_) => " (edited)",
+                    None => "",
+                };
+                let author = match announcement.author_name() {
+                    "" => String::new(),
+                    author => format!(" by {}", escape_html(author)),
+                };
+
+                format!("<li>{title}{edited}{author}</li>\n")
+            })
+            .collect();
+
+        let secs = window_end
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        Self {
+            title,
+            id: format!("digest:{scope}:{secs}"),
+            updated: window_end,
+            published: window_end,
+            links: Vec::new(),
+            author: None,
+            content: Content {
+                content_type: String::from("html"),
+                content: format!("<ul>\n{items}</ul>"),
+            },
+            attachments: Vec::new(),
+            sections: Vec::new(),
+            edit: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::announcement;
+    use time::macros::datetime;
+
+    fn at(datetime: OffsetDateTime) -> SystemTime {
+        datetime.into()
+    }
+
+    fn daily(at: &str, timezone: &str) -> DeliveryMode {
+        DeliveryMode::Daily {
+            at: at.to_owned(),
+            timezone: timezone.to_owned(),
+        }
+    }
+
+    #[test]
+    fn immediate() {
+        let now = at(datetime!(2022-01-20 10:30 UTC));
+
+        assert_eq!(DeliveryMode::Immediate.window_end(now), None);
+    }
+
+    #[test]
+    fn hourly() {
+        let hourly = DeliveryMode::Hourly;
+
+        assert_eq!(
+            hourly.window_end(at(datetime!(2022-01-20 10:30 UTC))),
+            Some(at(datetime!(2022-01-20 10:00 UTC)))
+        );
+        assert_eq!(
+            hourly.window_end(at(datetime!(2022-01-20 10:00 UTC))),
+            Some(at(datetime!(2022-01-20 10:00 UTC)))
+        );
+    }
+
+    #[test]
+    fn daily_today() {
+        let mode = daily("08:00", "Europe/Brussels");
+
+        // 08:00 in Brussels is 07:00 UTC in the winter
+        assert_eq!(
+            mode.window_end(at(datetime!(2022-01-20 07:00 UTC))),
+            Some(at(datetime!(2022-01-20 07:00 UTC)))
+        );
+        assert_eq!(
+            mode.window_end(at(datetime!(2022-01-20 22:00 UTC))),
+            Some(at(datetime!(2022-01-20 07:00 UTC)))
+        );
+    }
+
+    #[test]
+    fn daily_previous_day() {
+        let mode = daily("08:00", "Europe/Brussels");
+
+        assert_eq!(
+            mode.window_end(at(datetime!(2022-01-20 06:59 UTC))),
+            Some(at(datetime!(2022-01-19 07:00 UTC)))
+        );
+    }
+
+    #[test]
+    fn daily_other_date_than_utc() {
+        // Already the 21st in Tokyo, 09:00 there is 00:00 UTC
+        let mode = daily("09:00", "Asia/Tokyo");
+
+        assert_eq!(
+            mode.window_end(at(datetime!(2022-01-20 23:00 UTC))),
+            Some(at(datetime!(2022-01-20 00:00 UTC)))
+        );
+        assert_eq!(
+            mode.window_end(at(datetime!(2022-01-21 00:30 UTC))),
+            Some(at(datetime!(2022-01-21 00:00 UTC)))
+        );
+    }
+
+    #[test]
+    fn daily_summer_time() {
+        let mode = daily("08:00", "Europe/Brussels");
+
+        // 08:00 in Brussels is 06:00 UTC after the clocks went forward on the 27th
+        assert_eq!(
+            mode.window_end(at(datetime!(2022-03-27 12:00 UTC))),
+            Some(at(datetime!(2022-03-27 06:00 UTC)))
+        );
+        assert_eq!(
+            mode.window_end(at(datetime!(2022-03-27 06:30 UTC))),
+            Some(at(datetime!(2022-03-27 06:00 UTC)))
+        );
+        assert_eq!(
+            mode.window_end(at(datetime!(2022-03-27 05:30 UTC))),
+            Some(at(datetime!(2022-03-26 07:00 UTC)))
+        );
+    }
+
+    #[test]
+    fn daily_skipped_time() {
+        // 02:30 does not exist in Brussels on the 27th, the window ends at 03:30 summer time
+        let mode = daily("02:30", "Europe/Brussels");
+
+        assert_eq!(
+            mode.window_end(at(datetime!(2022-03-27 12:00 UTC))),
+            Some(at(datetime!(2022-03-27 01:30 UTC)))
+        );
+        assert_eq!(
+            mode.window_end(at(datetime!(2022-03-27 01:00 UTC))),
+            Some(at(datetime!(2022-03-26 01:30 UTC)))
+        );
+    }
+
+    #[test]
+    fn daily_unreadable() {
+        let now = at(datetime!(2022-01-20 10:30 UTC));
+
+        assert_eq!(daily("8h", "Europe/Brussels").window_end(now), None);
+        assert_eq!(daily("08:00", "Mars/Olympus").window_end(now), None);
+    }
+
+    #[test]
+    fn validate() {
+        assert!(daily("08:00", "Europe/Brussels").validate().is_ok());
+        assert!(daily("24:00", "Europe/Brussels").validate().is_err());
+        assert!(daily("8:0", "Europe/Brussels").validate().is_err());
+        assert!(daily("08:00", "Brussels").validate().is_err());
+    }
+
+    #[test]
+    fn db_round_trip() {
+        let mode = daily("08:00", "Europe/Brussels");
+
+        assert_eq!(DeliveryMode::Immediate.to_db(), None);
+        assert_eq!(
+            DeliveryMode::from_db(None).unwrap(),
+            DeliveryMode::Immediate
+        );
+        assert_eq!(
+            DeliveryMode::from_db(mode.to_db().as_deref()).unwrap(),
+            mode
+        );
+    }
+
+    #[test]
+    fn digest() {
+        let window_end = at(datetime!(2022-01-20 10:00 UTC));
+        let announcements = [
+            announcement("Exam <info>", "<p>Exam</p>"),
+            announcement("Welcome", "<p>Welcome</p>"),
+        ];
+
+        let digest = Announcement::digest("7", &announcements, window_end);
+
+        assert_eq!(digest.id, "digest:7:1642672800");
+        assert_eq!(digest.title, "Digest of 2 announcements");
+        assert_eq!(digest.published, window_end);
+        assert_eq!(
+            digest.content.content,
+            "<ul>\n\
+             <li><a href=\"https://canvas.example.com/courses/1/discussion_topics/5\">Exam &lt;info&gt;</a> by Jane Doe</li>\n\
+             <li><a href=\"https://canvas.example.com/courses/1/discussion_topics/5\">Welcome</a> by Jane Doe</li>\n\
+             </ul>"
+        );
+    }
+}
@@ -0,0 +1,499 @@
+use serde::Serialize;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::mentions::Mentions;
+use crate::models::Announcement;
+
+// Limits of discord on a message, in characters
+const TITLE_LIMIT: usize = 256;
+const DESCRIPTION_LIMIT: usize = 4096;
+const AUTHOR_LIMIT: usize = 256;
+const FOOTER_LIMIT: usize = 2048;
+const FIELD_VALUE_LIMIT: usize = 1024;
+
+/// Text outside the embeds, the pings
+const CONTENT_LIMIT: usize = 2000;
+
+/// Sum of the text of all embeds of a message
+const MESSAGE_LIMIT: usize = 6000;
+const EMBEDS_LIMIT: usize = 10;
+
+/// A message with less room left starts a new message instead of a short embed
+const MIN_PIECE: usize = 1000;
+
+const COLOR: u32 = 0xE63F30;
+
+/// Payload of a discord message, serializes to the json discord expects
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DiscordMessage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+
+    pub embeds: Vec<Embed>,
+
+    /// Which mentions of `content` ping, discord parses all of them without it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_mentions: Option<AllowedMentions>,
+}
+
+/// `allowed_mentions` of a discord message
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AllowedMentions {
+    /// Kinds of mentions that ping: `roles`, `users` or `everyone`
+    pub parse: Vec<String>,
+
+    /// Roles that ping, not with `roles` in `parse`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+
+    /// Users that ping, not with `users` in `parse`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub users: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Embed {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    /// RFC 3339
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<EmbedAuthor>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub footer: Option<EmbedFooter>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<EmbedImage>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<EmbedField>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EmbedAuthor {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EmbedFooter {
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EmbedImage {
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EmbedField {
+    pub name: String,
+    pub value: String,
+    pub inline: bool,
+}
+
+/// What to do with content that does not fit in an embed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Continue in more embeds and messages
+    #[default]
+    Split,
+
+    /// Cut it off with a link to the announcement
+    Truncate,
+}
+
+impl DiscordMessage {
+    pub fn to_json(&self) -> String {
+        // Only strings and numbers, this does not fail
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    /// Ping `mentions` with the message, nothing else in it pings
+    ///
+    /// The pings that do not fit in the content are left out.
+    pub fn mention(&mut self, mentions: &Mentions) {
+        if mentions.is_empty() {
+            return;
+        }
+
+        let mut content = mentions.content();
+        if count(&content) > CONTENT_LIMIT {
+            let end = content[..byte_index(&content, CONTENT_LIMIT + 1)]
+                .rfind(' ')
+                .unwrap_or(0);
+            content.truncate(end);
+        }
+
+        self.content = Some(content);
+        self.allowed_mentions = Some(mentions.allowed_mentions());
+    }
+
+    /// Characters discord counts against `MESSAGE_LIMIT`
+    fn len(&self) -> usize {
+        self.embeds.iter().map(Embed::len).sum()
+    }
+}
+
+impl Embed {
+    fn len(&self) -> usize {
+        let len = |s: &Option<String>| s.as_deref().map_or(0, count);
+
+        len(&self.title)
+            + len(&self.description)
+            + self.author.as_ref().map_or(0, |author| count(&author.name))
+            + self.footer.as_ref().map_or(0, |footer| count(&footer.text))
+            + self
+                .fields
+                .iter()
+                .map(|field| count(&field.name) + count(&field.value))
+                .sum::<usize>()
+    }
+}
+
+impl Announcement {
+    /// Messages to post the announcement with, within the limits of discord
+    ///
+    /// Only `Overflow::Split` can return more than one message.
+    pub fn render(&self, overflow: Overflow) -> Vec<DiscordMessage> {
+        let markdown = self.markdown();
+
+        let title = match self.edit {
+            Some(_) => format!("[Edited] {}", self.title),
+            None => self.title.clone(),
+        };
+        let href = Some(self.href()).filter(|href| !href.is_empty());
+
+        let head = Embed {
+            title: Some(truncate(&title, TITLE_LIMIT)),
+            url: href.map(String::from),
+            color: Some(COLOR),
+            author: Some(self.author_name())
+                .filter(|name| !name.is_empty())
+                .map(|name| EmbedAuthor {
+                    name: truncate(name, AUTHOR_LIMIT),
+                }),
+            image: markdown
+                .images
+                .first()
+                .map(|url| EmbedImage { url: url.clone() }),
+            fields: self.attachments_field().into_iter().collect(),
+            ..Default::default()
+        };
+
+        // The last embed gets the footer, there is always room left for it
+        let footer = Some(self.sections.join(", "))
+            .filter(|sections| !sections.is_empty())
+            .map(|sections| EmbedFooter {
+                text: truncate(&sections, FOOTER_LIMIT),
+            });
+        let footer_len = footer.as_ref().map_or(0, |footer| count(&footer.text));
+        let timestamp = OffsetDateTime::from(self.published).format(&Rfc3339).ok();
+
+        let mut messages = Vec::new();
+        let mut message = DiscordMessage::default();
+        let mut embed = head;
+        let mut rest = markdown.text;
+
+        loop {
+            let room = MESSAGE_LIMIT.saturating_sub(message.len() + embed.len() + footer_len);
+            let budget = DESCRIPTION_LIMIT.min(room);
+
+            let (piece, remaining) = match overflow {
+                Overflow::Split => split(&rest, budget),
+                Overflow::Truncate => (cut_off(&rest, budget, href), String::new()),
+            };
+            if !piece.is_empty() {
+                embed.description = Some(piece);
+            }
+
+            if remaining.is_empty() {
+                embed.footer = footer;
+                embed.timestamp = timestamp;
+                message.embeds.push(embed);
+                messages.push(message);
+
+                return messages;
+            }
+
+            message.embeds.push(embed);
+            rest = remaining;
+
+            if message.embeds.len() == EMBEDS_LIMIT
+                || MESSAGE_LIMIT.saturating_sub(message.len() + footer_len) < MIN_PIECE
+            {
+                messages.push(std::mem::take(&mut message));
+            }
+
+            // Continuations only have the text
+            embed = Embed {
+                color: Some(COLOR),
+                ..Default::default()
+            };
+        }
+    }
+
+    /// Links to the attachments, as many as fit in a field
+    fn attachments_field(&self) -> Option<EmbedField> {
+        let mut value = String::new();
+
+        for (i, attachment) in self.attachments.iter().enumerate() {
+            let line = format!("[{}]({})\n", attachment.name, attachment.url);
+            let more = format!("and {} more", self.attachments.len() - i);
+
+            if count(&value) + count(&line) + count(&more) > FIELD_VALUE_LIMIT {
+                value.push_str(&more);
+                break;
+            }
+            value.push_str(&line);
+        }
+
+        Some(value.trim_end().to_owned())
+            .filter(|value| !value.is_empty())
+            .map(|value| EmbedField {
+                name: String::from("Attachments"),
+                value,
+                inline: false,
+            })
+    }
+}
+
+fn count(s: &str) -> usize {
+    s.chars().count()
+}
+
+/// Byte index of the first `chars` characters
+fn byte_index(s: &str, chars: usize) -> usize {
+    s.char_indices().nth(chars).map_or(s.len(), |(i, _)| i)
+}
+
+fn truncate(s: &str, limit: usize) -> String {
+    if count(s) <= limit {
+        return s.to_owned();
+    }
+
+    format!("{}…", &s[..byte_index(s, limit - 1)])
+}
+
+/// Last place in `s` to break the text: a blank line, a line break or a space.
+/// Breaks in the first half are not used, they leave too much behind.
+fn break_at(s: &str) -> usize {
+    let half = s.len() / 2;
+
+    ["\n\n", "\n", " "]
+        .iter()
+        .filter_map(|separator| s.rfind(separator))
+        .find(|&i| i > half)
+        .unwrap_or(s.len())
+}
+
+/// Split off the start of `text` that fits in `budget` characters, returns the start and the rest
+///
+/// A code block that is split is closed and opened again in the rest.
+fn split(text: &str, budget: usize) -> (String, String) {
+    if count(text) <= budget {
+        return (text.to_owned(), String::new());
+    }
+
+    // Room to close a code block
+    let end = byte_index(text, budget.saturating_sub(4));
+    let at = break_at(&text[..end]);
+
+    let mut piece = text[..at].trim_end().to_owned();
+    let mut rest = text[at..].trim_start_matches([' ', '\n']).to_owned();
+
+    if piece.matches("```").count() % 2 == 1 {
+        piece.push_str("\n```");
+        rest.insert_str(0, "```\n");
+    }
+
+    (piece, rest)
+}
+
+/// Cut `text` off to fit in `budget` characters, ending with a link to the rest
+fn cut_off(text: &str, budget: usize, href: Option<&str>) -> String {
+    if count(text) <= budget {
+        return text.to_owned();
+    }
+
+    let more = match href {
+        Some(href) => format!("…\n\n[Read more]({href})"),
+        None => String::from("…"),
+    };
+
+    let (mut piece, _) = split(text, budget.saturating_sub(count(&more)));
+    piece.push_str(&more);
+
+    piece
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::announcement;
+
+    /// Html of `n` paragraphs of about 110 characters
+    fn paragraphs(n: usize) -> String {
+        (0..n)
+            .map(|i| {
+                format!(
+                    "<p>Paragraph {i} {}</p>",
+                    "lorem ipsum ".repeat(8).trim_end()
+                )
+            })
+            .collect()
+    }
+
+    fn descriptions(messages: &[DiscordMessage]) -> Vec<&str> {
+        messages
+            .iter()
+            .flat_map(|message| &message.embeds)
+            .filter_map(|embed| embed.description.as_deref())
+            .collect()
+    }
+
+    #[test]
+    fn short_announcement() {
+        let mut announcement = announcement("Exam info", "<p>Exam on <b>Monday</b></p>");
+        announcement.sections = vec![String::from("Group A"), String::from("Group B")];
+
+        let messages = announcement.render(Overflow::Split);
+
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content, None);
+        let embed = &messages[0].embeds[..];
+        assert_eq!(embed.len(), 1);
+        assert_eq!(embed[0].title.as_deref(), Some("Exam info"));
+        assert_eq!(
+            embed[0].url.as_deref(),
+            Some("https://canvas.example.com/courses/1/discussion_topics/5")
+        );
+        assert_eq!(embed[0].description.as_deref(), Some("Exam on **Monday**"));
+        assert_eq!(embed[0].author.as_ref().unwrap().name, "Jane Doe");
+        assert_eq!(embed[0].footer.as_ref().unwrap().text, "Group A, Group B");
+        assert_eq!(embed[0].timestamp.as_deref(), Some("2022-01-20T10:00:00Z"));
+    }
+
+    #[test]
+    fn long_title() {
+        let announcement = announcement(&"a".repeat(300), "<p>Exam</p>");
+
+        let title = announcement.render(Overflow::Split)[0].embeds[0]
+            .title
+            .clone()
+            .unwrap();
+
+        assert_eq!(count(&title), TITLE_LIMIT);
+        assert!(title.ends_with('…'));
+    }
+
+    #[test]
+    fn split_within_the_limits() {
+        let mut announcement = announcement("Exam info", &paragraphs(200));
+        announcement.sections = vec![String::from("Group A")];
+        let text = announcement.markdown().text;
+
+        let messages = announcement.render(Overflow::Split);
+
+        assert!(messages.len() > 1);
+        for message in &messages {
+            assert!(message.len() <= MESSAGE_LIMIT, "{}", message.len());
+            assert!(message.embeds.len() <= EMBEDS_LIMIT);
+            for embed in &message.embeds {
+                let description = embed.description.as_deref().unwrap();
+                assert!(count(description) <= DESCRIPTION_LIMIT);
+            }
+        }
+
+        // Only the first embed has the title and only the last one the footer
+        let embeds: Vec<&Embed> = messages.iter().flat_map(|m| &m.embeds).collect();
+        assert!(embeds[0].title.is_some());
+        assert!(embeds[1..].iter().all(|embed| embed.title.is_none()));
+        assert!(embeds.last().unwrap().footer.is_some());
+        assert_eq!(
+            embeds.iter().filter(|embed| embed.footer.is_some()).count(),
+            1
+        );
+
+        // Nothing is lost, the pieces are split between the paragraphs
+        assert_eq!(descriptions(&messages).join("\n\n"), text);
+    }
+
+    #[test]
+    fn full_description() {
+        let announcement = announcement("Exam info", &paragraphs(40));
+        let text = announcement.markdown().text;
+        assert!(count(&text) > DESCRIPTION_LIMIT && count(&text) < MESSAGE_LIMIT);
+
+        let messages = announcement.render(Overflow::Split);
+
+        // Over the limit of a description but within the one of a message
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].embeds.len(), 2);
+        assert_eq!(descriptions(&messages).join("\n\n"), text);
+    }
+
+    #[test]
+    fn split_code_block() {
+        let code = format!("<pre>{}</pre>", "let x = 1;\n".repeat(500));
+        let announcement = announcement("Exam info", &code);
+
+        let messages = announcement.render(Overflow::Split);
+        let descriptions = descriptions(&messages);
+
+        assert!(descriptions.len() > 1);
+        for description in descriptions {
+            assert!(description.starts_with("```"));
+            assert!(description.ends_with("```"));
+        }
+    }
+
+    #[test]
+    fn truncate_with_a_link() {
+        let announcement = announcement("Exam info", &paragraphs(200));
+
+        let messages = announcement.render(Overflow::Truncate);
+
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].embeds.len(), 1);
+        let description = messages[0].embeds[0].description.as_deref().unwrap();
+        assert!(count(description) <= DESCRIPTION_LIMIT);
+        assert!(description.ends_with(
+            "…\n\n[Read more](https://canvas.example.com/courses/1/discussion_topics/5)"
+        ));
+    }
+
+    #[test]
+    fn pings_within_the_content_limit() {
+        let mentions = Mentions {
+            roles: (0..200)
+                .map(|i| format!("{}", 100_000_000_000_000_000_u64 + i))
+                .collect(),
+            here: true,
+            ..Default::default()
+        };
+        let mut message =
+            announcement("Exam info", "<p>Exam</p>").render(Overflow::Split)[0].clone();
+
+        message.mention(&mentions);
+
+        let content = message.content.unwrap();
+        assert!(count(&content) <= CONTENT_LIMIT);
+        assert!(content.starts_with("<@&100000000000000000> <@&100000000000000001>"));
+        assert!(content.ends_with('>'));
+        assert_eq!(message.allowed_mentions.unwrap().roles.len(), 200);
+    }
+}
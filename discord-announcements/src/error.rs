@@ -5,10 +5,12 @@ use macros::quick_impl;
 
 pub use db::DbError;
 pub use feed::FeedError;
+pub use sink::SinkError;
 
 mod db;
 mod feed;
 mod macros;
+mod sink;
 
 /// The default error type for this crate
 #[derive(Debug)]
@@ -19,6 +21,9 @@ pub enum MyError {
     /// Container for FeedError
     Feed(FeedError),
 
+    /// Container for SinkError
+    Sink(SinkError),
+
     /// Just a generic error without dedicated variant,
     /// with a string to store a description
     Generic(String),
@@ -42,6 +47,7 @@ impl fmt::Display for MyError {
         match self {
             MyError::Db(err) => write!(f, "Error: {err}"),
             MyError::Feed(err) => write!(f, "Error: {err}"),
+            MyError::Sink(err) => write!(f, "Error: {err}"),
             MyError::Generic(desc) => write!(f, "Error: {desc}"),
             MyError::Empty => write!(f, "Error"),
         }
@@ -53,6 +59,7 @@ impl Error for MyError {
         match self {
             MyError::Db(_) => "DbError",
             MyError::Feed(_) => "FeedError",
+            MyError::Sink(_) => "SinkError",
             MyError::Generic(desc) => desc,
             MyError::Empty => "",
         }
@@ -71,6 +78,12 @@ impl From<FeedError> for MyError {
     }
 }
 
+impl From<SinkError> for MyError {
+    fn from(err: SinkError) -> Self {
+        Self::Sink(err)
+    }
+}
+
 quick_impl!(From<r2d2::Error> for MyError, MyError::Db);
 quick_impl!(From<diesel::result::Error> for MyError, MyError::Db);
 
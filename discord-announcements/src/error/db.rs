@@ -1,8 +1,6 @@
 use std::error::Error;
 use std::fmt;
 
-use super::quick_impl;
-
 /// Errors that come from database interactions
 #[derive(Debug)]
 pub enum DbError {
@@ -12,6 +10,12 @@ pub enum DbError {
     /// Unique Violation
     UniqueViolation,
 
+    /// The row conflicts with a row of something else
+    Conflict(String),
+
+    /// No connection to the database
+    Connection(String),
+
     /// Just a generic error without dedicated variant,
     /// with a string to store a description
     Generic(String),
@@ -35,6 +39,8 @@ impl fmt::Display for DbError {
         match self {
             Self::NotFound => write!(f, "DB error: not found"),
             Self::UniqueViolation => write!(f, "DB error: unique vioation"),
+            Self::Conflict(s) => write!(f, "DB error: conflict: {s}"),
+            Self::Connection(s) => write!(f, "DB error: connection: {s}"),
             Self::Generic(s) => write!(f, "DB error: {s}"),
             Self::Empty => write!(f, "DB error"),
         }
@@ -46,6 +52,8 @@ impl Error for DbError {
         match self {
             Self::NotFound => "not found",
             Self::UniqueViolation => "unique vioation",
+            Self::Conflict(s) => s,
+            Self::Connection(s) => s,
             Self::Generic(s) => s,
             Self::Empty => "",
         }
@@ -72,4 +80,8 @@ impl From<diesel::result::Error> for DbError {
     }
 }
 
-quick_impl!(From<r2d2::Error> for DbError);
+impl From<r2d2::Error> for DbError {
+    fn from(e: r2d2::Error) -> Self {
+        Self::Connection(e.to_string())
+    }
+}
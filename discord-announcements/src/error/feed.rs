@@ -13,6 +13,18 @@ pub enum FeedError {
     /// Invalid feed
     InvalidFeedUrl(String),
 
+    /// The feed url responded with an error status: 404, 401, ...
+    Status(u16),
+
+    /// The feed has no id to tell it apart from other feeds
+    MissingId,
+
+    /// Not an atom, RSS 2.0 or RSS 1.0 feed, with the name of the root element
+    UnknownFormat(String),
+
+    /// The body is larger than the limit in bytes
+    TooLarge(u64),
+
     /// Just a generic error without dedicated variant,
     /// with a string to store a description
     Generic(String),
@@ -33,6 +45,10 @@ impl fmt::Display for FeedError {
             Self::De(s) => write!(f, "Feed deserialization error: {s}"),
             Self::Web(s) => write!(f, "Feed web error: {s}"),
             Self::InvalidFeedUrl(s) => write!(f, "Feed invalid url: {s}"),
+            Self::Status(status) => write!(f, "Feed http status: {status}"),
+            Self::MissingId => write!(f, "Feed without id"),
+            Self::UnknownFormat(s) => write!(f, "Feed unknown format: <{s}>"),
+            Self::TooLarge(max) => write!(f, "Feed larger than {max} bytes"),
             Self::Generic(s) => write!(f, "Feed error: {s}"),
             Self::Empty => write!(f, "Feed error"),
         }
@@ -45,6 +61,10 @@ impl Error for FeedError {
             Self::De(s) => s,
             Self::Web(s) => s,
             Self::InvalidFeedUrl(s) => s,
+            Self::Status(_) => "http status",
+            Self::MissingId => "missing id",
+            Self::UnknownFormat(s) => s,
+            Self::TooLarge(_) => "too large",
             Self::Generic(s) => s,
             Self::Empty => "",
         }
@@ -93,9 +113,17 @@ impl From<quick_xml::DeError> for FeedError {
     }
 }
 
+impl From<serde_json::Error> for FeedError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::De(e.to_string())
+    }
+}
+
 impl From<reqwest::Error> for FeedError {
     fn from(e: reqwest::Error) -> Self {
-        if e.is_body() {
+        if let Some(status) = e.status() {
+            Self::Status(status.as_u16())
+        } else if e.is_body() {
             Self::Web(e.to_string())
         } else if e.is_builder() {
             Self::InvalidFeedUrl(e.to_string())
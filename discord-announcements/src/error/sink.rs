@@ -0,0 +1,116 @@
+use std::error::Error;
+use std::fmt;
+
+use super::FeedError;
+
+/// Errors that come from sending announcements to a sink
+#[derive(Debug)]
+pub enum SinkError {
+    /// Unknown sink type or invalid config, with the reason
+    Config(String),
+
+    /// The sink does not exist anymore or refuses us: a deleted webhook, a revoked token, ...
+    Gone(u16),
+
+    /// The sink responded with another error status
+    Status(u16),
+
+    /// Still rate limited after waiting as long as allowed
+    RateLimited,
+
+    /// Could not reach the sink
+    Web(String),
+
+    /// The mail server did not accept the mail
+    Email(String),
+
+    /// Only the first messages of the announcement were sent before the error,
+    /// sending it again repeats them
+    Partial(usize, Box<SinkError>),
+
+    /// Just a generic error without dedicated variant,
+    /// with a string to store a description
+    Generic(String),
+
+    /// An empty error without further info for when you are lazy
+    Empty,
+}
+
+impl SinkError {
+    pub fn new<T: ToString>(msg: T) -> Self {
+        Self::Generic(msg.to_string())
+    }
+}
+
+impl fmt::Display for SinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Config(s) => write!(f, "Sink invalid config: {s}"),
+            Self::Gone(status) => write!(f, "Sink gone, http status: {status}"),
+            Self::Status(status) => write!(f, "Sink http status: {status}"),
+            Self::RateLimited => write!(f, "Sink rate limited"),
+            Self::Web(s) => write!(f, "Sink web error: {s}"),
+            Self::Email(s) => write!(f, "Sink email error: {s}"),
+            Self::Partial(sent, e) => write!(f, "Sink sent {sent} messages before: {e}"),
+            Self::Generic(s) => write!(f, "Sink error: {s}"),
+            Self::Empty => write!(f, "Sink error"),
+        }
+    }
+}
+
+impl Error for SinkError {
+    fn description(&self) -> &str {
+        match self {
+            Self::Config(s) => s,
+            Self::Gone(_) => "gone",
+            Self::Status(_) => "http status",
+            Self::RateLimited => "rate limited",
+            Self::Web(s) => s,
+            Self::Email(s) => s,
+            Self::Partial(..) => "partially sent",
+            Self::Generic(s) => s,
+            Self::Empty => "",
+        }
+    }
+}
+
+impl From<reqwest::Error> for SinkError {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Web(e.to_string())
+    }
+}
+
+impl From<FeedError> for SinkError {
+    fn from(e: FeedError) -> Self {
+        match e {
+            FeedError::InvalidFeedUrl(s) => Self::Config(s),
+            FeedError::Status(status) => Self::Status(status),
+            FeedError::Web(s) => Self::Web(s),
+            e => Self::Web(e.to_string()),
+        }
+    }
+}
+
+impl From<serde_json::Error> for SinkError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Config(e.to_string())
+    }
+}
+
+impl From<lettre::error::Error> for SinkError {
+    fn from(e: lettre::error::Error) -> Self {
+        Self::Email(e.to_string())
+    }
+}
+
+impl From<lettre::address::AddressError> for SinkError {
+    fn from(e: lettre::address::AddressError) -> Self {
+        Self::Config(format!("invalid address: {e}"))
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for SinkError {
+    fn from(e: lettre::transport::smtp::Error) -> Self {
+        Self::Email(e.to_string())
+    }
+}
@@ -0,0 +1,145 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::models::Announcement;
+
+/// Which announcements a subscription receives, stored as json in `subscriptions.filter`
+///
+/// Every rule that is set has to pass, an empty filter lets everything through.
+/// Keywords and authors are case insensitive, the regexes are not unless they start with `(?i)`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Filter {
+    /// At least one of these is in the title or the content
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+
+    /// None of these is in the title or the content
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title_regex: Option<String>,
+
+    /// Matched against the text of the content, without html or markdown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_regex: Option<String>,
+
+    /// Names of the authors that are let through
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<String>,
+}
+
+impl Filter {
+    /// Read the filter of a subscription, `None` lets everything through
+    pub fn from_db(filter: Option<&str>) -> Result<Self, serde_json::Error> {
+        match filter {
+            Some(filter) => serde_json::from_str(filter),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Json to store, `None` for an empty filter
+    pub fn to_db(&self) -> Option<String> {
+        match self.is_empty() {
+            true => None,
+            false => serde_json::to_string(self).ok(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Check the regexes before the filter is stored
+    pub fn validate(&self) -> Result<(), regex::Error> {
+        for regex in [&self.title_regex, &self.content_regex]
+            .into_iter()
+            .flatten()
+        {
+            Regex::new(regex)?;
+        }
+
+        Ok(())
+    }
+
+    /// Compile the regexes once to match many announcements
+    ///
+    /// `None` when a regex does not compile, that lets nothing through.
+    /// `validate` keeps those out of the db.
+    pub fn matcher(&self) -> Option<Matcher<'_>> {
+        Some(Matcher {
+            filter: self,
+            title_regex: compile(&self.title_regex).ok()?,
+            content_regex: compile(&self.content_regex).ok()?,
+        })
+    }
+
+    /// The keywords of `include` that are in the title or the content of `announcement`
+    pub fn matched_keywords(&self, announcement: &Announcement) -> Vec<&str> {
+        if self.include.is_empty() {
+            return Vec::new();
+        }
+
+        self.keywords(&lowercase_text(
+            &announcement.title,
+            &announcement.content.text(),
+        ))
+    }
+
+    /// The keywords of `include` that are in the lowercase `text`
+    fn keywords(&self, text: &str) -> Vec<&str> {
+        self.include
+            .iter()
+            .filter(|keyword| text.contains(&keyword.to_lowercase()))
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+/// What the keywords are looked for in
+fn lowercase_text(title: &str, content: &str) -> String {
+    format!("{title}\n{content}").to_lowercase()
+}
+
+fn compile(regex: &Option<String>) -> Result<Option<Regex>, regex::Error> {
+    regex.as_deref().map(Regex::new).transpose()
+}
+
+/// A filter with its regexes compiled
+#[derive(Debug)]
+pub struct Matcher<'a> {
+    filter: &'a Filter,
+    title_regex: Option<Regex>,
+    content_regex: Option<Regex>,
+}
+
+impl Matcher<'_> {
+    /// Does `announcement` pass every rule
+    pub fn matches(&self, announcement: &Announcement) -> bool {
+        let filter = self.filter;
+        if filter.is_empty() {
+            return true;
+        }
+
+        let content = announcement.content.text();
+        let text = lowercase_text(&announcement.title, &content);
+
+        (filter.include.is_empty() || !filter.keywords(&text).is_empty())
+            && !filter
+                .exclude
+                .iter()
+                .any(|keyword| text.contains(&keyword.to_lowercase()))
+            && regex_matches(&self.title_regex, &announcement.title)
+            && regex_matches(&self.content_regex, &content)
+            && (filter.authors.is_empty()
+                || filter
+                    .authors
+                    .iter()
+                    .any(|author| author.eq_ignore_ascii_case(announcement.author_name().trim())))
+    }
+}
+
+fn regex_matches(regex: &Option<Regex>, text: &str) -> bool {
+    regex.as_ref().is_none_or(|regex| regex.is_match(text))
+}
@@ -0,0 +1,584 @@
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, IntoUrl, Proxy, Request, RequestBuilder, Response, StatusCode, Url};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use time::format_description::well_known::Rfc2822;
+use time::OffsetDateTime;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::error::FeedError;
+
+/// Longest time a host is left alone, whatever its `Retry-After` says
+const MAX_PAUSE: Duration = Duration::from_secs(10 * 60);
+
+/// Canvas fills the quota of a token up to 700, requests are throttled when it is empty
+const CANVAS_LOW_QUOTA: f64 = 100.0;
+
+/// Pause of a canvas instance when the quota of a token runs low
+const CANVAS_COOLDOWN: Duration = Duration::from_secs(10);
+
+/// Longest wait before a retry, the backoff stops doubling there
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Settings of the client feeds are retrieved with
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    /// Time a single request may take, including reading the body
+    pub timeout: Duration,
+
+    /// Largest body in bytes that is read, larger feeds fail
+    pub max_body_size: u64,
+
+    pub user_agent: String,
+
+    /// Proxy every request goes through, http and https
+    pub proxy: Option<String>,
+
+    /// Retries of a request after a connection error, a timeout or a 5xx status
+    pub retries: u32,
+
+    /// Wait before the first retry, doubles every retry up to a minute
+    pub retry_backoff: Duration,
+
+    /// Requests in flight at the same time, over all hosts
+    pub max_concurrent: usize,
+
+    /// Time between the start of two requests to the same host
+    pub host_interval: Duration,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            max_body_size: 10 * 1024 * 1024,
+            user_agent: format!(
+                "{}/{} (+https://github.com/vdbe/discord-announcements)",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION")
+            ),
+            proxy: None,
+            retries: 2,
+            retry_backoff: Duration::from_secs(1),
+            max_concurrent: 8,
+            host_interval: Duration::from_millis(200),
+        }
+    }
+}
+
+impl HttpConfig {
+    /// Read the config from `HTTP_TIMEOUT`, `HTTP_MAX_BODY_SIZE`, `HTTP_USER_AGENT`, `HTTP_PROXY`,
+    /// `HTTP_RETRIES`, `HTTP_RETRY_BACKOFF`, `HTTP_MAX_CONCURRENT` and `HTTP_HOST_INTERVAL_MS`,
+    /// the defaults are used for the ones not set
+    ///
+    /// Durations are in seconds unless the name says otherwise, sizes in bytes.
+    pub fn from_env() -> Result<Self, FeedError> {
+        Self::from_vars(|key| std::env::var(key).ok())
+    }
+
+    /// Read the config from the variables `var` looks up, see [`HttpConfig::from_env`]
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, FeedError> {
+        let string = |key: &str| var(key).filter(|value| !value.is_empty());
+        let default = Self::default();
+
+        Ok(Self {
+            timeout: number(string, "HTTP_TIMEOUT")?
+                .map(Duration::from_secs)
+                .unwrap_or(default.timeout),
+            max_body_size: number(string, "HTTP_MAX_BODY_SIZE")?.unwrap_or(default.max_body_size),
+            user_agent: string("HTTP_USER_AGENT").unwrap_or(default.user_agent),
+            proxy: string("HTTP_PROXY"),
+            retries: number(string, "HTTP_RETRIES")?.unwrap_or(default.retries),
+            retry_backoff: number(string, "HTTP_RETRY_BACKOFF")?
+                .map(Duration::from_secs)
+                .unwrap_or(default.retry_backoff),
+            max_concurrent: number(string, "HTTP_MAX_CONCURRENT")?
+                .map(|max: usize| max.max(1))
+                .unwrap_or(default.max_concurrent),
+            host_interval: number(string, "HTTP_HOST_INTERVAL_MS")?
+                .map(Duration::from_millis)
+                .unwrap_or(default.host_interval),
+        })
+    }
+}
+
+/// Parse the variable `key` as a `T`, a value out of its range is an error as well
+fn number<T: FromStr>(
+    string: impl Fn(&str) -> Option<String>,
+    key: &str,
+) -> Result<Option<T>, FeedError> {
+    string(key)
+        .map(|number| {
+            number
+                .parse()
+                .map_err(|_| FeedError::new(format!("{key} must be a positive number")))
+        })
+        .transpose()
+}
+
+/// Client shared by every request for a feed, cloning it shares the connection pool
+/// and the limits on the requests
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: Client,
+    config: HttpConfig,
+    limits: Arc<Limits>,
+}
+
+impl HttpClient {
+    pub fn new(config: HttpConfig) -> Result<Self, FeedError> {
+        let mut builder = Client::builder()
+            .timeout(config.timeout)
+            .user_agent(&config.user_agent);
+
+        if let Some(proxy) = &config.proxy {
+            builder = builder.proxy(
+                Proxy::all(proxy).map_err(|e| FeedError::new(format!("invalid proxy: {e}")))?,
+            );
+        }
+
+        Ok(Self {
+            client: builder.build()?,
+            limits: Arc::new(Limits {
+                concurrent: Arc::new(Semaphore::new(config.max_concurrent)),
+                hosts: Mutex::new(HashMap::new()),
+                host_interval: config.host_interval,
+            }),
+            config,
+        })
+    }
+
+    pub fn get(&self, url: Url) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    pub fn put<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.put(url)
+    }
+
+    /// Send a request once the limits allow it, it is sent again after a connection error,
+    /// a timeout, a 5xx status or being throttled
+    ///
+    /// The response of the last try is returned, also when its status is an error.
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, FeedError> {
+        let request = request.build()?;
+        let host = host_key(request.url());
+        let mut backoff = self.config.retry_backoff;
+
+        for _ in 0..self.config.retries {
+            // Requests with a streaming body can not be sent again
+            let retry = match request.try_clone() {
+                Some(retry) => retry,
+                None => break,
+            };
+
+            match self.execute(&host, retry).await {
+                Ok(response) if !should_retry(&response) => return Ok(response),
+                Err(e) if !(e.is_connect() || e.is_timeout()) => return Err(e.into()),
+                // The other feeds of the host wait as well
+                _ => self.limits.pause(&host, backoff),
+            }
+            backoff = backoff.saturating_mul(2).min(MAX_BACKOFF);
+        }
+
+        Ok(self.execute(&host, request).await?)
+    }
+
+    /// Send a request once the limits allow it, it is never sent again
+    ///
+    /// For requests that must not be repeated, the caller decides what to do with the response.
+    pub async fn send_once(&self, request: RequestBuilder) -> Result<Response, FeedError> {
+        let request = request.build()?;
+
+        Ok(self.execute(&host_key(request.url()), request).await?)
+    }
+
+    /// The response holds its permit in its extensions,
+    /// it is in flight until the body is read or the response is dropped
+    ///
+    /// The permit is taken once it is the turn of the host, a paused host does not hold
+    /// permits the other hosts could use. When the host asked for a pause while waiting
+    /// for the permit, the permit is given back until the pause is over.
+    async fn execute(&self, host: &str, request: Request) -> Result<Response, reqwest::Error> {
+        let permit = loop {
+            self.limits.wait_for(host).await;
+
+            // The semaphore is never closed
+            let permit = self.limits.concurrent.clone().acquire_owned().await.ok();
+            if !self.limits.is_paused(host) {
+                break permit;
+            }
+        };
+
+        let mut response = self.client.execute(request).await?;
+        self.limits.observe(host, &response);
+        if let Some(permit) = permit {
+            response
+                .extensions_mut()
+                .insert::<OwnedSemaphorePermit>(permit);
+        }
+
+        Ok(response)
+    }
+
+    /// Read the body of a successful response as text, up to `max_body_size` bytes
+    ///
+    /// The request stops counting against `max_concurrent` once the body is read.
+    pub async fn text(&self, response: Response) -> Result<String, FeedError> {
+        let mut response = response.error_for_status()?;
+        let max = self.config.max_body_size;
+
+        if response.content_length().unwrap_or_default() > max {
+            return Err(FeedError::TooLarge(max));
+        }
+
+        // The content length can be missing or wrong
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if (body.len() + chunk.len()) as u64 > max {
+                return Err(FeedError::TooLarge(max));
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+}
+
+/// Limits on the requests of all clones of a client
+#[derive(Debug)]
+struct Limits {
+    /// Permits for the requests in flight
+    concurrent: Arc<Semaphore>,
+
+    hosts: Mutex<HashMap<String, HostLimit>>,
+    host_interval: Duration,
+}
+
+#[derive(Debug)]
+struct HostLimit {
+    /// Start of the next free turn
+    next: Instant,
+
+    /// No requests are sent before, set when the host asks to slow down
+    paused_until: Instant,
+}
+
+impl Limits {
+    /// Wait for a turn to send a request to `host`
+    async fn wait_for(&self, host: &str) {
+        loop {
+            let turn = {
+                let mut hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+                let now = Instant::now();
+                let limit = hosts.entry(host.to_owned()).or_insert(HostLimit {
+                    next: now,
+                    paused_until: now,
+                });
+
+                let turn = limit.next.max(limit.paused_until).max(now);
+                limit.next = turn + self.host_interval;
+                turn
+            };
+            tokio::time::sleep_until(turn.into()).await;
+
+            // The host can ask to slow down while waiting, then wait for a turn after the pause
+            if !self.is_paused(host) {
+                break;
+            }
+        }
+    }
+
+    fn is_paused(&self, host: &str) -> bool {
+        self.hosts
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(host)
+            .is_some_and(|limit| limit.paused_until > Instant::now())
+    }
+
+    /// Send no requests to `host` for `duration`, longer pauses are kept
+    fn pause(&self, host: &str, duration: Duration) {
+        let until = Instant::now() + duration.min(MAX_PAUSE);
+        let mut hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+        let limit = hosts.entry(host.to_owned()).or_insert(HostLimit {
+            next: until,
+            paused_until: until,
+        });
+
+        limit.paused_until = limit.paused_until.max(until);
+    }
+
+    /// Slow down when the response asks for it
+    fn observe(&self, host: &str, response: &Response) {
+        if let Some(retry_after) = retry_after(response.headers()) {
+            self.pause(host, retry_after);
+        } else if canvas_quota(response.headers()).is_some_and(|quota| quota < CANVAS_LOW_QUOTA) {
+            self.pause(host, CANVAS_COOLDOWN);
+        }
+    }
+}
+
+/// Requests to the same host and port share their limits
+fn host_key(url: &Url) -> String {
+    format!(
+        "{}:{}",
+        url.host_str().unwrap_or_default(),
+        url.port_or_known_default().unwrap_or_default()
+    )
+}
+
+fn should_retry(response: &Response) -> bool {
+    let status = response.status();
+
+    status.is_server_error()
+        || status == StatusCode::TOO_MANY_REQUESTS
+        // Canvas throttles with `403 Forbidden (Rate Limit Exceeded)`
+        || status == StatusCode::FORBIDDEN
+            && canvas_quota(response.headers()).is_some_and(|quota| quota <= 0.0)
+}
+
+/// `Retry-After` in seconds or as http date
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date: SystemTime = OffsetDateTime::parse(value, &Rfc2822).ok()?.into();
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+/// What is left of the quota of the canvas api token
+fn canvas_quota(headers: &HeaderMap) -> Option<f64> {
+    headers
+        .get("X-Rate-Limit-Remaining")?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{Reply, TestServer};
+
+    fn client(retries: u32, max_concurrent: usize) -> HttpClient {
+        HttpClient::new(HttpConfig {
+            retries,
+            retry_backoff: Duration::ZERO,
+            max_concurrent,
+            host_interval: Duration::ZERO,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    /// Time until the next request to the host of `server` may start
+    fn paused_for(http: &HttpClient, server: &TestServer) -> Duration {
+        let url = Url::parse(&server.url("/")).unwrap();
+        let hosts = http.limits.hosts.lock().unwrap();
+
+        hosts[&host_key(&url)]
+            .paused_until
+            .saturating_duration_since(Instant::now())
+    }
+
+    /// The response is dropped right away, that frees its permit
+    async fn get(http: &HttpClient, server: &TestServer) -> StatusCode {
+        let url = Url::parse(&server.url("/feed")).unwrap();
+
+        http.send(http.get(url)).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn retry_after_pauses_the_host() {
+        let server =
+            TestServer::start(vec![Reply::new(200, "").header("Retry-After", "120")]).await;
+        let http = client(0, 8);
+
+        get(&http, &server).await;
+
+        assert!(paused_for(&http, &server) > Duration::from_secs(110));
+    }
+
+    #[tokio::test]
+    async fn low_canvas_quota_pauses_the_host() {
+        let server = TestServer::start(vec![
+            Reply::new(200, "").header("X-Rate-Limit-Remaining", "42.5")
+        ])
+        .await;
+        let http = client(0, 8);
+
+        get(&http, &server).await;
+
+        assert!(paused_for(&http, &server) > CANVAS_COOLDOWN - Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn enough_canvas_quota() {
+        let server = TestServer::start(vec![
+            Reply::new(200, "").header("X-Rate-Limit-Remaining", "600")
+        ])
+        .await;
+        let http = client(0, 8);
+
+        get(&http, &server).await;
+
+        assert_eq!(paused_for(&http, &server), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn waits_for_a_pause_while_waiting_for_a_permit() {
+        let server = TestServer::start(vec![
+            Reply::new(200, "").header("Retry-After", "1"),
+            Reply::new(200, ""),
+        ])
+        .await;
+        let http = client(0, 1);
+
+        // The second request waits for the permit of the first one, then for the pause it got
+        let start = Instant::now();
+        tokio::join!(get(&http, &server), get(&http, &server));
+
+        assert!(start.elapsed() >= Duration::from_millis(900));
+        assert_eq!(server.received().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn paused_host_does_not_hold_the_permit() {
+        let paused =
+            TestServer::start(vec![Reply::new(200, "").header("Retry-After", "120")]).await;
+        let other = TestServer::start(vec![Reply::new(200, "")]).await;
+        let http = client(0, 1);
+
+        get(&http, &paused).await;
+
+        let url = Url::parse(&paused.url("/feed")).unwrap();
+        let waiting = tokio::spawn({
+            let http = http.clone();
+            async move {
+                http.send(http.get(url))
+                    .await
+                    .map(|response| response.status())
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let status = tokio::time::timeout(Duration::from_secs(5), get(&http, &other))
+            .await
+            .expect("the other host waited for the paused one");
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(paused.received().len(), 1);
+        waiting.abort();
+    }
+
+    #[tokio::test]
+    async fn retries_a_server_error() {
+        let server = TestServer::start(vec![Reply::new(503, ""), Reply::new(200, "feed")]).await;
+        let http = HttpClient::new(HttpConfig {
+            retries: 2,
+            retry_backoff: Duration::from_millis(200),
+            host_interval: Duration::ZERO,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let start = Instant::now();
+        let url = Url::parse(&server.url("/feed")).unwrap();
+        let response = http.send(http.get(url)).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(http.text(response).await.unwrap(), "feed");
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert_eq!(server.received().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_retries() {
+        let server = TestServer::start(vec![Reply::new(503, "")]).await;
+        let http = client(2, 8);
+
+        assert_eq!(get(&http, &server).await, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(server.received().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let server = TestServer::start(vec![Reply::new(404, ""), Reply::new(200, "")]).await;
+        let http = client(2, 8);
+
+        assert_eq!(get(&http, &server).await, StatusCode::NOT_FOUND);
+        assert_eq!(server.received().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn body_too_large() {
+        let server = TestServer::start(vec![Reply::new(200, &"x".repeat(100))]).await;
+        let http = HttpClient::new(HttpConfig {
+            max_body_size: 10,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let url = Url::parse(&server.url("/feed")).unwrap();
+        let response = http.send(http.get(url)).await.unwrap();
+        let err = http.text(response).await.unwrap_err();
+
+        assert!(matches!(err, FeedError::TooLarge(10)), "{err:?}");
+    }
+
+    #[test]
+    fn config_from_vars() {
+        let vars: HashMap<&str, &str> = HashMap::from([
+            ("HTTP_TIMEOUT", "5"),
+            ("HTTP_MAX_BODY_SIZE", "1024"),
+            ("HTTP_USER_AGENT", "test-agent"),
+            ("HTTP_PROXY", ""),
+            ("HTTP_RETRIES", "4"),
+            ("HTTP_RETRY_BACKOFF", "3"),
+            ("HTTP_MAX_CONCURRENT", "0"),
+            ("HTTP_HOST_INTERVAL_MS", "50"),
+        ]);
+
+        let config = HttpConfig::from_vars(|key| vars.get(key).map(|v| v.to_string())).unwrap();
+        assert_eq!(config.timeout, Duration::from_secs(5));
+        assert_eq!(config.max_body_size, 1024);
+        assert_eq!(config.user_agent, "test-agent");
+        assert_eq!(config.proxy, None);
+        assert_eq!(config.retries, 4);
+        assert_eq!(config.retry_backoff, Duration::from_secs(3));
+        assert_eq!(config.max_concurrent, 1);
+        assert_eq!(config.host_interval, Duration::from_millis(50));
+    }
+
+    #[test]
+    fn config_defaults() {
+        let config = HttpConfig::from_vars(|_| None).unwrap();
+        let default = HttpConfig::default();
+
+        assert_eq!(config.retries, default.retries);
+        assert_eq!(config.timeout, default.timeout);
+        assert_eq!(config.user_agent, default.user_agent);
+    }
+
+    #[test]
+    fn config_invalid_number() {
+        for retries in ["many", "-1", "4294967296"] {
+            let err =
+                HttpConfig::from_vars(|key| (key == "HTTP_RETRIES").then(|| retries.to_owned()))
+                    .unwrap_err();
+
+            assert!(err.to_string().contains("HTTP_RETRIES"), "{err}");
+        }
+    }
+}
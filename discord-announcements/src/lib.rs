@@ -1,14 +1,45 @@
+// diesel 1.4 derives expand to impls nested inside a const item
+#![allow(non_local_definitions)]
+
 #[macro_use]
 extern crate diesel;
 
 use diesel::prelude::*;
 use diesel::r2d2::{self, ConnectionManager};
 
-pub use error::{DbError, FeedError, MyError};
-pub use models::{DbSubscription, Feed};
+pub use digest::DeliveryMode;
+pub use discord::{
+    AllowedMentions, DiscordMessage, Embed, EmbedAuthor, EmbedField, EmbedFooter, EmbedImage,
+    Overflow,
+};
+pub use error::{DbError, FeedError, MyError, SinkError};
+pub use filter::Filter;
+pub use http::{HttpClient, HttpConfig};
+pub use markdown::Markdown;
+pub use mentions::Mentions;
+pub use models::{
+    Announcement, Attachment, Author, Channel, Content, DbAnnouncement, DbBackupFeed, DbDelivery,
+    DbFeed, DbGuildSettings, DbSubscription, Edit, Feed, FeedFormat, FeedOutcome, Link,
+    PendingDelivery, PendingSink, Validators,
+};
+pub use quiet::QuietHours;
+pub use sink::{
+    DiscordConfig, EmailConfig, MatrixConfig, Sink, Sinks, SlackConfig, SmtpConfig, WebhookConfig,
+};
 
+mod digest;
+mod discord;
 mod error;
+mod filter;
+mod http;
+mod markdown;
+mod mentions;
 mod models;
+mod quiet;
 mod schema;
+mod sink;
+
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_server;
 
 pub type Pool = r2d2::Pool<ConnectionManager<PgConnection>>;
@@ -0,0 +1,543 @@
+use reqwest::Url;
+use scraper::{ElementRef, Html, Node};
+
+/// Content of an announcement in the markdown discord understands
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Markdown {
+    pub text: String,
+
+    /// Urls of the images in the html, discord does not show images in text
+    pub images: Vec<String>,
+}
+
+impl Markdown {
+    /// Convert html, tables are flattened to a line per row and images are left out of the text
+    ///
+    /// Relative links and images are resolved against `base`, without it only their text is kept.
+    pub fn from_html(html: &str, base: Option<&Url>) -> Self {
+        let fragment = Html::parse_fragment(html);
+
+        let mut writer = Writer {
+            base: base.cloned(),
+            ..Default::default()
+        };
+        writer.children(fragment.root_element());
+
+        Self {
+            text: writer.out.trim_end().to_owned(),
+            images: writer.images,
+        }
+    }
+
+    /// Convert the content of an announcement, atom `text` content is not html
+    pub fn from_content(content_type: &str, content: &str, base: Option<&Url>) -> Self {
+        match content_type {
+            "text" => Self::from_text(content),
+            _ => Self::from_html(content, base),
+        }
+    }
+
+    /// Plain text, the lines are kept
+    pub fn from_text(text: &str) -> Self {
+        let text = text
+            .lines()
+            .map(|line| escape(line.trim_end(), true))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        Self {
+            text: text.trim().to_owned(),
+            images: Vec::new(),
+        }
+    }
+}
+
+/// Writes markdown while walking the html
+///
+/// Line breaks, spaces and opening markers are only written in front of the next text,
+/// so empty elements and the whitespace around them leave no trace.
+#[derive(Default)]
+struct Writer {
+    out: String,
+    images: Vec<String>,
+
+    /// Url relative links and images are resolved against
+    base: Option<Url>,
+
+    /// Line breaks to write before the next text, 2 for a new paragraph
+    newlines: usize,
+    space: bool,
+
+    /// Written in front of the next text, `**` of a `<b>` for example
+    open: String,
+
+    /// Written at the start of every line: `> ` in quotes, indentation in lists
+    prefix: Vec<&'static str>,
+
+    /// Prefix of the current line
+    line_prefix: String,
+
+    /// Nothing written on the current line after the prefix or the marker of a list item
+    fresh: bool,
+
+    /// No line breaks right after the marker of a list item
+    item_start: bool,
+
+    /// Blocks in table cells are flattened
+    cells: usize,
+
+    /// No escaping in `<code>`
+    code: bool,
+}
+
+impl Writer {
+    /// Absolute http url of a link, discord can not open the others
+    fn url(&self, href: &str) -> Option<String> {
+        if is_absolute(href) {
+            return Some(href.to_owned());
+        }
+
+        let url = self.base.as_ref()?.join(href.trim()).ok()?;
+        is_absolute(url.as_str()).then(|| url.into())
+    }
+
+    fn children(&mut self, element: ElementRef) {
+        for child in element.children() {
+            match child.value() {
+                Node::Text(text) => self.text(text),
+                Node::Element(_) => {
+                    if let Some(element) = ElementRef::wrap(child) {
+                        self.element(element);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn element(&mut self, element: ElementRef) {
+        let name = element.value().name();
+
+        match name {
+            "script" | "style" | "head" | "title" => {}
+            "br" => {
+                if self.cells > 0 {
+                    self.space = true;
+                } else if !self.item_start {
+                    self.newlines = (self.newlines + 1).min(2);
+                }
+            }
+            "p" | "div" | "section" | "article" | "header" | "footer" | "hr" | "dl" | "dd"
+            | "dt" | "figure" | "figcaption" => {
+                self.block(2);
+                self.children(element);
+                self.block(2);
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.block(2);
+                self.inline(element, "**", "**");
+                self.block(2);
+            }
+            "b" | "strong" => self.inline(element, "**", "**"),
+            "i" | "em" | "cite" => self.inline(element, "*", "*"),
+            "u" | "ins" => self.inline(element, "__", "__"),
+            "s" | "strike" | "del" => self.inline(element, "~~", "~~"),
+            "code" | "kbd" | "samp" => {
+                let code = std::mem::replace(&mut self.code, true);
+                self.inline(element, "`", "`");
+                self.code = code;
+            }
+            "a" => match element.value().attr("href").and_then(|href| self.url(href)) {
+                Some(href) => {
+                    let close = format!("]({})", href.replace('(', "%28").replace(')', "%29"));
+                    self.inline(element, "[", &close);
+                }
+                None => self.children(element),
+            },
+            "img" => {
+                if let Some(src) = element.value().attr("src").and_then(|src| self.url(src)) {
+                    self.images.push(src);
+                }
+            }
+            "pre" => self.pre(element),
+            "blockquote" => {
+                self.block(2);
+                self.prefix.push("> ");
+                self.children(element);
+                self.prefix.pop();
+                self.block(2);
+            }
+            "ul" | "ol" => self.list(element, name == "ol"),
+            "li" => self.item(element, "- "),
+            "table" => {
+                self.block(2);
+                self.children(element);
+                self.block(2);
+            }
+            "tr" => {
+                self.block(1);
+                let mut first = true;
+                for cell in element.children().filter_map(ElementRef::wrap) {
+                    self.cell(cell, &mut first);
+                }
+                self.block(1);
+            }
+            "td" | "th" => {
+                let mut first = true;
+                self.cell(element, &mut first);
+            }
+            _ => self.children(element),
+        }
+    }
+
+    /// Children between the markers, the markers are left out when there is no text
+    fn inline(&mut self, element: ElementRef, open: &str, close: &str) {
+        let len = self.open.len();
+        self.open.push_str(open);
+
+        let written = self.out.len();
+        self.children(element);
+
+        if self.out.len() == written && self.open.len() > len {
+            // Nothing written, forget about the opening marker
+            self.open.truncate(len);
+        } else {
+            self.out.push_str(close);
+        }
+    }
+
+    fn list(&mut self, element: ElementRef, ordered: bool) {
+        self.block(if self.prefix.is_empty() { 2 } else { 1 });
+
+        let items = element
+            .children()
+            .filter_map(ElementRef::wrap)
+            .filter(|child| child.value().name() == "li");
+        for (i, item) in items.enumerate() {
+            let marker = match ordered {
+                true => format!("{}. ", i + 1),
+                false => String::from("- "),
+            };
+            self.item(item, &marker);
+        }
+
+        self.block(if self.prefix.is_empty() { 2 } else { 1 });
+    }
+
+    fn item(&mut self, element: ElementRef, marker: &str) {
+        self.block(1);
+        self.write(marker);
+        self.fresh = true;
+        self.item_start = true;
+
+        self.prefix
+            .push(if marker.len() > 2 { "   " } else { "  " });
+        self.children(element);
+        self.prefix.pop();
+
+        // Paragraphs in an item do not put blank lines between the items
+        self.newlines = self.newlines.min(1);
+        self.item_start = false;
+        self.block(1);
+    }
+
+    fn cell(&mut self, element: ElementRef, first: &mut bool) {
+        let name = element.value().name();
+        if name != "td" && name != "th" {
+            return;
+        }
+
+        if !*first {
+            self.space = true;
+            self.write("|");
+            self.space = true;
+        }
+        *first = false;
+
+        self.cells += 1;
+        match name {
+            "th" => self.inline(element, "**", "**"),
+            _ => self.children(element),
+        }
+        self.cells -= 1;
+    }
+
+    fn pre(&mut self, element: ElementRef) {
+        let text: String = element.text().collect();
+
+        // A code block has to start on its own line
+        self.item_start = false;
+        self.block(2);
+        self.write("```");
+        for line in text.trim_end().lines() {
+            self.newlines = 1;
+            self.write(line);
+        }
+        self.newlines = 1;
+        self.write("```");
+        self.block(2);
+    }
+
+    fn text(&mut self, text: &str) {
+        if text.starts_with(char::is_whitespace) {
+            self.space = true;
+        }
+
+        for (i, word) in text.split_whitespace().enumerate() {
+            if i > 0 {
+                self.space = true;
+            }
+
+            let escaped = match self.code {
+                true => word.to_owned(),
+                false => escape(word, self.at_line_start()),
+            };
+            self.write(&escaped);
+        }
+
+        if text.ends_with(char::is_whitespace) {
+            self.space = true;
+        }
+    }
+
+    /// Start a new line, with a blank line in between for 2
+    fn block(&mut self, newlines: usize) {
+        if self.cells > 0 {
+            self.space = true;
+        } else if !self.item_start {
+            self.newlines = self.newlines.max(newlines);
+        }
+    }
+
+    /// Write the line breaks that are waiting and the prefix of the new line
+    fn flush_newlines(&mut self) {
+        let prefix = self.prefix.concat();
+
+        if self.out.is_empty() {
+            self.newlines = 0;
+            self.out.push_str(&prefix);
+            self.line_prefix = prefix;
+            self.fresh = true;
+            return;
+        }
+        if self.newlines == 0 {
+            return;
+        }
+
+        // Blank lines only keep the quotes that go on, a new quote starts after them
+        let shared = common_prefix(&self.line_prefix, &prefix);
+        for i in 0..self.newlines {
+            if i > 0 {
+                self.out.push_str(shared.trim_end());
+            }
+            self.out.push('\n');
+        }
+        self.out.push_str(&prefix);
+
+        self.newlines = 0;
+        self.line_prefix = prefix;
+        self.fresh = true;
+    }
+
+    fn write(&mut self, s: &str) {
+        self.flush_newlines();
+
+        if self.space && !self.fresh {
+            self.out.push(' ');
+        }
+        self.space = false;
+
+        self.out.push_str(&self.open);
+        self.open.clear();
+        self.out.push_str(s);
+
+        self.fresh = false;
+        self.item_start = false;
+    }
+
+    fn at_line_start(&self) -> bool {
+        self.newlines > 0 || self.out.is_empty() || self.fresh
+    }
+}
+
+fn common_prefix<'a>(a: &'a str, b: &str) -> &'a str {
+    let len = a
+        .char_indices()
+        .zip(b.chars())
+        .find(|((_, a), b)| a != b)
+        .map_or(a.len().min(b.len()), |((i, _), _)| i);
+
+    &a[..len]
+}
+
+fn is_absolute(url: &str) -> bool {
+    url.starts_with("https://") || url.starts_with("http://")
+}
+
+/// Escape the characters discord would see as markdown
+fn escape(text: &str, line_start: bool) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    if line_start && text.starts_with(['>', '#', '-']) {
+        escaped.push('\\');
+    }
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '~' | '`' | '|') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(html: &str) -> String {
+        Markdown::from_html(html, None).text
+    }
+
+    #[test]
+    fn paragraphs() {
+        assert_eq!(
+            text("<p>First  paragraph\n of text</p>\n<p>Second<br>line</p>"),
+            "First paragraph of text\n\nSecond\nline"
+        );
+        assert_eq!(text("<p></p><div> </div><p>Only</p>"), "Only");
+    }
+
+    #[test]
+    fn inline() {
+        assert_eq!(
+            text("<p><b>bold</b>, <i>italic</i>, <u>under</u>, <s>gone</s> and <strong><em>both</em></strong></p>"),
+            "**bold**, *italic*, __under__, ~~gone~~ and ***both***"
+        );
+        assert_eq!(text("<p>no <b> </b>markers</p>"), "no markers");
+    }
+
+    #[test]
+    fn headings() {
+        assert_eq!(
+            text("<h2>Exam</h2><p>On Monday</p>"),
+            "**Exam**\n\nOn Monday"
+        );
+    }
+
+    #[test]
+    fn links() {
+        assert_eq!(
+            text(r#"<p>See <a href="https://example.com/a_(b)">the <b>slides</b></a></p>"#),
+            "See [the **slides**](https://example.com/a_%28b%29)"
+        );
+        // Relative links go nowhere in discord, only the text is kept
+        assert_eq!(
+            text(r#"<p><a href="/courses/1/files">Files</a></p>"#),
+            "Files"
+        );
+    }
+
+    #[test]
+    fn relative_links_and_images() {
+        let base = Url::parse("https://canvas.example.com/courses/1/discussion_topics/5").unwrap();
+        let markdown = Markdown::from_html(
+            r#"<p><a href="/courses/1/files">Files</a> and <a href="../assignments">tasks</a>
+               <img src="map.png"> <a href="mailto:teacher@example.com">Mail</a></p>"#,
+            Some(&base),
+        );
+
+        assert_eq!(
+            markdown.text,
+            "[Files](https://canvas.example.com/courses/1/files) and \
+             [tasks](https://canvas.example.com/courses/1/assignments) Mail"
+        );
+        assert_eq!(
+            markdown.images,
+            ["https://canvas.example.com/courses/1/discussion_topics/map.png"]
+        );
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(
+            text("<p>2 * 3 = 6, snake_case, a|b, ~x~ and `tick`</p>"),
+            r"2 \* 3 = 6, snake\_case, a\|b, \~x\~ and \`tick\`"
+        );
+        assert_eq!(
+            text("<p># not a heading</p><p>- not a list</p>"),
+            "\\# not a heading\n\n\\- not a list"
+        );
+    }
+
+    #[test]
+    fn code() {
+        assert_eq!(
+            text("<p>Run <code>cargo *build*</code></p>"),
+            "Run `cargo *build*`"
+        );
+        assert_eq!(
+            text("<p>Example:</p><pre>fn main() {\n    println!(\"*hi*\");\n}\n</pre><p>Done</p>"),
+            "Example:\n\n```\nfn main() {\n    println!(\"*hi*\");\n}\n```\n\nDone"
+        );
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(
+            text("<p>Bring:</p><ul><li>a pen</li><li><p>paper</p></li></ul><p>Thanks</p>"),
+            "Bring:\n\n- a pen\n- paper\n\nThanks"
+        );
+        assert_eq!(
+            text("<ol><li>first<ul><li>nested</li></ul></li><li>second</li></ol>"),
+            "1. first\n   - nested\n2. second"
+        );
+    }
+
+    #[test]
+    fn quotes() {
+        assert_eq!(
+            text("<blockquote><p>Quoted</p><p>twice</p></blockquote><p>After</p>"),
+            "> Quoted\n>\n> twice\n\nAfter"
+        );
+    }
+
+    #[test]
+    fn tables() {
+        assert_eq!(
+            text("<table><tr><th>Day</th><th>Room</th></tr><tr><td>Monday</td><td><p>A1</p></td></tr></table>"),
+            "**Day** | **Room**\nMonday | A1"
+        );
+    }
+
+    #[test]
+    fn images() {
+        let markdown = Markdown::from_html(
+            r#"<p>Map: <img src="https://example.com/map.png"> <img src="/local.png"></p>"#,
+            None,
+        );
+
+        assert_eq!(markdown.text, "Map:");
+        assert_eq!(markdown.images, ["https://example.com/map.png"]);
+    }
+
+    #[test]
+    fn skipped_elements() {
+        assert_eq!(
+            text("<style>p { color: red }</style><script>alert(1)</script><p>Text</p>"),
+            "Text"
+        );
+    }
+
+    #[test]
+    fn plain_text() {
+        assert_eq!(
+            Markdown::from_content("text", "  First *line*\n# second\n", None).text,
+            "First \\*line\\*\n\\# second"
+        );
+        assert_eq!(
+            Markdown::from_content("html", "<p>a &amp; b</p>", None).text,
+            "a & b"
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+
+use crate::discord::AllowedMentions;
+
+/// Who is pinged with the announcements of a subscription, stored as json in `subscriptions.mentions`
+///
+/// Nobody is pinged by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Mentions {
+    /// Ids of the discord roles to ping
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+
+    /// Ids of the discord users to ping
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub users: Vec<String>,
+
+    /// Ping `@here`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub here: bool,
+
+    /// Only ping for announcements with one of the `include` keywords of the filter,
+    /// a filter without keywords pings for nothing then
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub only_keywords: bool,
+}
+
+impl Mentions {
+    /// Read the mentions of a subscription, `None` pings nobody
+    pub fn from_db(mentions: Option<&str>) -> Result<Self, serde_json::Error> {
+        match mentions {
+            Some(mentions) => serde_json::from_str(mentions),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Json to store, `None` when nobody is pinged
+    pub fn to_db(&self) -> Option<String> {
+        match self.is_empty() {
+            true => None,
+            false => serde_json::to_string(self).ok(),
+        }
+    }
+
+    /// Nobody is pinged
+    pub fn is_empty(&self) -> bool {
+        self.roles.is_empty() && self.users.is_empty() && !self.here
+    }
+
+    /// Check the role and user ids before they are stored, discord ids are numbers
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(role) = self.roles.iter().find(|role| !is_id(role)) {
+            return Err(format!("invalid role id '{role}'"));
+        }
+        match self.users.iter().find(|user| !is_id(user)) {
+            Some(user) => Err(format!("invalid user id '{user}'")),
+            None => Ok(()),
+        }
+    }
+
+    /// Text of the message that pings: `<@&123> <@456> @here`
+    pub fn content(&self) -> String {
+        self.roles
+            .iter()
+            .map(|role| format!("<@&{role}>"))
+            .chain(self.users.iter().map(|user| format!("<@{user}>")))
+            .chain(self.here.then(|| String::from("@here")))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Lets discord ping exactly these mentions and nothing else in the message
+    pub fn allowed_mentions(&self) -> AllowedMentions {
+        AllowedMentions {
+            // `@here` falls under `everyone`
+            parse: match self.here {
+                true => vec![String::from("everyone")],
+                false => Vec::new(),
+            },
+            roles: self.roles.clone(),
+            users: self.users.clone(),
+        }
+    }
+}
+
+fn is_id(id: &str) -> bool {
+    !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit())
+}
@@ -1,17 +1,47 @@
 use diesel::{QueryDsl, RunQueryDsl};
-use reqwest::IntoUrl;
-use serde::Deserialize;
+use reqwest::header::{HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{IntoUrl, StatusCode, Url};
+use scraper::{Html, Node};
+use serde::de::{Error, IgnoredAny, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::fmt;
 use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::task::JoinHandle;
 
-use crate::error::MyError;
+use crate::error::{DbError, FeedError, MyError};
 use crate::schema::feeds as schema_feeds;
 use crate::schema::feeds::dsl::feeds as db_feeds;
-use crate::Pool;
 use crate::{diesel::ExpressionMethods, DbSubscription};
+use crate::{HttpClient, Markdown, Pool};
 
-use super::{DbFeed, NewFeed};
+use rfc3339_time::Time;
 
-mod rfc3339_time {
+use super::{
+    Channel, DbAnnouncement, DbBackupFeed, DbDelivery, DbFeed, DbGuildSettings, FeedFormat, NewFeed,
+};
+
+/// Elements that start on a new line in `Content::text`
+const BLOCKS: &[&str] = &[
+    "p",
+    "div",
+    "br",
+    "li",
+    "tr",
+    "td",
+    "th",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "pre",
+    "blockquote",
+];
+
+pub(super) mod rfc3339_time {
+    use serde::de::Error;
     use serde::{Deserialize, Deserializer};
     use std::time::SystemTime;
     use time::format_description::well_known::Rfc3339;
@@ -20,17 +50,28 @@ mod rfc3339_time {
     pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
         let time: String = Deserialize::deserialize(deserializer)?;
 
-        // TODO: Get rid of this unwrap
-        Ok(OffsetDateTime::parse(&time, &Rfc3339).unwrap().into())
+        let time = OffsetDateTime::parse(&time, &Rfc3339)
+            .map_err(|e| D::Error::custom(format!("invalid time '{time}': {e}")))?;
+
+        Ok(time.into())
+    }
+
+    /// Time of an element that is not deserialized by a derive
+    pub struct Time(pub SystemTime);
+
+    impl<'de> Deserialize<'de> for Time {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserialize(deserializer).map(Time)
+        }
     }
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Feed {
+    #[serde(default)]
     pub xmlns: String,
 
-    /// Url of course announcements on canvas
+    /// Url of course announcements on canvas, the url of the feed itself for RSS feeds
     pub id: String,
 
     pub title: String,
@@ -40,15 +81,31 @@ pub struct Feed {
     pub updated: SystemTime,
 
     /// Same value as `id`
-    pub link: Link,
+    #[serde(default, rename = "link")]
+    pub links: Vec<Link>,
 
     /// All announcements
-    #[serde(rename = "entry")]
+    #[serde(default, rename = "entry")]
     pub announcements: Vec<Announcement>,
+
+    /// Format the feed was parsed from
+    #[serde(skip)]
+    pub format: FeedFormat,
+
+    /// Validators of the response the feed was parsed from
+    #[serde(skip)]
+    pub validators: Validators,
+}
+
+/// `ETag` and `Last-Modified` of a response, sent back so the server only responds
+/// with the feed when it changed
+#[derive(Debug, Clone, Default)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone)]
 pub struct Announcement {
     /// Title of announcement
     pub title: String,
@@ -59,61 +116,391 @@ pub struct Announcement {
     pub id: String,
 
     /// Last time announcement was updated (on canvas)
-    #[serde(with = "rfc3339_time")]
     pub updated: SystemTime,
 
-    /// When the announcement was placed
-    //#[serde(with = "rfc3339_time")]
-    //pub published: OffsetDateTime,
-    #[serde(with = "rfc3339_time")]
+    /// When the announcement was placed, `updated` when the feed leaves it out
+    ///
+    /// RSS items without date have `UNIX_EPOCH` until `Feed::date_undated` dates them.
     pub published: SystemTime,
 
-    /// Link to the the announcements
-    /// TODO: Check if there can be more than 1 <link/> in an entry
-    pub link: Link,
+    /// Links to the the announcement, see `Announcement::href`
+    pub links: Vec<Link>,
 
     /// Person that made the announcements
-    pub author: Author,
+    pub author: Option<Author>,
 
     /// Content
     pub content: Content,
+
+    /// Files attached to the announcement, only the canvas api has them
+    pub attachments: Vec<Attachment>,
+
+    /// Names of the sections the announcement is for, empty when it is for the whole course
+    pub sections: Vec<String>,
+
+    /// Set when the announcement was edited since it was stored
+    pub edit: Option<Edit>,
+}
+
+/// Deserialized by hand, a derive refuses `<link>` elements that are not next to each other
+impl<'de> Deserialize<'de> for Announcement {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct("entry", ENTRY_FIELDS, EntryVisitor)
+    }
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(deny_unknown_fields)]
+const ENTRY_FIELDS: &[&str] = &[
+    "title",
+    "id",
+    "updated",
+    "published",
+    "link",
+    "author",
+    "content",
+];
+
+struct EntryVisitor;
+
+impl<'de> Visitor<'de> for EntryVisitor {
+    type Value = Announcement;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "an atom <entry>")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut title = None;
+        let mut id = None;
+        let mut updated = None;
+        let mut published = None;
+        let mut links = Vec::new();
+        let mut author = None;
+        let mut content = None;
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "title" => title = Some(map.next_value()?),
+                "id" => id = Some(map.next_value()?),
+                "updated" => updated = Some(map.next_value::<Time>()?.0),
+                "published" => published = Some(map.next_value::<Time>()?.0),
+                "link" => links.push(map.next_value()?),
+                "author" => author = Some(map.next_value()?),
+                "content" => content = Some(map.next_value()?),
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        let updated = updated.ok_or_else(|| A::Error::missing_field("updated"))?;
+
+        Ok(Announcement {
+            title: title.ok_or_else(|| A::Error::missing_field("title"))?,
+            id: id.ok_or_else(|| A::Error::missing_field("id"))?,
+            updated,
+            published: published.unwrap_or(updated),
+            links,
+            author,
+            content: content.unwrap_or_default(),
+            attachments: Vec::new(),
+            sections: Vec::new(),
+            edit: None,
+        })
+    }
+}
+
+impl Announcement {
+    /// Url of the announcement: the `alternate` link, or the first link when there is none
+    pub fn href(&self) -> &str {
+        self.links
+            .iter()
+            .find(|link| link.rel == "alternate")
+            .or_else(|| self.links.first())
+            .map_or("", |link| link.href.as_str())
+    }
+
+    /// The content in the markdown discord understands,
+    /// relative links are resolved against the url of the announcement
+    pub fn markdown(&self) -> Markdown {
+        self.content.markdown(self.base().as_ref())
+    }
+
+    /// Url relative links in the content are resolved against, `None` when the url is not absolute
+    pub fn base(&self) -> Option<Url> {
+        Url::parse(self.href()).ok()
+    }
+
+    /// Name of the author, empty when the feed leaves it out
+    pub fn author_name(&self) -> &str {
+        self.author
+            .as_ref()
+            .map_or("", |author| author.name.as_str())
+    }
+}
+
+/// File attached to an announcement
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attachment {
+    /// File name shown to users
+    pub name: String,
+
+    /// Download url
+    pub url: String,
+
+    /// Mime type: application/pdf, ...
+    pub content_type: String,
+
+    /// Size in bytes
+    pub size: u64,
+}
+
+/// Previous version of an edited announcement
+#[derive(Debug, Clone)]
+pub struct Edit {
+    pub title: String,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct Link {
-    /// rel
+    /// rel, `alternate` when left out
+    #[serde(default = "alternate")]
     pub rel: String,
 
     /// href
     pub href: String,
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(deny_unknown_fields)]
+fn alternate() -> String {
+    String::from("alternate")
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct Author {
     /// Author: Firstame Lastname
     pub name: String,
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct Content {
     /// content type: html, ...
-    #[serde(rename = "type")]
+    #[serde(rename = "type", default)]
     pub content_type: String,
 
     /// html of the announcements itself
-    #[serde(rename = "$value")]
+    #[serde(rename = "$value", default)]
     pub content: String,
 }
 
+impl Content {
+    /// The content in the markdown discord understands, relative links are resolved against `base`
+    pub fn markdown(&self, base: Option<&Url>) -> Markdown {
+        Markdown::from_content(&self.content_type, &self.content, base)
+    }
+
+    /// The text of the content without markup, blocks are separated by a line break
+    pub fn text(&self) -> String {
+        if self.content_type == "text" {
+            return self.content.clone();
+        }
+
+        let fragment = Html::parse_fragment(&self.content);
+        let mut text = String::new();
+        for node in fragment.root_element().descendants() {
+            match node.value() {
+                Node::Text(t) => text.push_str(t),
+                Node::Element(e) if BLOCKS.contains(&e.name()) => text.push('\n'),
+                _ => {}
+            }
+        }
+
+        text.trim().to_owned()
+    }
+}
+
 impl Feed {
-    /// Get a feed from a atom url
-    pub async fn from_url<T: IntoUrl>(url: T) -> Result<Feed, MyError> {
-        let body = reqwest::get(url).await?.text().await?;
+    /// Get a feed from a url, the format of the feed is detected
+    pub async fn from_url<T: IntoUrl>(url: T, http: &HttpClient) -> Result<Feed, FeedError> {
+        Feed::from_url_as(url, None, http).await
+    }
+
+    /// Get a feed in `format` from a url, `None` detects the format
+    pub async fn from_url_as<T: IntoUrl>(
+        url: T,
+        format: Option<FeedFormat>,
+        http: &HttpClient,
+    ) -> Result<Feed, FeedError> {
+        // Without validators the response is never `304 Not Modified`
+        Feed::from_url_if_modified(url, format, &Validators::default(), http)
+            .await?
+            .ok_or(FeedError::Status(304))
+    }
+
+    /// Get a feed in `format` from a url unless it did not change since the response
+    /// `validators` are from, then returns `None` without parsing anything
+    pub async fn from_url_if_modified<T: IntoUrl>(
+        url: T,
+        format: Option<FeedFormat>,
+        validators: &Validators,
+        http: &HttpClient,
+    ) -> Result<Option<Feed>, FeedError> {
+        let url = url.into_url()?;
+
+        let mut request = http.get(url.clone());
+        if let Some(etag) = &validators.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
 
-        Ok(quick_xml::de::from_str::<Feed>(&body)?)
+        let response = http.send(request).await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value: &HeaderValue| value.to_str().ok())
+                .map(String::from)
+        };
+        let response_validators = Validators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+        let body = http.text(response).await?;
+
+        let format = match format {
+            Some(format) => format,
+            None => FeedFormat::detect(&body)?,
+        };
+        let mut feed = format.parse(url.as_str(), &body)?;
+        feed.validators = response_validators;
+
+        // The id is what tells feeds apart
+        if feed.id.trim().is_empty() {
+            return Err(FeedError::MissingId);
+        }
+
+        Ok(Some(feed))
+    }
+
+    /// Get the feed from its primary url, or from the first backup url that works
+    ///
+    /// A working backup url is promoted to primary url.
+    /// Returns the error of the primary url when every url fails.
+    pub async fn fetch(db_feed: &DbFeed, http: &HttpClient, pool: &Pool) -> Result<Feed, MyError> {
+        Feed::fetch_with(db_feed, &Validators::default(), http, pool)
+            .await?
+            .ok_or_else(|| FeedError::Status(304).into())
+    }
+
+    /// Same as `Feed::fetch`, but returns `None` when the primary url responds that the feed
+    /// did not change since the last processed response
+    pub async fn fetch_if_modified(
+        db_feed: &DbFeed,
+        http: &HttpClient,
+        pool: &Pool,
+    ) -> Result<Option<Feed>, MyError> {
+        Feed::fetch_with(db_feed, &db_feed.validators(), http, pool).await
+    }
+
+    async fn fetch_with(
+        db_feed: &DbFeed,
+        validators: &Validators,
+        http: &HttpClient,
+        pool: &Pool,
+    ) -> Result<Option<Feed>, MyError> {
+        let format = FeedFormat::from_name(&db_feed.format);
+
+        // Backups are urls of other users, the token is not theirs.
+        // Every poll asks for other dates, there is nothing to validate.
+        if format == Some(FeedFormat::CanvasApi) {
+            return Ok(Some(Feed::from_canvas_api_of(db_feed, http, pool).await?));
+        }
+
+        let err = match Feed::from_url_if_modified(&db_feed.url, format, validators, http).await {
+            Ok(Some(mut feed)) => {
+                feed.date_undated(db_feed, pool)?;
+                return Ok(Some(feed));
+            }
+            Ok(None) => return Ok(None),
+            Err(err) => err,
+        };
+
+        // The validators are of the primary url
+        for backup in DbBackupFeed::get_by_feed_id(db_feed.id, pool)? {
+            match Feed::from_url_as(&backup.url, format, http).await {
+                // Only a backup when it still is the same course
+                Ok(mut feed) if feed.id == db_feed.canvas_id => {
+                    backup.promote(pool)?;
+                    feed.date_undated(db_feed, pool)?;
+                    return Ok(Some(feed));
+                }
+                _ => continue,
+            }
+        }
+
+        Err(err.into())
+    }
+
+    /// Give the announcements without date the dates they were stored with,
+    /// so they keep the same dates every poll and are never seen as edited
+    fn date_undated(&mut self, db_feed: &DbFeed, pool: &Pool) -> Result<(), DbError> {
+        let undated: Vec<&str> = self
+            .announcements
+            .iter()
+            .filter(|announcement| announcement.published == UNIX_EPOCH)
+            .map(|announcement| announcement.id.as_str())
+            .collect();
+        if undated.is_empty() {
+            return Ok(());
+        }
+
+        let stored = DbAnnouncement::dates(db_feed.id, &undated, pool)?;
+        self.set_dates(&stored, SystemTime::now());
+
+        Ok(())
+    }
+
+    /// Date the announcements without date with their `(published, updated)` in `stored`,
+    /// the ones that were not stored yet are first seen `now`
+    fn set_dates(&mut self, stored: &HashMap<String, (SystemTime, SystemTime)>, now: SystemTime) {
+        for announcement in &mut self.announcements {
+            if announcement.published != UNIX_EPOCH {
+                continue;
+            }
+
+            let (published, updated) = stored.get(&announcement.id).copied().unwrap_or((now, now));
+            announcement.published = published;
+            announcement.updated = updated;
+        }
+    }
+
+    /// Read a feed in the `canvas_api` format with the token of one of its subscribers,
+    /// a token that is refused does not stop the others
+    async fn from_canvas_api_of(
+        db_feed: &DbFeed,
+        http: &HttpClient,
+        pool: &Pool,
+    ) -> Result<Feed, MyError> {
+        let mut tokens = DbSubscription::api_tokens(db_feed.id, pool)?;
+        if tokens.is_empty() {
+            tokens.push(String::new());
+        }
+
+        let mut result = Err(FeedError::Status(401));
+        for token in &tokens {
+            result = Feed::from_canvas_api(&db_feed.url, token, http).await;
+            match result {
+                Err(FeedError::Status(401 | 403)) => continue,
+                _ => break,
+            }
+        }
+
+        Ok(result?)
     }
 
     /// only keep announcements published after `after`
@@ -130,63 +517,65 @@ impl Feed {
             .map(|f| f.published)
     }
 
-    /// Add a feed to the db
-    pub async fn add(feed_url: &str, pool: &Pool) -> Result<(), MyError> {
-        // Check if feed url is valid
-        let feed = Feed::from_url(feed_url).await;
-        if feed.is_err() {
-            return Err(MyError::new("Cold not deserialize xml file"));
-        }
+    /// Add a feed to the db, a new url for a known feed is stored as backup url
+    ///
+    /// Returns the id of the feed in the db
+    pub async fn add(feed_url: &str, http: &HttpClient, pool: &Pool) -> Result<i32, MyError> {
+        let feed = Feed::from_url(feed_url, http).await?;
 
-        // Not an error checked above
-        let feed = feed.unwrap();
+        Ok(feed.store(feed_url, pool)?)
+    }
 
+    /// Store the feed retrieved from `feed_url` unless it is already known,
+    /// returns the id of the feed in the db
+    pub(crate) fn store(&self, feed_url: &str, pool: &Pool) -> Result<i32, DbError> {
         let conn = pool.get()?;
 
-        // Check if feed id already exists
-        match diesel::select(diesel::dsl::exists(
-            db_feeds.filter(schema_feeds::canvas_id.eq(&feed.id)),
-        ))
-        .get_result(&conn)
+        let new_feed = NewFeed {
+            canvas_id: &self.id,
+            url: feed_url,
+            last_update: UNIX_EPOCH,
+            title: &self.title,
+            format: self.format.as_str(),
+        };
+
+        // Also does nothing when another subscribe added the feed in the meantime
+        diesel::insert_into(schema_feeds::table)
+            .values(&new_feed)
+            .on_conflict_do_nothing()
+            .execute(&conn)?;
+
+        let feed_id = match db_feeds
+            .filter(schema_feeds::canvas_id.eq(&self.id))
+            .select(schema_feeds::id)
+            .get_result(&conn)
         {
-            Ok(true) => {
-                match diesel::select(diesel::dsl::exists(
-                    db_feeds.filter(schema_feeds::url.eq(feed_url)),
-                ))
-                .get_result(&conn)
-                {
-                    Ok(true) => (), // Exact feed already exists
-                    Ok(false) => {
-                        // Add to backup feeds
-                        todo!();
-                    }
-                    Err(_) => todo!(), // Error
-                }
+            Ok(feed_id) => feed_id,
+            // Not inserted because the url is the primary url of another feed
+            Err(diesel::result::Error::NotFound) => {
+                return Err(DbError::Conflict(format!(
+                    "{feed_url} belongs to another feed than {}",
+                    self.id
+                )))
             }
-            Ok(false) => {
-                // Add to db
-                let new_feed = NewFeed {
-                    canvas_id: &feed.id,
-                    url: feed_url,
-                    last_update: UNIX_EPOCH,
-                };
-
-                // returns rows changes/inserted
-                let _ret: usize = diesel::insert_into(schema_feeds::table)
-                    .values(&new_feed)
-                    .execute(&conn)?;
-
-                return Ok(());
-            }
-            Err(_) => todo!(),
+            Err(e) => return Err(e.into()),
+        };
+
+        // The tokens of the canvas api are kept by the subscriptions, a course has no backups.
+        // Nothing happens when `feed_url` already is the primary url.
+        if self.format != FeedFormat::CanvasApi {
+            DbBackupFeed::add(feed_id, feed_url, pool)?;
         }
 
-        Err(MyError::new("blablabla"))
+        Ok(feed_id)
     }
 
-    /// Retrieve all feeds
-    pub async fn get_all(pool: &Pool) -> Result<Option<Vec<Self>>, MyError> {
-        let vec_db_feeds = match DbFeed::get_all(pool) {
+    /// Retrieve all feeds that are not disabled, a feed that fails does not stop the others
+    pub async fn get_all(
+        http: &HttpClient,
+        pool: &Pool,
+    ) -> Result<Option<Vec<FeedOutcome<Self>>>, MyError> {
+        let vec_db_feeds = match DbFeed::get_active(pool) {
             Ok(Some(vec)) => vec,
             Ok(None) => return Ok(None),
             //Err(DieselError::NotFound) => return Ok(None), // TODO: Return Ok(None) when we have a proper error type
@@ -196,23 +585,30 @@ impl Feed {
         // spawn tasks to retrieve the xml feeds
         let tasks: Vec<_> = vec_db_feeds
             .iter()
-            .map(|item| tokio::spawn(Feed::from_url(item.url.to_owned())))
+            .map(|item| spawn_fetch(item, http, pool))
             .collect();
 
         // collect tasks
-        let mut feeds: Vec<Feed> = Vec::new();
-        for task in tasks {
-            feeds.push(task.await??);
+        let mut outcomes = Vec::new();
+        for (db_feed, task) in vec_db_feeds.into_iter().zip(tasks) {
+            let result = match task.await {
+                Ok(feed) => feed,
+                Err(err) => Err(err.into()),
+            };
+
+            outcomes.push(FeedOutcome::new(db_feed, result, pool));
         }
 
-        Ok(Some(feeds))
+        Ok(Some(outcomes))
     }
 
-    /// Retrieve feeds containing only announcements placed after the last time this function was called
+    /// Retrieve feeds containing only announcements placed after the last time this function was called,
+    /// a feed that fails does not stop the others
     pub async fn get_new(
+        http: &HttpClient,
         pool: &Pool,
-    ) -> Result<Option<Vec<(Self, Vec<(String, String)>)>>, MyError> {
-        let vec_db_feeds = match DbFeed::get_all(pool) {
+    ) -> Result<Option<Vec<FeedOutcome<Vec<(Self, Vec<Channel>)>>>>, MyError> {
+        let vec_db_feeds = match DbFeed::get_active(pool) {
             Ok(Some(vec)) => vec,
             Ok(None) => return Ok(None),
             //Err(DieselError::NotFound) => return Ok(None), // TODO: Return Ok(None) when we have a proper error type
@@ -222,36 +618,416 @@ impl Feed {
         // spawn tasks to retrieve the xml feeds
         let tasks: Vec<_> = vec_db_feeds
             .iter()
-            .map(|item| tokio::spawn(Feed::from_url(item.url.to_owned())))
+            .map(|item| spawn_fetch_if_modified(item, http, pool))
             .collect(); // NOTE: .collect() needed otherwise start on .await
 
+        // collect tasks
+        let mut outcomes = Vec::new();
+        for (db_feed, task) in vec_db_feeds.into_iter().zip(tasks) {
+            let result = match task.await {
+                Ok(Ok(Some(feed))) => feed.keep_new(&db_feed, pool),
+                // Not modified, so nothing new
+                Ok(Ok(None)) => Ok(Vec::new()),
+                Ok(Err(err)) => Err(err),
+                Err(err) => Err(err.into()),
+            };
+
+            outcomes.push(FeedOutcome::new(db_feed, result, pool));
+        }
+
+        Ok(Some(outcomes))
+    }
+
+    /// Retrieve a single feed containing only new and edited announcements
+    pub async fn get_new_for(
+        db_feed: &DbFeed,
+        http: &HttpClient,
+        pool: &Pool,
+    ) -> FeedOutcome<Vec<(Self, Vec<Channel>)>> {
+        let result = match Feed::fetch_if_modified(db_feed, http, pool).await {
+            Ok(Some(feed)) => feed.keep_new(db_feed, pool),
+            Ok(None) => Ok(Vec::new()),
+            Err(err) => Err(err),
+        };
+
+        FeedOutcome::new(db_feed.clone(), result, pool)
+    }
+
+    /// Store the announcements of the feed and only keep the ones that are new or edited,
+    /// bump `last_update` and attach the subscribed channels
+    ///
+    /// Returns one feed per group of channels receiving the same announcements,
+    /// empty when there are no new or edited announcements
+    fn keep_new(
+        mut self,
+        db_feed: &DbFeed,
+        pool: &Pool,
+    ) -> Result<Vec<(Self, Vec<Channel>)>, MyError> {
+        if self.title != db_feed.title {
+            let conn = pool.get()?;
+
+            diesel::update(db_feeds.find(db_feed.id))
+                .set(schema_feeds::title.eq(&self.title))
+                .execute(&conn)?;
+        }
+
+        // Feeds added before announcements were stored already sent everything up to `last_update`
+        let seeding = !DbAnnouncement::exists_for_feed(db_feed.id, pool)?;
+
+        DbAnnouncement::update_edited(db_feed.id, &mut self.announcements, pool)?;
+        let inserted = DbAnnouncement::add_all(db_feed.id, &self.announcements, pool)?;
+        self.announcements.retain(|announcement| {
+            announcement.edit.is_some()
+                || inserted.contains(&announcement.id)
+                    && (!seeding || announcement.published > db_feed.last_update)
+        });
+
+        // Only once the announcements are stored, the next poll would skip them otherwise
+        DbFeed::set_validators(db_feed.id, &self.validators, pool)?;
+
+        if self.announcements.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let last = self
+            .announcements
+            .iter()
+            .filter(|a| a.edit.is_none())
+            .map(|a| a.published)
+            .fold(db_feed.last_update, SystemTime::max);
+
         let conn = pool.get()?;
 
-        // collect tasks
-        let mut ret: Vec<(Feed, Vec<(String, String)>)> = Vec::new();
-        for (i, task) in tasks.into_iter().enumerate() {
-            let mut feed = task.await??;
-
-            // Only keep announcement published after the last_update
-            if let Some(last) = feed.after(vec_db_feeds[i].last_update) {
-                // Update the `last_update` table field of the feed
-                diesel::update(db_feeds.filter(schema_feeds::canvas_id.eq(&feed.id)))
-                    .set(schema_feeds::last_update.eq(last))
-                    .execute(&conn)?;
-
-                let subs =
-                    if let Some(v) = DbSubscription::get_by_feed_id(vec_db_feeds[i].id, pool)? {
-                        v.iter()
-                            .map(|s| (s.server_id.clone(), s.channel_id.clone()))
-                            .collect()
-                    } else {
-                        Vec::new()
-                    };
-
-                ret.push((feed, subs));
+        // Update the `last_update` table field of the feed
+        diesel::update(db_feeds.filter(schema_feeds::canvas_id.eq(&self.id)))
+            .set(schema_feeds::last_update.eq(last))
+            .execute(&conn)?;
+
+        let subs = DbSubscription::get_by_feed_id(db_feed.id, pool)?.unwrap_or_default();
+
+        // The filter of a subscription is read once for all announcements
+        let subs: Vec<(&DbSubscription, Vec<usize>)> = subs
+            .iter()
+            .map(|subscription| (subscription, subscription.wanted(&self.announcements)))
+            .collect();
+
+        // Queue the announcements until the consumer acknowledges them
+        DbDelivery::add_all(&subs, &self.announcements, pool)?;
+
+        // Servers in their quiet hours get them once the quiet hours end
+        let quiet = DbGuildSettings::quiet_at(SystemTime::now(), pool)?;
+        for (server_id, until) in &quiet {
+            let held = subs.iter().any(|(subscription, wanted)| {
+                subscription.server_id == *server_id && !wanted.is_empty()
+            });
+            if held {
+                DbGuildSettings::hold(server_id, *until, pool)?;
+            }
+        }
+
+        Ok(self.split(&subs, &quiet))
+    }
+
+    /// Split the feed into one feed per group of subscriptions receiving the same announcements,
+    /// leaving out the servers in `quiet`
+    ///
+    /// Every subscription comes with the indices of the announcements it wants.
+    fn split(
+        self,
+        subscriptions: &[(&DbSubscription, Vec<usize>)],
+        quiet: &HashMap<String, SystemTime>,
+    ) -> Vec<(Self, Vec<Channel>)> {
+        if subscriptions.is_empty() {
+            return vec![(self, Vec::new())];
+        }
+
+        // indices of the announcements and the channels receiving them
+        let mut groups: Vec<(Vec<usize>, Vec<Channel>)> = Vec::new();
+        // The server sends to the other sinks itself, digests wait for the end of their window
+        let now = SystemTime::now();
+        for (subscription, wanted) in subscriptions.iter().filter(|(s, _)| {
+            s.delivered_by_bot() && s.digest_end(now).is_none() && !quiet.contains_key(&s.server_id)
+        }) {
+            if wanted.is_empty() {
+                continue;
+            }
+
+            let announcements: Vec<Announcement> = wanted
+                .iter()
+                .map(|&i| self.announcements[i].clone())
+                .collect();
+            let channel = Channel::for_subscription(subscription, &announcements);
+            match groups.iter_mut().find(|(w, _)| *w == *wanted) {
+                Some((_, channels)) => channels.push(channel),
+                None => groups.push((wanted.clone(), vec![channel])),
+            }
+        }
+
+        groups
+            .into_iter()
+            .map(|(wanted, channels)| {
+                let mut feed = self.clone();
+                feed.announcements = wanted
+                    .into_iter()
+                    .map(|i| self.announcements[i].clone())
+                    .collect();
+                (feed, channels)
+            })
+            .collect()
+    }
+}
+
+/// Fetch the feed in a separate task, `http` limits how many requests are sent at once
+fn spawn_fetch(
+    db_feed: &DbFeed,
+    http: &HttpClient,
+    pool: &Pool,
+) -> JoinHandle<Result<Feed, MyError>> {
+    let db_feed = db_feed.clone();
+    let http = http.clone();
+    let pool = pool.clone();
+
+    tokio::spawn(async move { Feed::fetch(&db_feed, &http, &pool).await })
+}
+
+/// Fetch the feed in a separate task, `None` when it did not change
+fn spawn_fetch_if_modified(
+    db_feed: &DbFeed,
+    http: &HttpClient,
+    pool: &Pool,
+) -> JoinHandle<Result<Option<Feed>, MyError>> {
+    let db_feed = db_feed.clone();
+    let http = http.clone();
+    let pool = pool.clone();
+
+    tokio::spawn(async move { Feed::fetch_if_modified(&db_feed, &http, &pool).await })
+}
+
+/// Outcome of retrieving a single feed of the db
+#[derive(Debug)]
+pub struct FeedOutcome<T> {
+    /// The feed as stored in the db
+    pub db_feed: DbFeed,
+
+    pub result: Result<T, MyError>,
+
+    /// Whether the health of the feed could be recorded on its row
+    pub recorded: Result<(), MyError>,
+}
+
+impl<T> FeedOutcome<T> {
+    /// The health of the feed is recorded on its row
+    fn new(db_feed: DbFeed, result: Result<T, MyError>, pool: &Pool) -> Self {
+        let recorded = record(&db_feed, &result, pool);
+
+        Self {
+            db_feed,
+            result,
+            recorded,
+        }
+    }
+}
+
+/// Update the health of the feed after retrieving it
+fn record<T>(db_feed: &DbFeed, result: &Result<T, MyError>, pool: &Pool) -> Result<(), MyError> {
+    let recorded = match result {
+        Ok(_) => DbFeed::record_success(db_feed.id, pool),
+        Err(err) => {
+            let status = match err {
+                MyError::Feed(FeedError::Status(status)) => Some(*status),
+                _ => None,
             };
+
+            DbFeed::record_failure(db_feed.id, &err.to_string(), status, pool)
+        }
+    };
+
+    Ok(recorded?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{http_client, Reply, TestServer};
+    use time::macros::datetime;
+
+    const FEED: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Course One Announcements</title>
+  <id>tag:canvas.example.com,2022-01-01:/courses/1</id>
+  <updated>2022-01-21T10:00:00+00:00</updated>
+  <link rel="alternate" href="https://canvas.example.com/courses/1"/>
+  <entry>
+    <title>Exam info</title>
+    <id>tag:canvas.example.com,2022-01-20:/courses/1/discussion_topics/5</id>
+    <link rel="alternate" href="https://canvas.example.com/courses/1/discussion_topics/5"/>
+    <category term="exams"/>
+    <link rel="enclosure" href="https://canvas.example.com/files/7"/>
+    <updated>2022-01-21T10:00:00+00:00</updated>
+    <published>2022-01-20T10:00:00+00:00</published>
+    <author>
+      <name>Jane Doe</name>
+    </author>
+    <content type="html">&lt;p&gt;Exam on &lt;b&gt;Monday&lt;/b&gt;&lt;/p&gt;</content>
+  </entry>
+  <entry>
+    <title>Welcome</title>
+    <id>tag:canvas.example.com,2022-01-10:/courses/1/discussion_topics/4</id>
+    <link href="https://canvas.example.com/courses/1/discussion_topics/4"/>
+    <updated>2022-01-10T08:00:00+00:00</updated>
+    <content type="html">&lt;p&gt;Hello&lt;/p&gt;</content>
+  </entry>
+</feed>"#;
+
+    fn parse(xml: &str) -> Feed {
+        FeedFormat::Atom
+            .parse("https://canvas.example.com/feeds/1.atom", xml)
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_the_entries() {
+        let feed = parse(FEED);
+
+        assert_eq!(feed.title, "Course One Announcements");
+        assert_eq!(feed.format, FeedFormat::Atom);
+        assert_eq!(feed.announcements.len(), 2);
+
+        let exam = &feed.announcements[0];
+        assert_eq!(exam.title, "Exam info");
+        assert_eq!(exam.published, datetime!(2022-01-20 10:00 UTC));
+        assert_eq!(exam.updated, datetime!(2022-01-21 10:00 UTC));
+        assert_eq!(exam.author_name(), "Jane Doe");
+        assert_eq!(exam.content.content_type, "html");
+        assert_eq!(exam.content.content, "<p>Exam on <b>Monday</b></p>");
+    }
+
+    #[test]
+    fn links_apart_from_each_other() {
+        let exam = &parse(FEED).announcements[0];
+
+        let links: Vec<(&str, &str)> = exam
+            .links
+            .iter()
+            .map(|link| (link.rel.as_str(), link.href.as_str()))
+            .collect();
+        assert_eq!(
+            links,
+            [
+                (
+                    "alternate",
+                    "https://canvas.example.com/courses/1/discussion_topics/5"
+                ),
+                ("enclosure", "https://canvas.example.com/files/7"),
+            ]
+        );
+        assert_eq!(
+            exam.href(),
+            "https://canvas.example.com/courses/1/discussion_topics/5"
+        );
+    }
+
+    #[test]
+    fn optional_author_and_published() {
+        let welcome = &parse(FEED).announcements[1];
+
+        assert!(welcome.author.is_none());
+        assert_eq!(welcome.author_name(), "");
+        assert_eq!(welcome.published, datetime!(2022-01-10 08:00 UTC));
+        assert_eq!(welcome.published, welcome.updated);
+        assert_eq!(welcome.links[0].rel, "alternate");
+    }
+
+    #[test]
+    fn undated_announcements_keep_their_first_dates() {
+        let mut feed = parse(FEED);
+        let stored_at = datetime!(2022-01-15 12:00 UTC).into();
+        let now = datetime!(2022-01-22 12:00 UTC).into();
+        for announcement in &mut feed.announcements {
+            announcement.published = UNIX_EPOCH;
+            announcement.updated = UNIX_EPOCH;
         }
+        feed.announcements[0].published = datetime!(2022-01-20 10:00 UTC).into();
+
+        let stored = HashMap::from([(feed.announcements[1].id.clone(), (stored_at, stored_at))]);
+        feed.set_dates(&stored, now);
+
+        // Dated announcements are left alone, the dates of the others are the stored ones
+        assert_eq!(
+            feed.announcements[0].published,
+            datetime!(2022-01-20 10:00 UTC)
+        );
+        assert_eq!(feed.announcements[0].updated, UNIX_EPOCH);
+        assert_eq!(feed.announcements[1].published, stored_at);
+        assert_eq!(feed.announcements[1].updated, stored_at);
+
+        feed.set_dates(&HashMap::new(), now);
+        assert_eq!(feed.announcements[1].published, stored_at);
+
+        let mut feed = parse(FEED);
+        feed.announcements[1].published = UNIX_EPOCH;
+        feed.set_dates(&HashMap::new(), now);
+        assert_eq!(feed.announcements[1].published, now);
+        assert_eq!(feed.announcements[1].updated, now);
+    }
+
+    #[tokio::test]
+    async fn sends_the_validators() {
+        let server = TestServer::start(vec![
+            Reply::new(200, FEED)
+                .header("ETag", "\"v1\"")
+                .header("Last-Modified", "Fri, 21 Jan 2022 10:00:00 GMT"),
+            Reply::new(304, ""),
+        ])
+        .await;
+        let http = http_client();
+
+        let feed = Feed::from_url_if_modified(
+            server.url("/feed.atom"),
+            None,
+            &Validators::default(),
+            &http,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(feed.validators.etag.as_deref(), Some("\"v1\""));
+
+        // Not parsed: the body of the 304 is not a feed
+        let not_modified =
+            Feed::from_url_if_modified(server.url("/feed.atom"), None, &feed.validators, &http)
+                .await
+                .unwrap();
+        assert!(not_modified.is_none());
+
+        let received = server.received();
+        assert_eq!(received[0].header("If-None-Match"), None);
+        assert_eq!(received[1].header("If-None-Match"), Some("\"v1\""));
+        assert_eq!(
+            received[1].header("If-Modified-Since"),
+            Some("Fri, 21 Jan 2022 10:00:00 GMT")
+        );
+    }
+
+    #[tokio::test]
+    async fn not_modified_without_validators() {
+        let server = TestServer::start(vec![Reply::new(304, "")]).await;
+
+        let err = Feed::from_url(server.url("/feed.atom"), &http_client())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, FeedError::Status(304)), "{err:?}");
+    }
+
+    #[test]
+    fn entry_without_updated() {
+        let xml = FEED.replace("<updated>2022-01-10T08:00:00+00:00</updated>", "");
+
+        let err = FeedFormat::Atom
+            .parse("https://canvas.example.com/feeds/1.atom", &xml)
+            .unwrap_err();
 
-        Ok(Some(ret))
+        assert!(err.to_string().contains("updated"), "{err}");
     }
 }
@@ -0,0 +1,394 @@
+use reqwest::header::{HeaderMap, LINK};
+use reqwest::Url;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::time::SystemTime;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::error::FeedError;
+use crate::HttpClient;
+
+use super::canvas::{Attachment, Author, Content, Link};
+use super::{Announcement, Feed, FeedFormat};
+
+/// Most pages of announcements read per poll
+const MAX_PAGES: usize = 10;
+
+/// A course on a canvas instance
+struct Course {
+    /// Url of the canvas instance, the api is relative to it
+    base: Url,
+    id: String,
+}
+
+impl Course {
+    /// Parse a course url: https://canvas.example.com/courses/123
+    fn from_url(url: &str) -> Result<Self, FeedError> {
+        let invalid = || {
+            FeedError::InvalidFeedUrl(format!(
+                "{url} is not a canvas course url like https://canvas.example.com/courses/123"
+            ))
+        };
+
+        let mut base = Url::parse(url).map_err(|_| invalid())?;
+
+        let mut segments = base.path_segments().into_iter().flatten();
+        let id = loop {
+            match segments.next() {
+                Some("courses") => break segments.next(),
+                Some(_) => continue,
+                None => break None,
+            }
+        };
+        let id = id
+            .filter(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()))
+            .ok_or_else(invalid)?
+            .to_owned();
+
+        base.set_path("");
+        base.set_query(None);
+        base.set_fragment(None);
+
+        Ok(Self { base, id })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base.as_str().trim_end_matches('/'))
+    }
+}
+
+#[derive(Deserialize)]
+struct ApiCourse {
+    name: String,
+}
+
+/// Discussion topic as returned by `/api/v1/announcements`
+#[derive(Deserialize)]
+struct ApiAnnouncement {
+    title: String,
+    message: Option<String>,
+    html_url: String,
+    posted_at: Option<String>,
+    delayed_post_at: Option<String>,
+
+    /// Last edit
+    updated_at: Option<String>,
+
+    user_name: Option<String>,
+    author: Option<ApiAuthor>,
+    attachments: Option<Vec<ApiAttachment>>,
+
+    /// Only set for announcements that target some sections
+    sections: Option<Vec<ApiSection>>,
+}
+
+#[derive(Deserialize)]
+struct ApiAuthor {
+    display_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ApiAttachment {
+    display_name: String,
+    url: String,
+
+    #[serde(rename = "content-type", default)]
+    content_type: String,
+
+    #[serde(default)]
+    size: u64,
+}
+
+#[derive(Deserialize)]
+struct ApiSection {
+    name: String,
+}
+
+impl Feed {
+    /// Get the announcements of a canvas course through the canvas api
+    ///
+    /// `url` is the url of the course, the api is on the same host.
+    /// `token` is an access token of a user in the course.
+    pub async fn from_canvas_api(
+        url: &str,
+        token: &str,
+        http: &HttpClient,
+    ) -> Result<Feed, FeedError> {
+        let course = Course::from_url(url)?;
+
+        let (api_course, _) = get_json::<ApiCourse>(
+            http,
+            &course,
+            &course.url(&format!("/api/v1/courses/{}", course.id)),
+            token,
+        )
+        .await?;
+
+        // Without dates only the announcements of the last 14 days are returned
+        let end_date = (OffsetDateTime::now_utc() + time::Duration::days(1))
+            .format(&Rfc3339)
+            .map_err(FeedError::new)?;
+        let mut next = Url::parse_with_params(
+            &course.url("/api/v1/announcements"),
+            &[
+                ("context_codes[]", format!("course_{}", course.id)),
+                ("start_date", String::from("2000-01-01")),
+                ("end_date", end_date),
+                ("include[]", String::from("sections")),
+                ("per_page", String::from("100")),
+            ],
+        )
+        .map(String::from)
+        .ok();
+
+        let mut announcements = Vec::new();
+        for _ in 0..MAX_PAGES {
+            let page_url = match next.take() {
+                Some(page_url) => page_url,
+                None => break,
+            };
+
+            let (page, next_page) =
+                get_json::<Vec<ApiAnnouncement>>(http, &course, &page_url, token).await?;
+            announcements.extend(page.into_iter().map(ApiAnnouncement::into_announcement));
+            next = next_page;
+        }
+
+        let updated = announcements
+            .iter()
+            .map(|a| a.updated)
+            .max()
+            .unwrap_or_else(SystemTime::now);
+        let course_url = course.url(&format!("/courses/{}", course.id));
+
+        Ok(Feed {
+            xmlns: String::new(),
+            id: course_url.clone(),
+            title: api_course.name,
+            updated,
+            links: vec![alternate(course_url)],
+            announcements,
+            format: FeedFormat::CanvasApi,
+            validators: Default::default(),
+        })
+    }
+}
+
+impl ApiAnnouncement {
+    fn into_announcement(self) -> Announcement {
+        let published = self
+            .posted_at
+            .or(self.delayed_post_at)
+            .and_then(|date| parse_date(&date))
+            .unwrap_or_else(SystemTime::now);
+        let updated = self
+            .updated_at
+            .and_then(|date| parse_date(&date))
+            .unwrap_or(published);
+
+        let author = self
+            .author
+            .and_then(|author| author.display_name)
+            .or(self.user_name)
+            .map(|name| Author { name });
+
+        let attachments = self
+            .attachments
+            .unwrap_or_default()
+            .into_iter()
+            .map(|attachment| Attachment {
+                name: attachment.display_name,
+                url: attachment.url,
+                content_type: attachment.content_type,
+                size: attachment.size,
+            })
+            .collect();
+
+        let sections = self
+            .sections
+            .unwrap_or_default()
+            .into_iter()
+            .map(|section| section.name)
+            .collect();
+
+        Announcement {
+            title: self.title,
+            // The numeric id is only unique within a canvas instance
+            id: self.html_url.clone(),
+            updated,
+            published,
+            links: vec![alternate(self.html_url)],
+            author,
+            content: Content {
+                content_type: String::from("html"),
+                content: self.message.unwrap_or_default(),
+            },
+            attachments,
+            sections,
+            edit: None,
+        }
+    }
+}
+
+fn alternate(href: String) -> Link {
+    Link {
+        rel: String::from("alternate"),
+        href,
+    }
+}
+
+fn parse_date(date: &str) -> Option<SystemTime> {
+    OffsetDateTime::parse(date, &Rfc3339).ok().map(Into::into)
+}
+
+/// Get a json response and the url of the next page
+///
+/// The token is only sent to the canvas instance of the course, whatever the `Link` header says.
+async fn get_json<T: DeserializeOwned>(
+    http: &HttpClient,
+    course: &Course,
+    url: &str,
+    token: &str,
+) -> Result<(T, Option<String>), FeedError> {
+    let url = Url::parse(url).map_err(|e| FeedError::InvalidFeedUrl(e.to_string()))?;
+    if url.origin() != course.base.origin() {
+        return Err(FeedError::InvalidFeedUrl(format!(
+            "{url} is not on the canvas instance {}",
+            course.base
+        )));
+    }
+    let response = http.send(http.get(url).bearer_auth(token)).await?;
+
+    let next = next_link(response.headers());
+    let body = http.text(response).await?;
+
+    Ok((serde_json::from_str(&body)?, next))
+}
+
+/// Pages are linked in the `Link` header: `<https://..&page=2>; rel="next", <..>; rel="last"`
+fn next_link(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(LINK)?
+        .to_str()
+        .ok()?
+        .split(',')
+        .find(|link| link.contains("rel=\"next\""))?
+        .split(';')
+        .next()
+        .map(|url| url.trim().trim_matches(|c| c == '<' || c == '>').to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{http_client, Reply, TestServer};
+
+    const COURSE: &str = r#"{"name": "Course One"}"#;
+
+    fn announcement(id: u32, title: &str) -> String {
+        format!(
+            r#"{{"title": "{title}", "message": "<p>{title}</p>", "user_name": "Jane Doe",
+                "html_url": "{{base}}/courses/123/discussion_topics/{id}",
+                "posted_at": "2022-01-{id:02}T10:00:00Z"}}"#
+        )
+    }
+
+    #[tokio::test]
+    async fn follows_the_next_links() {
+        let server = TestServer::start(vec![
+            Reply::new(200, COURSE),
+            Reply::new(200, &format!("[{}]", announcement(2, "Exam info"))).header(
+                "Link",
+                r#"<{base}/api/v1/announcements?page=2>; rel="next", <{base}/api/v1/announcements?page=2>; rel="last""#,
+            ),
+            Reply::new(200, &format!("[{}]", announcement(1, "Welcome"))),
+        ])
+        .await;
+
+        let feed = Feed::from_canvas_api(&server.url("/courses/123"), "secret", &http_client())
+            .await
+            .unwrap();
+
+        assert_eq!(feed.title, "Course One");
+        assert_eq!(feed.id, server.url("/courses/123"));
+        let titles: Vec<&str> = feed
+            .announcements
+            .iter()
+            .map(|a| a.title.as_str())
+            .collect();
+        assert_eq!(titles, ["Exam info", "Welcome"]);
+        assert_eq!(feed.announcements[1].author_name(), "Jane Doe");
+
+        let received = server.received();
+        assert_eq!(received.len(), 3);
+        assert_eq!(received[0].path, "/api/v1/courses/123");
+        assert!(received[1]
+            .path
+            .starts_with("/api/v1/announcements?context_codes%5B%5D=course_123"));
+        assert_eq!(received[2].path, "/api/v1/announcements?page=2");
+        for request in &received {
+            assert_eq!(request.method, "GET");
+            assert!(request.body.is_empty());
+            assert_eq!(request.header("Authorization"), Some("Bearer secret"));
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_the_token_on_the_instance() {
+        let server = TestServer::start(vec![
+            Reply::new(200, COURSE),
+            Reply::new(200, &format!("[{}]", announcement(2, "Exam info"))).header(
+                "Link",
+                r#"<https://elsewhere.example.com/api/v1/announcements?page=2>; rel="next""#,
+            ),
+        ])
+        .await;
+
+        let err = Feed::from_canvas_api(&server.url("/courses/123"), "secret", &http_client())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, FeedError::InvalidFeedUrl(_)), "{err:?}");
+        assert_eq!(server.received().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn rejected_token() {
+        let server = TestServer::start(vec![Reply::new(
+            401,
+            r#"{"errors": [{"message": "Invalid access token."}]}"#,
+        )])
+        .await;
+
+        let err = Feed::from_canvas_api(&server.url("/courses/123"), "expired", &http_client())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, FeedError::Status(401)), "{err:?}");
+        assert_eq!(server.received().len(), 1);
+    }
+
+    #[test]
+    fn reads_the_next_link() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            LINK,
+            r#"<https://canvas.example.com/a?page=1>; rel="current", <https://canvas.example.com/a?page=2>; rel="next""#
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(
+            next_link(&headers).as_deref(),
+            Some("https://canvas.example.com/a?page=2")
+        );
+
+        headers.insert(
+            LINK,
+            r#"<https://canvas.example.com/a?page=2>; rel="last""#
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(next_link(&headers), None);
+    }
+}
@@ -1,15 +1,37 @@
-use diesel::{Insertable, QueryDsl, RunQueryDsl};
+use diesel::pg::upsert::excluded;
+use diesel::pg::Pg;
+use diesel::query_builder::QueryFragment;
+use diesel::query_dsl::LoadQuery;
+use diesel::{
+    BoolExpressionMethods, Connection, Insertable, JoinOnDsl, PgConnection, QueryDsl, RunQueryDsl,
+};
+use std::collections::HashMap;
 use std::time::SystemTime;
 
 use crate::diesel::ExpressionMethods;
-use crate::error::{DbError, MyError};
+use crate::digest::DeliveryMode;
+use crate::error::{DbError, MyError, SinkError};
+use crate::filter::Filter;
+use crate::mentions::Mentions;
+use crate::quiet::QuietHours;
+use crate::schema::announcements::dsl::announcements as db_announcements;
+use crate::schema::backup_feeds::dsl::backup_feeds as db_backup_feeds;
+use crate::schema::deliveries::dsl::deliveries as db_deliveries;
 use crate::schema::feeds::dsl::feeds as db_feeds;
 use crate::schema::subscriptions::dsl::subscriptions as db_subscriptions;
+use crate::sink::Sink;
 
-use crate::schema::{backup_feeds, feeds, subscriptions};
-use crate::Pool;
+use crate::schema::guild_settings::dsl::guild_settings as db_guild_settings;
+use crate::schema::{
+    announcements, backup_feeds, deliveries, feeds, guild_settings, subscriptions,
+};
+use crate::{HttpClient, Pool};
 
-use super::Feed;
+use super::canvas::{Announcement, Attachment, Author, Content, Edit, Link};
+use super::{Feed, Validators};
+
+/// Failed retrievals in a row after which a feed is no longer polled
+pub const MAX_FAILURES: i32 = 10;
 
 #[derive(Debug, Insertable)]
 #[table_name = "feeds"]
@@ -17,14 +39,49 @@ pub struct NewFeed<'a> {
     pub canvas_id: &'a str,
     pub url: &'a str,
     pub last_update: SystemTime,
+    pub title: &'a str,
+    pub format: &'a str,
 }
 
-#[derive(Debug, Queryable)]
+#[derive(Debug, Clone, Queryable)]
 pub struct DbFeed {
     pub id: i32,
     pub canvas_id: String,
     pub url: String,
     pub last_update: SystemTime,
+
+    /// Seconds between polls of this feed, `None` uses the scheduler default
+    pub poll_interval: Option<i32>,
+
+    /// Title of the feed the last time it was retrieved
+    pub title: String,
+
+    /// Error of the last failed retrieval
+    pub last_error: Option<String>,
+
+    /// When the last retrieval failed
+    pub last_error_at: Option<SystemTime>,
+
+    /// Failed retrievals in a row
+    pub failures: i32,
+
+    /// When the feed was last retrieved without error
+    pub last_success: Option<SystemTime>,
+
+    /// Http status of the last failed retrieval, `None` when there was no response
+    pub last_status: Option<i32>,
+
+    /// Set after `MAX_FAILURES` failures in a row, disabled feeds are not polled
+    pub disabled: bool,
+
+    /// Format detected when the feed was added, see `FeedFormat::as_str`
+    pub format: String,
+
+    /// `ETag` header of the last response that was processed
+    pub etag: Option<String>,
+
+    /// `Last-Modified` header of the last response that was processed
+    pub last_modified: Option<String>,
 }
 
 #[derive(Debug, Insertable)]
@@ -47,6 +104,8 @@ pub struct NewSubsription<'a> {
     pub server_id: &'a str,
     pub channel_id: &'a str,
     pub feed_id: i32,
+    pub edits: bool,
+    pub api_token: Option<&'a str>,
 }
 
 #[derive(Debug, Queryable)]
@@ -55,9 +114,140 @@ pub struct DbSubscription {
     pub server_id: String,
     pub channel_id: String,
     pub feed_id: i32,
+
+    /// Receive announcements again when they are edited
+    pub edits: bool,
+
+    /// Where the announcements go, see `Sink`
+    pub sink_type: String,
+
+    /// Json config of the sink, `None` for the bot
+    pub sink_config: Option<String>,
+
+    /// Json `Filter` of the announcements, `None` for all
+    pub filter: Option<String>,
+
+    /// Json `Mentions` pinged with the announcements, `None` for nobody
+    pub mentions: Option<String>,
+
+    /// Json `DeliveryMode`, `None` to send announcements right away
+    pub delivery_mode: Option<String>,
+
+    /// End of the window of the last digest handed to watchers
+    pub last_digest: Option<SystemTime>,
+
+    /// Why the sink refused the announcements, nothing is sent to it until it is set again
+    pub sink_error: Option<String>,
+
+    pub sink_error_at: Option<SystemTime>,
+
+    /// Token for the canvas api the subscriber added the feed with,
+    /// only for feeds in the `canvas_api` format
+    pub api_token: Option<String>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "announcements"]
+pub struct NewAnnouncement<'a> {
+    pub id: &'a str,
+    pub feed_id: i32,
+    pub title: &'a str,
+    pub author: &'a str,
+    pub link: &'a str,
+    pub content: &'a str,
+    pub published: SystemTime,
+    pub updated: SystemTime,
+    pub attachments: String,
+    pub sections: &'a [String],
+}
+
+#[derive(Debug, Queryable)]
+pub struct DbAnnouncement {
+    /// Atom entry id
+    pub id: String,
+    pub feed_id: i32,
+    pub title: String,
+    pub author: String,
+    pub link: String,
+    pub content: String,
+    pub published: SystemTime,
+    pub updated: SystemTime,
+
+    /// Title before the last edit
+    pub previous_title: Option<String>,
+
+    /// Content before the last edit
+    pub previous_content: Option<String>,
+
+    /// `Attachment`s as json
+    pub attachments: String,
+
+    /// Names of the sections the announcement is for
+    pub sections: Vec<String>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "deliveries"]
+pub struct NewDelivery<'a> {
+    pub subscription_id: i32,
+    pub feed_id: i32,
+    pub announcement_id: &'a str,
+    pub edited: bool,
+    pub queued: SystemTime,
+}
+
+#[derive(Debug, Queryable)]
+pub struct DbDelivery {
+    pub subscription_id: i32,
+    pub feed_id: i32,
+    pub announcement_id: String,
+
+    /// Deliver the announcement as an edit
+    pub edited: bool,
+
+    /// When the consumer acknowledged the delivery, `None` while pending
+    pub delivered: Option<SystemTime>,
+
+    /// When the announcement was found, a digest holds it until its window ends
+    pub queued: SystemTime,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "guild_settings"]
+pub struct NewGuildSettings<'a> {
+    pub server_id: &'a str,
+    pub timezone: &'a str,
+    pub quiet_start: Option<&'a str>,
+    pub quiet_end: Option<&'a str>,
+}
+
+/// Settings of a discord server for all its subscriptions
+#[derive(Debug, Clone, Queryable)]
+pub struct DbGuildSettings {
+    pub server_id: String,
+
+    /// Timezone of the quiet hours
+    pub timezone: String,
+
+    /// Start of the quiet hours (`HH:MM`), `None` without quiet hours
+    pub quiet_start: Option<String>,
+    pub quiet_end: Option<String>,
+
+    /// End of the quiet hours that hold announcements, cleared once they are released
+    pub held_until: Option<SystemTime>,
 }
 
 impl DbFeed {
+    pub fn get_by_id(search_id: i32, pool: &Pool) -> Result<Option<Self>, DbError> {
+        let conn = pool.get()?;
+
+        match db_feeds.find(search_id).get_result(&conn) {
+            Ok(f) => Ok(Some(f)),
+            Err(diesel::result::Error::NotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
     pub fn get_by_canvas_id(search_canvas_id: &str, pool: &Pool) -> Result<Option<Self>, DbError> {
         let conn = pool.get()?;
 
@@ -80,45 +270,195 @@ impl DbFeed {
             Err(e) => Err(e.into()),
         }
     }
+
+    /// Get all feeds that are not disabled
+    pub fn get_active(pool: &Pool) -> Result<Option<Vec<DbFeed>>, DbError> {
+        let conn = pool.get()?;
+
+        match db_feeds
+            .filter(feeds::disabled.eq(false))
+            .load::<DbFeed>(&conn)
+        {
+            Ok(v) => Ok(Some(v)),
+            Err(diesel::result::Error::NotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Record a successful retrieval, this also enables the feed again
+    pub fn record_success(id: i32, pool: &Pool) -> Result<(), DbError> {
+        let conn = pool.get()?;
+
+        diesel::update(db_feeds.find(id))
+            .set((
+                feeds::failures.eq(0),
+                feeds::last_success.eq(SystemTime::now()),
+                feeds::disabled.eq(false),
+            ))
+            .execute(&conn)?;
+
+        Ok(())
+    }
+
+    /// Record why retrieving the feed failed, disables the feed after `MAX_FAILURES` failures in a row
+    pub fn record_failure(
+        id: i32,
+        error: &str,
+        status: Option<u16>,
+        pool: &Pool,
+    ) -> Result<(), DbError> {
+        let conn = pool.get()?;
+
+        // The right hand sides see the values from before the update
+        diesel::update(db_feeds.find(id))
+            .set((
+                feeds::failures.eq(feeds::failures + 1),
+                feeds::last_error.eq(error),
+                feeds::last_error_at.eq(SystemTime::now()),
+                feeds::last_status.eq(status.map(i32::from)),
+                feeds::disabled.eq(feeds::failures.ge(MAX_FAILURES - 1)),
+            ))
+            .execute(&conn)?;
+
+        Ok(())
+    }
+
+    /// Validators to only get the feed again when it changed since the last processed response
+    pub fn validators(&self) -> Validators {
+        Validators {
+            etag: self.etag.clone(),
+            last_modified: self.last_modified.clone(),
+        }
+    }
+
+    /// Store the validators of the last processed response
+    pub fn set_validators(id: i32, validators: &Validators, pool: &Pool) -> Result<(), DbError> {
+        let conn = pool.get()?;
+
+        diesel::update(db_feeds.find(id))
+            .set((
+                feeds::etag.eq(&validators.etag),
+                feeds::last_modified.eq(&validators.last_modified),
+            ))
+            .execute(&conn)?;
+
+        Ok(())
+    }
+
+    /// Set how often the feed is polled, `None` resets it to the scheduler default
+    pub fn set_poll_interval(
+        search_canvas_id: &str,
+        interval: Option<i32>,
+        pool: &Pool,
+    ) -> Result<(), DbError> {
+        let conn = pool.get()?;
+
+        let rows = diesel::update(db_feeds.filter(feeds::canvas_id.eq(search_canvas_id)))
+            .set(feeds::poll_interval.eq(interval))
+            .execute(&conn)?;
+
+        if rows == 0 {
+            return Err(DbError::NotFound);
+        }
+
+        Ok(())
+    }
+}
+
+impl DbBackupFeed {
+    /// Store `url` as a backup of the feed, unless it is already known for the feed
+    pub fn add(feed_id: i32, url: &str, pool: &Pool) -> Result<(), DbError> {
+        let conn = pool.get()?;
+
+        let known: bool = diesel::select(diesel::dsl::exists(
+            db_feeds.find(feed_id).filter(feeds::url.eq(url)),
+        ))
+        .get_result(&conn)?;
+        let known = known
+            || diesel::select(diesel::dsl::exists(
+                db_backup_feeds
+                    .filter(backup_feeds::feed_id.eq(feed_id))
+                    .filter(backup_feeds::url.eq(url)),
+            ))
+            .get_result(&conn)?;
+
+        if !known {
+            // Nothing happens when the url is a backup of another feed
+            diesel::insert_into(backup_feeds::table)
+                .values(&NewBackupFeed { feed_id, url })
+                .on_conflict_do_nothing()
+                .execute(&conn)?;
+        }
+
+        Ok(())
+    }
+
+    /// Backups of the feed in the order they should be tried
+    pub fn get_by_feed_id(feed_id: i32, pool: &Pool) -> Result<Vec<Self>, DbError> {
+        let conn = pool.get()?;
+
+        Ok(db_backup_feeds
+            .filter(backup_feeds::feed_id.eq(feed_id))
+            .order(backup_feeds::id)
+            .load(&conn)?)
+    }
+
+    /// Make the backup the primary url of its feed, the old primary url becomes the last backup
+    pub fn promote(&self, pool: &Pool) -> Result<(), DbError> {
+        let conn = pool.get()?;
+
+        Ok(conn.transaction::<_, diesel::result::Error, _>(|| {
+            let primary: String = db_feeds
+                .find(self.feed_id)
+                .select(feeds::url)
+                .get_result(&conn)?;
+
+            diesel::update(db_feeds.find(self.feed_id))
+                .set(feeds::url.eq(&self.url))
+                .execute(&conn)?;
+            diesel::delete(db_backup_feeds.find(self.id)).execute(&conn)?;
+            diesel::insert_into(backup_feeds::table)
+                .values(&NewBackupFeed {
+                    feed_id: self.feed_id,
+                    url: &primary,
+                })
+                .execute(&conn)?;
+
+            Ok(())
+        })?)
+    }
 }
 
 impl DbSubscription {
     /// Add Feed to the db and returns its title
+    ///
+    /// With `api_token` `url` is a canvas course read through the canvas api
     pub async fn add(
         server_id: &str,
         channel_id: &str,
         url: &str,
+        edits: bool,
+        api_token: Option<&str>,
+        http: &HttpClient,
         pool: &Pool,
     ) -> Result<String, MyError> {
-        // TODO: Ged rid of this FeedError
-        // Get feed canvas_id
-        let feed = Feed::from_url(url).await?;
-
-        let conn = pool.get()?;
-
-        let tmp: Result<i32, _> = db_feeds
-            .filter(feeds::canvas_id.eq(&feed.id))
-            .select(feeds::id)
-            .get_result(&conn);
-
-        let feed_id = if let Ok(id) = tmp {
-            id
-        } else if Err(diesel::result::Error::NotFound) == tmp {
-            // Add feed to db
-            Feed::add(url, pool).await?;
-            // Get the pk of new entry
-            db_feeds
-                .filter(feeds::canvas_id.eq(&feed.id))
-                .select(feeds::id)
-                .get_result(&conn)?
-        } else {
-            tmp?
+        let feed = match api_token {
+            Some(token) => Feed::from_canvas_api(url, token, http).await?,
+            None => Feed::from_url(url, http).await?,
         };
+        let feed_id = feed.store(url, pool)?;
+
+        // The feed was just retrieved, poll it again when it was disabled
+        DbFeed::record_success(feed_id, pool)?;
+
+        let conn = pool.get()?;
 
         let new_subscription = NewSubsription {
             server_id,
             channel_id,
             feed_id,
+            edits,
+            api_token,
         };
 
         // Insert subscription
@@ -137,6 +477,355 @@ impl DbSubscription {
         Ok(feed.title)
     }
 
+    /// Find the subscription of a channel to the feed with primary or backup url `url`
+    pub fn find(
+        server_id: &str,
+        channel_id: &str,
+        url: &str,
+        pool: &Pool,
+    ) -> Result<Option<Self>, DbError> {
+        let conn = pool.get()?;
+
+        match db_subscriptions
+            .inner_join(db_feeds)
+            .filter(subscriptions::server_id.eq(server_id))
+            .filter(subscriptions::channel_id.eq(channel_id))
+            .filter(
+                feeds::url.eq(url).or(feeds::id.eq_any(
+                    db_backup_feeds
+                        .filter(backup_feeds::url.eq(url))
+                        .select(backup_feeds::feed_id),
+                )),
+            )
+            .select(subscriptions::all_columns)
+            .get_result(&conn)
+        {
+            Ok(s) => Ok(Some(s)),
+            Err(diesel::result::Error::NotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Remove the subscription of a channel to the feed with url `url`
+    ///
+    /// The feed, its backups and its announcements are removed with the last subscription,
+    /// returns `true` when that happened
+    pub fn remove(
+        server_id: &str,
+        channel_id: &str,
+        url: &str,
+        pool: &Pool,
+    ) -> Result<bool, DbError> {
+        let subscription = match Self::find(server_id, channel_id, url, pool)? {
+            Some(subscription) => subscription,
+            None => return Err(DbError::NotFound),
+        };
+
+        let conn = pool.get()?;
+
+        Ok(conn.transaction::<_, diesel::result::Error, _>(|| {
+            diesel::delete(db_deliveries.filter(deliveries::subscription_id.eq(subscription.id)))
+                .execute(&conn)?;
+            diesel::delete(db_subscriptions.find(subscription.id)).execute(&conn)?;
+
+            let subscribed: bool = diesel::select(diesel::dsl::exists(
+                db_subscriptions.filter(subscriptions::feed_id.eq(subscription.feed_id)),
+            ))
+            .get_result(&conn)?;
+            if subscribed {
+                return Ok(false);
+            }
+
+            // Nobody is subscribed to the feed anymore
+            diesel::delete(
+                db_announcements.filter(announcements::feed_id.eq(subscription.feed_id)),
+            )
+            .execute(&conn)?;
+            diesel::delete(db_backup_feeds.filter(backup_feeds::feed_id.eq(subscription.feed_id)))
+                .execute(&conn)?;
+            diesel::delete(db_feeds.find(subscription.feed_id)).execute(&conn)?;
+
+            Ok(true)
+        })?)
+    }
+
+    /// Choose whether the channel receives edited announcements of the feed with url `url`
+    pub fn set_edits(
+        server_id: &str,
+        channel_id: &str,
+        url: &str,
+        edits: bool,
+        pool: &Pool,
+    ) -> Result<(), DbError> {
+        let subscription = match Self::find(server_id, channel_id, url, pool)? {
+            Some(subscription) => subscription,
+            None => return Err(DbError::NotFound),
+        };
+
+        let conn = pool.get()?;
+
+        diesel::update(db_subscriptions.find(subscription.id))
+            .set(subscriptions::edits.eq(edits))
+            .execute(&conn)?;
+
+        Ok(())
+    }
+
+    /// Send the announcements of the subscription to `sink`, `Sink::Bot` leaves them to the bot
+    pub fn set_sink(
+        server_id: &str,
+        channel_id: &str,
+        url: &str,
+        sink: &Sink,
+        pool: &Pool,
+    ) -> Result<(), DbError> {
+        let subscription = match Self::find(server_id, channel_id, url, pool)? {
+            Some(subscription) => subscription,
+            None => return Err(DbError::NotFound),
+        };
+
+        let (sink_type, sink_config) = sink.to_db();
+        let conn = pool.get()?;
+
+        diesel::update(db_subscriptions.find(subscription.id))
+            .set((
+                subscriptions::sink_type.eq(sink_type),
+                subscriptions::sink_config.eq(sink_config),
+                subscriptions::sink_error.eq(None::<String>),
+                subscriptions::sink_error_at.eq(None::<SystemTime>),
+            ))
+            .execute(&conn)?;
+
+        Ok(())
+    }
+
+    pub fn sink(&self) -> Result<Sink, SinkError> {
+        Sink::from_db(&self.sink_type, self.sink_config.as_deref())
+    }
+
+    /// Stop sending to the sink of a subscription that is gone, until the sink is set again
+    pub fn disable_sink(subscription_id: i32, error: &str, pool: &Pool) -> Result<(), DbError> {
+        let conn = pool.get()?;
+
+        diesel::update(db_subscriptions.find(subscription_id))
+            .set((
+                subscriptions::sink_error.eq(error),
+                subscriptions::sink_error_at.eq(SystemTime::now()),
+            ))
+            .execute(&conn)?;
+
+        Ok(())
+    }
+
+    /// Does the bot deliver the announcements, the server sends them to other sinks
+    pub fn delivered_by_bot(&self) -> bool {
+        self.sink_type == Sink::Bot.as_str()
+    }
+
+    /// Only send the announcements that pass `filter`, an empty filter sends all of them
+    pub fn set_filter(
+        server_id: &str,
+        channel_id: &str,
+        url: &str,
+        filter: &Filter,
+        pool: &Pool,
+    ) -> Result<(), DbError> {
+        let subscription = match Self::find(server_id, channel_id, url, pool)? {
+            Some(subscription) => subscription,
+            None => return Err(DbError::NotFound),
+        };
+
+        let conn = pool.get()?;
+
+        diesel::update(db_subscriptions.find(subscription.id))
+            .set(subscriptions::filter.eq(filter.to_db()))
+            .execute(&conn)?;
+
+        Ok(())
+    }
+
+    /// The filter of the subscription, an error when the stored filter can not be read
+    pub fn filter(&self) -> Result<Filter, serde_json::Error> {
+        Filter::from_db(self.filter.as_deref())
+    }
+
+    /// Indices of the announcements this subscription receives, the filter is read once for all
+    ///
+    /// A filter that can not be used lets everything through, rather than losing announcements.
+    pub fn wanted(&self, announcements: &[Announcement]) -> Vec<usize> {
+        let filter = self.filter().unwrap_or_else(|err| {
+            eprintln!(
+                "Subscription {}: unreadable filter, ignored: {err}",
+                self.id
+            );
+            Filter::default()
+        });
+        let matcher = filter.matcher();
+        if matcher.is_none() {
+            eprintln!("Subscription {}: invalid filter regex, ignored", self.id);
+        }
+
+        announcements
+            .iter()
+            .enumerate()
+            .filter(|(_, announcement)| announcement.edit.is_none() || self.edits)
+            .filter(|(_, announcement)| {
+                matcher
+                    .as_ref()
+                    .is_none_or(|matcher| matcher.matches(announcement))
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Change who is pinged with the announcements of a subscription
+    pub fn set_mentions(
+        server_id: &str,
+        channel_id: &str,
+        url: &str,
+        mentions: &Mentions,
+        pool: &Pool,
+    ) -> Result<(), DbError> {
+        let subscription = match Self::find(server_id, channel_id, url, pool)? {
+            Some(subscription) => subscription,
+            None => return Err(DbError::NotFound),
+        };
+
+        let conn = pool.get()?;
+
+        diesel::update(db_subscriptions.find(subscription.id))
+            .set(subscriptions::mentions.eq(mentions.to_db()))
+            .execute(&conn)?;
+
+        Ok(())
+    }
+
+    /// The mentions of the subscription, ones that can not be read ping nobody
+    pub fn mentions(&self) -> Mentions {
+        Mentions::from_db(self.mentions.as_deref()).unwrap_or_default()
+    }
+
+    /// Ids of the `announcements` that ping the mentions of the subscription
+    pub fn pings(&self, announcements: &[Announcement]) -> Vec<String> {
+        let mentions = self.mentions();
+        if mentions.is_empty() {
+            return Vec::new();
+        }
+
+        let filter = match mentions.only_keywords {
+            true => match self.filter() {
+                Ok(filter) => Some(filter),
+                Err(_) => return Vec::new(),
+            },
+            false => None,
+        };
+
+        announcements
+            .iter()
+            .filter(|announcement| {
+                filter
+                    .as_ref()
+                    .is_none_or(|filter| !filter.matched_keywords(announcement).is_empty())
+            })
+            .map(|announcement| announcement.id.clone())
+            .collect()
+    }
+
+    /// Change when the announcements of a subscription are sent
+    pub fn set_delivery_mode(
+        server_id: &str,
+        channel_id: &str,
+        url: &str,
+        delivery_mode: &DeliveryMode,
+        pool: &Pool,
+    ) -> Result<(), DbError> {
+        let subscription = match Self::find(server_id, channel_id, url, pool)? {
+            Some(subscription) => subscription,
+            None => return Err(DbError::NotFound),
+        };
+
+        let conn = pool.get()?;
+
+        diesel::update(db_subscriptions.find(subscription.id))
+            .set(subscriptions::delivery_mode.eq(delivery_mode.to_db()))
+            .execute(&conn)?;
+
+        Ok(())
+    }
+
+    /// The delivery mode of the subscription, one that can not be read sends right away
+    pub fn delivery_mode(&self) -> DeliveryMode {
+        DeliveryMode::from_db(self.delivery_mode.as_deref()).unwrap_or_default()
+    }
+
+    /// End of the last digest window at `now`, `None` when announcements are sent right away
+    pub fn digest_end(&self, now: SystemTime) -> Option<SystemTime> {
+        self.delivery_mode().window_end(now)
+    }
+
+    /// Remember that the digest of the window ending at `window_end` was handed out
+    pub fn set_last_digest(
+        subscription_id: i32,
+        window_end: SystemTime,
+        pool: &Pool,
+    ) -> Result<(), DbError> {
+        let conn = pool.get()?;
+
+        diesel::update(db_subscriptions.find(subscription_id))
+            .set(subscriptions::last_digest.eq(window_end))
+            .execute(&conn)?;
+
+        Ok(())
+    }
+
+    /// Subscriptions with their feed, optionally only those of a server and/or channel
+    pub fn list(
+        server_id: Option<&str>,
+        channel_id: Option<&str>,
+        pool: &Pool,
+    ) -> Result<Vec<(Self, DbFeed)>, DbError> {
+        let conn = pool.get()?;
+
+        Ok(Self::list_query(server_id, channel_id).load(&conn)?)
+    }
+
+    /// Query of `DbSubscription::list`, `None` leaves out the filter on that column
+    fn list_query<'a>(
+        server_id: Option<&'a str>,
+        channel_id: Option<&'a str>,
+    ) -> impl LoadQuery<PgConnection, (Self, DbFeed)> + QueryFragment<Pg> + 'a {
+        let mut query = db_subscriptions
+            .inner_join(db_feeds)
+            .order((
+                subscriptions::server_id,
+                subscriptions::channel_id,
+                feeds::title,
+            ))
+            .into_boxed();
+        if let Some(server_id) = server_id {
+            query = query.filter(subscriptions::server_id.eq(server_id));
+        }
+        if let Some(channel_id) = channel_id {
+            query = query.filter(subscriptions::channel_id.eq(channel_id));
+        }
+
+        query
+    }
+
+    /// Tokens for the canvas api of the subscriptions to the feed, oldest subscription first
+    pub fn api_tokens(feed_id: i32, pool: &Pool) -> Result<Vec<String>, DbError> {
+        let conn = pool.get()?;
+
+        let tokens: Vec<Option<String>> = db_subscriptions
+            .filter(subscriptions::feed_id.eq(feed_id))
+            .filter(subscriptions::api_token.is_not_null())
+            .order(subscriptions::id)
+            .select(subscriptions::api_token)
+            .load(&conn)?;
+
+        Ok(tokens.into_iter().flatten().collect())
+    }
+
     pub fn get_by_feed_id(feed_id: i32, pool: &Pool) -> Result<Option<Vec<Self>>, DbError> {
         let conn = pool.get()?;
 
@@ -150,3 +839,492 @@ impl DbSubscription {
         }
     }
 }
+
+impl DbAnnouncement {
+    /// Store the announcements of a feed and returns the ids of the ones that were not stored yet
+    pub fn add_all(
+        feed_id: i32,
+        announcements: &[Announcement],
+        pool: &Pool,
+    ) -> Result<Vec<String>, DbError> {
+        let conn = pool.get()?;
+
+        let new_announcements: Vec<_> = announcements
+            .iter()
+            .map(|announcement| NewAnnouncement {
+                id: &announcement.id,
+                feed_id,
+                title: &announcement.title,
+                author: announcement.author_name(),
+                link: announcement.href(),
+                content: &announcement.content.content,
+                published: announcement.published,
+                updated: announcement.updated,
+                attachments: attachments_json(&announcement.attachments),
+                sections: &announcement.sections,
+            })
+            .collect();
+
+        // Only returns the ids of the inserted rows
+        Ok(diesel::insert_into(announcements::table)
+            .values(&new_announcements)
+            .on_conflict_do_nothing()
+            .returning(announcements::id)
+            .get_results(&conn)?)
+    }
+
+    /// Store the new version of announcements of a feed that were edited since they were stored
+    ///
+    /// Sets `edit` with the previous version on the edited announcements
+    pub fn update_edited(
+        feed_id: i32,
+        announcements: &mut [Announcement],
+        pool: &Pool,
+    ) -> Result<(), DbError> {
+        let conn = pool.get()?;
+
+        let ids: Vec<&str> = announcements.iter().map(|a| a.id.as_str()).collect();
+        let stored: Vec<DbAnnouncement> = db_announcements
+            .filter(announcements::feed_id.eq(feed_id))
+            .filter(announcements::id.eq_any(ids))
+            .load(&conn)?;
+
+        for announcement in announcements.iter_mut() {
+            let previous = match stored.iter().find(|s| s.id == announcement.id) {
+                Some(previous) if announcement.updated > previous.updated => previous,
+                _ => continue,
+            };
+
+            diesel::update(db_announcements.find((feed_id, &announcement.id)))
+                .set((
+                    announcements::title.eq(&announcement.title),
+                    announcements::author.eq(announcement.author_name()),
+                    announcements::link.eq(announcement.href()),
+                    announcements::content.eq(&announcement.content.content),
+                    announcements::updated.eq(announcement.updated),
+                    announcements::attachments.eq(attachments_json(&announcement.attachments)),
+                    announcements::sections.eq(&announcement.sections),
+                    announcements::previous_title.eq(&previous.title),
+                    announcements::previous_content.eq(&previous.content),
+                ))
+                .execute(&conn)?;
+
+            announcement.edit = Some(Edit {
+                title: previous.title.clone(),
+                content: previous.content.clone(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// `published` and `updated` of the stored announcements of a feed among `ids`
+    pub fn dates(
+        feed_id: i32,
+        ids: &[&str],
+        pool: &Pool,
+    ) -> Result<HashMap<String, (SystemTime, SystemTime)>, DbError> {
+        let conn = pool.get()?;
+
+        let dates: Vec<(String, SystemTime, SystemTime)> = db_announcements
+            .filter(announcements::feed_id.eq(feed_id))
+            .filter(announcements::id.eq_any(ids))
+            .select((
+                announcements::id,
+                announcements::published,
+                announcements::updated,
+            ))
+            .load(&conn)?;
+
+        Ok(dates
+            .into_iter()
+            .map(|(id, published, updated)| (id, (published, updated)))
+            .collect())
+    }
+
+    /// Announcements of a feed published after `after`, oldest first
+    pub fn get_by_feed_id(
+        feed_id: i32,
+        after: SystemTime,
+        pool: &Pool,
+    ) -> Result<Vec<Self>, DbError> {
+        let conn = pool.get()?;
+
+        Ok(db_announcements
+            .filter(announcements::feed_id.eq(feed_id))
+            .filter(announcements::published.gt(after))
+            .order(announcements::published.asc())
+            .load(&conn)?)
+    }
+
+    /// Does the feed have any stored announcements
+    pub fn exists_for_feed(feed_id: i32, pool: &Pool) -> Result<bool, DbError> {
+        let conn = pool.get()?;
+
+        Ok(diesel::select(diesel::dsl::exists(
+            db_announcements.filter(announcements::feed_id.eq(feed_id)),
+        ))
+        .get_result(&conn)?)
+    }
+}
+
+fn attachments_json(attachments: &[Attachment]) -> String {
+    serde_json::to_string(attachments).unwrap_or_else(|_| String::from("[]"))
+}
+
+impl From<DbAnnouncement> for Announcement {
+    fn from(announcement: DbAnnouncement) -> Self {
+        Self {
+            title: announcement.title,
+            id: announcement.id,
+            updated: announcement.updated,
+            published: announcement.published,
+            links: vec![Link {
+                rel: String::from("alternate"),
+                href: announcement.link,
+            }],
+            author: Some(Author {
+                name: announcement.author,
+            }),
+            content: Content {
+                content_type: String::from("html"),
+                content: announcement.content,
+            },
+            // Stored by `attachments_json`, a broken value only loses the attachments
+            attachments: serde_json::from_str(&announcement.attachments).unwrap_or_default(),
+            sections: announcement.sections,
+            edit: None,
+        }
+    }
+}
+
+impl DbDelivery {
+    /// Queue the announcements for every subscription,
+    /// with the indices of the ones it wants from `DbSubscription::wanted`
+    ///
+    /// Edited announcements are queued again, even if they were delivered before
+    pub fn add_all(
+        subscriptions: &[(&DbSubscription, Vec<usize>)],
+        announcements: &[Announcement],
+        pool: &Pool,
+    ) -> Result<(), DbError> {
+        let queued = SystemTime::now();
+        let new_deliveries: Vec<_> = subscriptions
+            .iter()
+            .flat_map(|(subscription, wanted)| {
+                wanted
+                    .iter()
+                    .map(|&i| &announcements[i])
+                    .map(|announcement| NewDelivery {
+                        subscription_id: subscription.id,
+                        feed_id: subscription.feed_id,
+                        announcement_id: &announcement.id,
+                        edited: announcement.edit.is_some(),
+                        queued,
+                    })
+            })
+            .collect();
+
+        if new_deliveries.is_empty() {
+            return Ok(());
+        }
+
+        let conn = pool.get()?;
+
+        diesel::insert_into(deliveries::table)
+            .values(&new_deliveries)
+            .on_conflict((
+                deliveries::subscription_id,
+                deliveries::feed_id,
+                deliveries::announcement_id,
+            ))
+            .do_update()
+            .set((
+                deliveries::edited.eq(excluded(deliveries::edited)),
+                deliveries::delivered.eq(None::<SystemTime>),
+                deliveries::queued.eq(excluded(deliveries::queued)),
+            ))
+            .execute(&conn)?;
+
+        Ok(())
+    }
+
+    /// Mark announcements of the feed with canvas id `feed` as delivered to a channel,
+    /// returns the number of acknowledged deliveries
+    pub fn ack(
+        server_id: &str,
+        channel_id: &str,
+        feed: &str,
+        announcement_ids: &[String],
+        pool: &Pool,
+    ) -> Result<usize, DbError> {
+        let conn = pool.get()?;
+
+        let subscription_ids = db_subscriptions
+            .inner_join(db_feeds)
+            .filter(subscriptions::server_id.eq(server_id))
+            .filter(subscriptions::channel_id.eq(channel_id))
+            .filter(feeds::canvas_id.eq(feed))
+            .select(subscriptions::id);
+
+        Ok(diesel::update(
+            db_deliveries
+                .filter(deliveries::subscription_id.eq_any(subscription_ids))
+                .filter(deliveries::announcement_id.eq_any(announcement_ids))
+                .filter(deliveries::delivered.is_null()),
+        )
+        .set(deliveries::delivered.eq(SystemTime::now()))
+        .execute(&conn)?)
+    }
+
+    /// Mark the delivery of an announcement to a single subscription as done
+    pub fn ack_subscription(
+        subscription_id: i32,
+        announcement_id: &str,
+        pool: &Pool,
+    ) -> Result<usize, DbError> {
+        let conn = pool.get()?;
+
+        Ok(diesel::update(
+            db_deliveries
+                .filter(deliveries::subscription_id.eq(subscription_id))
+                .filter(deliveries::announcement_id.eq(announcement_id))
+                .filter(deliveries::delivered.is_null()),
+        )
+        .set(deliveries::delivered.eq(SystemTime::now()))
+        .execute(&conn)?)
+    }
+
+    /// All deliveries that were not acknowledged yet,
+    /// with their subscription, announcement and the canvas id of the feed
+    pub fn get_pending(
+        pool: &Pool,
+    ) -> Result<Vec<(Self, DbSubscription, DbAnnouncement, String)>, DbError> {
+        let conn = pool.get()?;
+
+        Ok(db_deliveries
+            .inner_join(db_subscriptions)
+            .inner_join(
+                db_announcements.on(announcements::feed_id
+                    .eq(deliveries::feed_id)
+                    .and(announcements::id.eq(deliveries::announcement_id))),
+            )
+            .inner_join(db_feeds.on(feeds::id.eq(deliveries::feed_id)))
+            .filter(deliveries::delivered.is_null())
+            .order((deliveries::feed_id, announcements::published.asc()))
+            .select((
+                deliveries::all_columns,
+                subscriptions::all_columns,
+                announcements::all_columns,
+                feeds::canvas_id,
+            ))
+            .load(&conn)?)
+    }
+}
+
+impl DbGuildSettings {
+    pub fn get(server_id: &str, pool: &Pool) -> Result<Option<Self>, DbError> {
+        let conn = pool.get()?;
+
+        match db_guild_settings.find(server_id).get_result(&conn) {
+            Ok(settings) => Ok(Some(settings)),
+            Err(diesel::result::Error::NotFound) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn get_all(pool: &Pool) -> Result<Vec<Self>, DbError> {
+        let conn = pool.get()?;
+
+        Ok(db_guild_settings.load(&conn)?)
+    }
+
+    /// Change the quiet hours of a server, `None` turns them off
+    pub fn set_quiet_hours(
+        server_id: &str,
+        quiet_hours: Option<&QuietHours>,
+        pool: &Pool,
+    ) -> Result<(), DbError> {
+        let new_settings = NewGuildSettings {
+            server_id,
+            timezone: quiet_hours.map_or("UTC", |quiet| quiet.timezone.as_str()),
+            quiet_start: quiet_hours.map(|quiet| quiet.start.as_str()),
+            quiet_end: quiet_hours.map(|quiet| quiet.end.as_str()),
+        };
+
+        let conn = pool.get()?;
+
+        diesel::insert_into(guild_settings::table)
+            .values(&new_settings)
+            .on_conflict(guild_settings::server_id)
+            .do_update()
+            .set((
+                guild_settings::timezone.eq(excluded(guild_settings::timezone)),
+                guild_settings::quiet_start.eq(excluded(guild_settings::quiet_start)),
+                guild_settings::quiet_end.eq(excluded(guild_settings::quiet_end)),
+            ))
+            .execute(&conn)?;
+
+        Ok(())
+    }
+
+    pub fn quiet_hours(&self) -> Option<QuietHours> {
+        Some(QuietHours {
+            start: self.quiet_start.clone()?,
+            end: self.quiet_end.clone()?,
+            timezone: self.timezone.clone(),
+        })
+    }
+
+    /// End of the quiet hours of the server when `now` is in them
+    pub fn quiet_until(&self, now: SystemTime) -> Option<SystemTime> {
+        self.quiet_hours()?.until(now)
+    }
+
+    /// Servers in their quiet hours at `now`, with the end of the quiet hours
+    pub fn quiet_at(now: SystemTime, pool: &Pool) -> Result<HashMap<String, SystemTime>, DbError> {
+        Ok(Self::get_all(pool)?
+            .into_iter()
+            .filter_map(|settings| {
+                let until = settings.quiet_until(now)?;
+                Some((settings.server_id, until))
+            })
+            .collect())
+    }
+
+    /// Remember that announcements of a server are held until `until`
+    pub fn hold(server_id: &str, until: SystemTime, pool: &Pool) -> Result<(), DbError> {
+        let conn = pool.get()?;
+
+        diesel::update(db_guild_settings.find(server_id))
+            .set(guild_settings::held_until.eq(until))
+            .execute(&conn)?;
+
+        Ok(())
+    }
+
+    /// Servers holding announcements that are no longer in their quiet hours at `now`
+    pub fn released_at(now: SystemTime, pool: &Pool) -> Result<Vec<String>, DbError> {
+        Ok(Self::get_all(pool)?
+            .into_iter()
+            .filter(|settings| settings.held_until.is_some() && settings.quiet_until(now).is_none())
+            .map(|settings| settings.server_id)
+            .collect())
+    }
+
+    /// Forget the held announcements of the servers, they were handed out
+    pub fn release(server_ids: &[String], pool: &Pool) -> Result<(), DbError> {
+        let conn = pool.get()?;
+
+        diesel::update(db_guild_settings.filter(guild_settings::server_id.eq_any(server_ids)))
+            .set(guild_settings::held_until.eq(None::<SystemTime>))
+            .execute(&conn)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::debug_query;
+
+    fn subscription(filter: Option<&str>) -> DbSubscription {
+        DbSubscription {
+            id: 1,
+            server_id: String::from("123"),
+            channel_id: String::from("456"),
+            feed_id: 1,
+            edits: true,
+            sink_type: String::from("bot"),
+            sink_config: None,
+            filter: filter.map(String::from),
+            mentions: None,
+            delivery_mode: None,
+            last_digest: None,
+            sink_error: None,
+            sink_error_at: None,
+            api_token: None,
+        }
+    }
+
+    fn announcements() -> Vec<Announcement> {
+        vec![
+            crate::test_server::announcement("Exam info", "<p>Exam on Monday</p>"),
+            crate::test_server::announcement("Party", "<p>Drinks on Friday</p>"),
+        ]
+    }
+
+    #[test]
+    fn wanted_by_the_filter() {
+        let filter = Filter {
+            include: vec![String::from("exam")],
+            ..Default::default()
+        };
+        let subscription = subscription(filter.to_db().as_deref());
+
+        assert_eq!(subscription.wanted(&announcements()), [0]);
+    }
+
+    #[test]
+    fn unreadable_filter_wants_everything() {
+        let subscription = subscription(Some("{not json"));
+
+        assert_eq!(subscription.wanted(&announcements()), [0, 1]);
+    }
+
+    #[test]
+    fn invalid_regex_wants_everything() {
+        let subscription = subscription(Some(r#"{"title_regex": "(unclosed"}"#));
+
+        assert_eq!(subscription.wanted(&announcements()), [0, 1]);
+    }
+
+    fn list_sql(server_id: Option<&str>, channel_id: Option<&str>) -> String {
+        debug_query::<Pg, _>(&DbSubscription::list_query(server_id, channel_id)).to_string()
+    }
+
+    #[test]
+    fn list_everything() {
+        let sql = list_sql(None, None);
+
+        assert!(!sql.contains("WHERE"), "{sql}");
+        assert!(sql.contains("binds: []"), "{sql}");
+    }
+
+    #[test]
+    fn list_of_a_server() {
+        let sql = list_sql(Some("123"), None);
+
+        assert!(
+            sql.contains(r#"WHERE "subscriptions"."server_id" = $1"#),
+            "{sql}"
+        );
+        assert!(!sql.contains(r#""subscriptions"."channel_id" = "#), "{sql}");
+        assert!(sql.contains(r#"binds: ["123"]"#), "{sql}");
+    }
+
+    #[test]
+    fn list_of_a_channel() {
+        let sql = list_sql(None, Some("456"));
+
+        assert!(
+            sql.contains(r#"WHERE "subscriptions"."channel_id" = $1"#),
+            "{sql}"
+        );
+        assert!(!sql.contains(r#""subscriptions"."server_id" = "#), "{sql}");
+        assert!(sql.contains(r#"binds: ["456"]"#), "{sql}");
+    }
+
+    #[test]
+    fn list_of_a_channel_in_a_server() {
+        let sql = list_sql(Some("123"), Some("456"));
+
+        assert!(sql.contains(r#""subscriptions"."server_id" = $1"#), "{sql}");
+        assert!(
+            sql.contains(r#""subscriptions"."channel_id" = $2"#),
+            "{sql}"
+        );
+        assert!(sql.contains(r#"binds: ["123", "456"]"#), "{sql}");
+    }
+}
@@ -0,0 +1,226 @@
+use std::time::SystemTime;
+
+use crate::error::DbError;
+use crate::sink::Sink;
+use crate::Pool;
+
+use super::canvas::{Announcement, Edit};
+use super::db::{DbDelivery, DbGuildSettings, DbSubscription};
+use super::Channel;
+
+/// Announcements of a feed that still have to be delivered to channels
+#[derive(Debug, Clone)]
+pub struct PendingDelivery {
+    /// Same as `Feed::id`
+    pub feed_id: String,
+
+    pub announcements: Vec<Announcement>,
+
+    pub channels: Vec<Channel>,
+
+    /// End of the window when the announcements are sent as one digest
+    pub digest: Option<SystemTime>,
+}
+
+/// Announcements that still have to be sent to the sink of a subscription
+#[derive(Debug, Clone)]
+pub struct PendingSink {
+    pub subscription_id: i32,
+    pub sink: Sink,
+    pub channel: Channel,
+
+    pub announcements: Vec<Announcement>,
+
+    /// End of the window when the announcements are sent as one digest
+    pub digest: Option<SystemTime>,
+}
+
+/// Pending announcements of a subscription
+struct Queued {
+    /// Canvas id of the feed
+    feed_id: String,
+
+    subscription: DbSubscription,
+    announcements: Vec<Announcement>,
+
+    /// End of the last digest window, `None` when sent right away
+    digest: Option<SystemTime>,
+}
+
+impl PendingDelivery {
+    /// All deliveries that were not acknowledged yet,
+    /// one per group of channels waiting for the same announcements of a feed
+    ///
+    /// Subscriptions with another sink than the bot are left out, the server sends those.
+    /// Digests only have the announcements of windows that ended,
+    /// servers in their quiet hours wait until those end.
+    pub fn get_all(pool: &Pool) -> Result<Vec<Self>, DbError> {
+        Ok(group(
+            per_subscription(SystemTime::now(), pool)?
+                .into_iter()
+                .filter(|queued| queued.subscription.delivered_by_bot()),
+        ))
+    }
+
+    /// Digests of the windows that ended since they were last taken, every window is taken once
+    ///
+    /// They stay pending until they are acknowledged, `get_all` keeps returning them.
+    pub fn take_digests(pool: &Pool) -> Result<Vec<Self>, DbError> {
+        let mut digests = Vec::new();
+        for queued in per_subscription(SystemTime::now(), pool)? {
+            let end = match queued.digest {
+                Some(end) if queued.subscription.delivered_by_bot() => end,
+                _ => continue,
+            };
+            if queued
+                .subscription
+                .last_digest
+                .is_some_and(|last| last >= end)
+            {
+                continue;
+            }
+
+            DbSubscription::set_last_digest(queued.subscription.id, end, pool)?;
+            digests.push(queued);
+        }
+
+        Ok(group(digests.into_iter()))
+    }
+
+    /// Deliveries held by quiet hours that ended, every server is released once
+    ///
+    /// Digests are left out, `take_digests` hands those out.
+    pub fn take_released(pool: &Pool) -> Result<Vec<Self>, DbError> {
+        let now = SystemTime::now();
+        let released = DbGuildSettings::released_at(now, pool)?;
+        if released.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let pending = group(per_subscription(now, pool)?.into_iter().filter(|queued| {
+            queued.subscription.delivered_by_bot()
+                && queued.digest.is_none()
+                && released.contains(&queued.subscription.server_id)
+        }));
+        DbGuildSettings::release(&released, pool)?;
+
+        Ok(pending)
+    }
+}
+
+impl PendingSink {
+    /// All deliveries to other sinks than the bot that were not acknowledged yet,
+    /// one per subscription
+    ///
+    /// Subscriptions with an invalid sink or a sink that is gone are left out.
+    pub fn get_all(pool: &Pool) -> Result<Vec<Self>, DbError> {
+        Ok(per_subscription(SystemTime::now(), pool)?
+            .into_iter()
+            .filter(|queued| !queued.subscription.delivered_by_bot())
+            .filter(|queued| queued.subscription.sink_error.is_none())
+            .filter_map(|queued| {
+                let subscription = queued.subscription;
+                let sink = match subscription.sink() {
+                    Ok(sink) => sink,
+                    Err(err) => {
+                        eprintln!("Subscription {}: {err}", subscription.id);
+                        return None;
+                    }
+                };
+
+                Some(Self {
+                    subscription_id: subscription.id,
+                    sink,
+                    channel: Channel::for_subscription(&subscription, &queued.announcements),
+                    announcements: queued.announcements,
+                    digest: queued.digest,
+                })
+            })
+            .collect())
+    }
+}
+
+/// Pending announcements per subscription, in the order of the query
+///
+/// Digests leave out the announcements queued after the end of their last window at `now`,
+/// servers in their quiet hours are left out entirely.
+fn per_subscription(now: SystemTime, pool: &Pool) -> Result<Vec<Queued>, DbError> {
+    let quiet = DbGuildSettings::quiet_at(now, pool)?;
+
+    let mut per_subscription: Vec<Queued> = Vec::new();
+    for (delivery, subscription, db_announcement, canvas_id) in DbDelivery::get_pending(pool)? {
+        if quiet.contains_key(&subscription.server_id) {
+            continue;
+        }
+
+        let edit = if delivery.edited {
+            Some(Edit {
+                title: db_announcement.previous_title.clone().unwrap_or_default(),
+                content: db_announcement.previous_content.clone().unwrap_or_default(),
+            })
+        } else {
+            None
+        };
+
+        let mut announcement = Announcement::from(db_announcement);
+        announcement.edit = edit;
+
+        let index = match per_subscription
+            .iter()
+            .position(|queued| queued.subscription.id == subscription.id)
+        {
+            Some(index) => index,
+            None => {
+                per_subscription.push(Queued {
+                    feed_id: canvas_id,
+                    digest: subscription.digest_end(now),
+                    subscription,
+                    announcements: Vec::new(),
+                });
+                per_subscription.len() - 1
+            }
+        };
+
+        let queued = &mut per_subscription[index];
+        if queued.digest.is_some_and(|end| delivery.queued >= end) {
+            continue;
+        }
+        queued.announcements.push(announcement);
+    }
+
+    per_subscription.retain(|queued| !queued.announcements.is_empty());
+
+    Ok(per_subscription)
+}
+
+/// One delivery per group of channels waiting for the same announcements of a feed
+fn group(queued: impl Iterator<Item = Queued>) -> Vec<PendingDelivery> {
+    let mut pending: Vec<PendingDelivery> = Vec::new();
+    for queued in queued {
+        let channel = Channel::for_subscription(&queued.subscription, &queued.announcements);
+
+        match pending.iter_mut().find(|p| {
+            p.feed_id == queued.feed_id
+                && p.digest == queued.digest
+                && same(&p.announcements, &queued.announcements)
+        }) {
+            Some(p) => p.channels.push(channel),
+            None => pending.push(PendingDelivery {
+                feed_id: queued.feed_id,
+                announcements: queued.announcements,
+                channels: vec![channel],
+                digest: queued.digest,
+            }),
+        }
+    }
+
+    pending
+}
+
+/// Are both the same announcements, delivered in the same way
+fn same(a: &[Announcement], b: &[Announcement]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .all(|(a, b)| a.id == b.id && a.edit.is_some() == b.edit.is_some())
+}
@@ -0,0 +1,179 @@
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+use crate::error::FeedError;
+
+use super::rss::Root;
+use super::Feed;
+
+/// Formats a feed can be parsed from, all end up as a `Feed`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FeedFormat {
+    /// `<feed>`, canvas announcements, GitHub releases, ...
+    #[default]
+    Atom,
+
+    /// RSS 2.0 `<rss>`
+    Rss,
+
+    /// RSS 1.0 `<rdf:RDF>`, moodle forums, ...
+    Rdf,
+
+    /// Announcements of a course from the canvas api, never detected
+    CanvasApi,
+}
+
+impl FeedFormat {
+    /// Detect the format from the root element of the feed
+    pub fn detect(body: &str) -> Result<Self, FeedError> {
+        let mut reader = Reader::from_str(body);
+        let mut buf = Vec::new();
+
+        loop {
+            match reader.read_event(&mut buf)? {
+                Event::Start(e) | Event::Empty(e) => {
+                    return match e.local_name() {
+                        b"feed" => Ok(Self::Atom),
+                        b"rss" => Ok(Self::Rss),
+                        b"RDF" => Ok(Self::Rdf),
+                        name => Err(FeedError::UnknownFormat(
+                            String::from_utf8_lossy(name).into_owned(),
+                        )),
+                    };
+                }
+                Event::Eof => return Err(FeedError::UnknownFormat(String::new())),
+                _ => buf.clear(),
+            }
+        }
+    }
+
+    /// Name stored in the db
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Atom => "atom",
+            Self::Rss => "rss",
+            Self::Rdf => "rdf",
+            Self::CanvasApi => "canvas_api",
+        }
+    }
+
+    /// Inverse of `as_str`, `None` for unknown names
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "atom" => Some(Self::Atom),
+            "rss" => Some(Self::Rss),
+            "rdf" => Some(Self::Rdf),
+            "canvas_api" => Some(Self::CanvasApi),
+            _ => None,
+        }
+    }
+
+    /// Parse a feed retrieved from `url`
+    pub(crate) fn parse(self, url: &str, body: &str) -> Result<Feed, FeedError> {
+        let mut deserializer = quick_xml::de::Deserializer::from_reader(body.as_bytes());
+
+        let mut feed = match self {
+            Self::Atom => serde_ignored::deserialize::<_, _, Feed>(&mut deserializer, |path| {
+                log_ignored(url, path)
+            })?,
+            Self::Rss | Self::Rdf => {
+                serde_ignored::deserialize::<_, _, Root>(&mut deserializer, |path| {
+                    log_ignored(url, path)
+                })?
+                .into_feed(url)
+            }
+            // Spread over several requests, see `Feed::from_canvas_api`
+            Self::CanvasApi => return Err(FeedError::new("the canvas api needs a token")),
+        };
+        feed.format = self;
+
+        Ok(feed)
+    }
+}
+
+/// Unknown fields that were already logged, so every poll does not log them again
+static IGNORED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Log fields of the feed that are not used, these show when the format changes
+fn log_ignored(url: &str, path: serde_ignored::Path) {
+    let path = field_path(&path);
+
+    // Namespace declarations are attributes to quick-xml, not fields
+    if path
+        .rsplit('.')
+        .next()
+        .unwrap_or_default()
+        .starts_with("xmlns")
+    {
+        return;
+    }
+
+    if let Ok(mut ignored) = IGNORED.lock() {
+        if ignored.insert(path.clone()) {
+            eprintln!("Feed {url}: ignored unknown field `{path}`");
+        }
+    }
+}
+
+/// Path of a field without the indices of sequences, `entry.category` for every entry
+fn field_path(path: &serde_ignored::Path) -> String {
+    use serde_ignored::Path;
+
+    match path {
+        Path::Root => String::new(),
+        Path::Map { parent, key } => match field_path(parent) {
+            parent if parent.is_empty() => key.clone(),
+            parent => format!("{parent}.{key}"),
+        },
+        Path::Seq { parent, .. }
+        | Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => field_path(parent),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_the_root_element() {
+        let atom = r#"<?xml version="1.0"?><feed xmlns="http://www.w3.org/2005/Atom"></feed>"#;
+        let rss = r#"<?xml version="1.0"?><!-- news --><rss version="2.0"><channel/></rss>"#;
+        let rdf = r#"<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"/>"#;
+
+        assert_eq!(FeedFormat::detect(atom).unwrap(), FeedFormat::Atom);
+        assert_eq!(FeedFormat::detect(rss).unwrap(), FeedFormat::Rss);
+        assert_eq!(FeedFormat::detect(rdf).unwrap(), FeedFormat::Rdf);
+    }
+
+    #[test]
+    fn unknown_format() {
+        let err = FeedFormat::detect("<html><body>Log in</body></html>").unwrap_err();
+        assert!(
+            matches!(&err, FeedError::UnknownFormat(name) if name == "html"),
+            "{err:?}"
+        );
+
+        let err = FeedFormat::detect("").unwrap_err();
+        assert!(
+            matches!(&err, FeedError::UnknownFormat(name) if name.is_empty()),
+            "{err:?}"
+        );
+    }
+
+    #[test]
+    fn names() {
+        for format in [
+            FeedFormat::Atom,
+            FeedFormat::Rss,
+            FeedFormat::Rdf,
+            FeedFormat::CanvasApi,
+        ] {
+            assert_eq!(FeedFormat::from_name(format.as_str()), Some(format));
+        }
+        assert_eq!(FeedFormat::from_name("json"), None);
+    }
+}
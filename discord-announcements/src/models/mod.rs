@@ -1,14 +1,32 @@
 mod canvas;
+mod canvas_api;
 mod db;
+mod delivery;
+mod format;
+mod rss;
 
-use db::{DbFeed, NewFeed};
+use db::NewFeed;
 
-pub use canvas::Feed;
-pub use db::DbSubscription;
+use crate::mentions::Mentions;
 
+pub use canvas::{
+    Announcement, Attachment, Author, Content, Edit, Feed, FeedOutcome, Link, Validators,
+};
+pub use db::{DbAnnouncement, DbBackupFeed, DbDelivery, DbFeed, DbGuildSettings, DbSubscription};
+pub use delivery::{PendingDelivery, PendingSink};
+pub use format::FeedFormat;
+
+/// A discord channel subscribed to a feed
+#[derive(Debug, Clone)]
 pub struct Channel {
-    server_id: String,
-    channel_id: String,
+    pub server_id: String,
+    pub channel_id: String,
+
+    /// Pinged with the announcements in `pings`
+    pub mentions: Mentions,
+
+    /// Ids of the announcements sent to the channel that ping `mentions`
+    pub pings: Vec<String>,
 }
 
 impl Channel {
@@ -16,6 +34,28 @@ impl Channel {
         Self {
             server_id,
             channel_id,
+            mentions: Mentions::default(),
+            pings: Vec::new(),
+        }
+    }
+
+    /// Channel of a subscription receiving `announcements`
+    pub fn for_subscription(subscription: &DbSubscription, announcements: &[Announcement]) -> Self {
+        let mentions = subscription.mentions();
+        let pings = subscription.pings(announcements);
+
+        Self {
+            server_id: subscription.server_id.clone(),
+            channel_id: subscription.channel_id.clone(),
+            mentions,
+            pings,
         }
     }
+
+    /// Mentions to ping with `announcement`, `None` when it does not ping
+    pub fn mentions_for(&self, announcement: &Announcement) -> Option<&Mentions> {
+        self.pings
+            .contains(&announcement.id)
+            .then_some(&self.mentions)
+    }
 }
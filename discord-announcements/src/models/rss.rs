@@ -0,0 +1,407 @@
+use serde::de::{IgnoredAny, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use time::format_description::well_known::{Iso8601, Rfc2822, Rfc3339};
+use time::OffsetDateTime;
+
+use super::canvas::{Author, Content, Link};
+use super::{Announcement, Feed};
+
+// RSS 2.0 and RSS 1.0 only differ in where the items are, both are read by the same types.
+// Elements are matched on their local name: `<dc:date>` is `date`, `<content:encoded>` is `encoded`.
+// Everything is deserialized by hand, a derive refuses elements with the same local name
+// that are not next to each other, like `<link>` and `<atom:link>`.
+
+/// `<rss>` or `<rdf:RDF>`
+pub(super) struct Root {
+    channel: Channel,
+
+    /// Items of RSS 1.0, they are next to the channel
+    items: Vec<Item>,
+}
+
+#[derive(Default)]
+struct Channel {
+    title: Option<String>,
+    link: Option<String>,
+
+    /// `<lastBuildDate>`, `<pubDate>` or `<dc:date>`
+    date: Option<SystemTime>,
+
+    /// Items of RSS 2.0
+    items: Vec<Item>,
+}
+
+#[derive(Default)]
+struct Item {
+    title: Option<String>,
+    link: Option<String>,
+    guid: Option<String>,
+
+    /// `rdf:about` of RSS 1.0
+    about: Option<String>,
+
+    description: Option<String>,
+
+    /// `<content:encoded>`, the full html when `description` is a summary
+    encoded: Option<String>,
+
+    /// `<pubDate>` or `<dc:date>`
+    date: Option<SystemTime>,
+
+    /// `<author>` or `<dc:creator>`
+    author: Option<String>,
+}
+
+impl Root {
+    /// Convert to the common model, feeds are told apart by their `url`
+    pub(super) fn into_feed(self, url: &str) -> Feed {
+        let mut items = self.channel.items;
+        items.extend(self.items);
+
+        let announcements: Vec<Announcement> = items
+            .into_iter()
+            .map(|item| item.into_announcement(url))
+            .collect();
+
+        let updated = self
+            .channel
+            .date
+            .or_else(|| {
+                announcements
+                    .iter()
+                    .map(|a| a.published)
+                    .filter(|&published| published != UNIX_EPOCH)
+                    .max()
+            })
+            .unwrap_or_else(SystemTime::now);
+
+        Feed {
+            xmlns: String::new(),
+            id: url.to_owned(),
+            title: self.channel.title.unwrap_or_default(),
+            updated,
+            links: self.channel.link.into_iter().map(alternate).collect(),
+            announcements,
+            format: Default::default(),
+            validators: Default::default(),
+        }
+    }
+}
+
+impl Item {
+    /// Ids are prefixed with the url of the feed, items of different feeds can share a guid
+    fn into_announcement(self, url: &str) -> Announcement {
+        // Items without date get the time they were first seen, see `Feed::date_undated`
+        let published = self.date.unwrap_or(UNIX_EPOCH);
+        let updated = published;
+
+        let title = self.title.unwrap_or_default();
+        let content = self.encoded.or(self.description).unwrap_or_default();
+
+        // Items without any identifier are told apart by what they say
+        let id = match self.guid.or(self.about).or_else(|| self.link.clone()) {
+            Some(id) => id,
+            None => content_hash(&title, &content),
+        };
+
+        Announcement {
+            title,
+            id: format!("{url}#{id}"),
+            updated,
+            published,
+            links: self.link.into_iter().map(alternate).collect(),
+            author: self.author.map(|name| Author { name }),
+            content: Content {
+                content_type: String::from("html"),
+                content,
+            },
+            attachments: Vec::new(),
+            sections: Vec::new(),
+            edit: None,
+        }
+    }
+}
+
+fn alternate(href: String) -> Link {
+    Link {
+        rel: String::from("alternate"),
+        href,
+    }
+}
+
+/// `sha256:` and the hex of the first 16 bytes of the hash of the title and the content
+fn content_hash(title: &str, content: &str) -> String {
+    let hash = Sha256::new()
+        .chain_update(title)
+        .chain_update([0])
+        .chain_update(content)
+        .finalize();
+
+    let hex: String = hash[..16].iter().map(|b| format!("{b:02x}")).collect();
+    format!("sha256:{hex}")
+}
+
+/// Keep the first value that is not empty, `<atom:link href=".."/>` has no text for example
+fn set(field: &mut Option<String>, value: String) {
+    if field.is_none() && !value.trim().is_empty() {
+        *field = Some(value.trim().to_owned());
+    }
+}
+
+/// RFC 2822 for RSS 2.0, ISO 8601 for `<dc:date>`, invalid dates are left out
+fn parse_date(date: &str) -> Option<SystemTime> {
+    let date = date.trim();
+
+    OffsetDateTime::parse(date, &Rfc3339)
+        .or_else(|_| OffsetDateTime::parse(date, &Rfc2822))
+        .or_else(|_| OffsetDateTime::parse(date, &Iso8601::DEFAULT))
+        .ok()
+        .map(Into::into)
+}
+
+impl<'de> Deserialize<'de> for Root {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct("rss", &["channel", "item"], RootVisitor)
+    }
+}
+
+struct RootVisitor;
+
+impl<'de> Visitor<'de> for RootVisitor {
+    type Value = Root;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "an <rss> or <rdf:RDF> element")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut channel = None;
+        let mut items = Vec::new();
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "channel" if channel.is_none() => channel = Some(map.next_value()?),
+                "item" => items.push(map.next_value()?),
+                "version" => {
+                    map.next_value::<String>()?;
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        Ok(Root {
+            channel: channel.unwrap_or_default(),
+            items,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Channel {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct("channel", &["title", "link", "item"], ChannelVisitor)
+    }
+}
+
+struct ChannelVisitor;
+
+impl<'de> Visitor<'de> for ChannelVisitor {
+    type Value = Channel;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a <channel> element")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut channel = Channel::default();
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "title" => set(&mut channel.title, map.next_value()?),
+                "link" => set(&mut channel.link, map.next_value()?),
+                "lastBuildDate" | "pubDate" | "date" => {
+                    let date = parse_date(&map.next_value::<String>()?);
+                    channel.date = channel.date.max(date);
+                }
+                "item" => channel.items.push(map.next_value()?),
+                // Known, but not needed
+                "description" | "rdf:about" => {
+                    map.next_value::<String>()?;
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        Ok(channel)
+    }
+}
+
+impl<'de> Deserialize<'de> for Item {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct("item", &["title", "link", "guid"], ItemVisitor)
+    }
+}
+
+struct ItemVisitor;
+
+impl<'de> Visitor<'de> for ItemVisitor {
+    type Value = Item;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "an <item> element")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut item = Item::default();
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "title" => set(&mut item.title, map.next_value()?),
+                "link" => set(&mut item.link, map.next_value()?),
+                "guid" => set(&mut item.guid, map.next_value()?),
+                "rdf:about" => set(&mut item.about, map.next_value()?),
+                "description" => set(&mut item.description, map.next_value()?),
+                "encoded" => set(&mut item.encoded, map.next_value()?),
+                "author" | "creator" => set(&mut item.author, map.next_value()?),
+                "pubDate" | "date" => {
+                    item.date = item.date.or(parse_date(&map.next_value::<String>()?));
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        Ok(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FeedFormat;
+    use time::macros::datetime;
+
+    const URL: &str = "https://example.com/news.xml";
+
+    const RSS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom" xmlns:content="http://purl.org/rss/1.0/modules/content/">
+  <channel>
+    <title>Course news</title>
+    <atom:link href="https://example.com/news.xml" rel="self" type="application/rss+xml"/>
+    <link>https://example.com/news</link>
+    <description>News of the course</description>
+    <lastBuildDate>Fri, 21 Jan 2022 10:00:00 +0000</lastBuildDate>
+    <item>
+      <title>Exam info</title>
+      <link>https://example.com/news/5</link>
+      <guid isPermaLink="false">news-5</guid>
+      <description>Summary</description>
+      <content:encoded><![CDATA[<p>Exam on <b>Monday</b></p>]]></content:encoded>
+      <pubDate>Thu, 20 Jan 2022 10:00:00 +0000</pubDate>
+      <author>jane@example.com (Jane Doe)</author>
+    </item>
+    <item>
+      <title>Welcome</title>
+      <description>&lt;p&gt;Hello&lt;/p&gt;</description>
+    </item>
+  </channel>
+</rss>"#;
+
+    const RDF: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#" xmlns="http://purl.org/rss/1.0/" xmlns:dc="http://purl.org/dc/elements/1.1/">
+  <channel rdf:about="https://moodle.example.com/rss/forum">
+    <title>Forum</title>
+    <link>https://moodle.example.com/mod/forum</link>
+    <description>Forum posts</description>
+  </channel>
+  <item rdf:about="https://moodle.example.com/mod/forum/post.php?id=12">
+    <title>Lab moved</title>
+    <link>https://moodle.example.com/mod/forum/discuss.php?d=7</link>
+    <description>&lt;p&gt;Room B&lt;/p&gt;</description>
+    <dc:creator>John Doe</dc:creator>
+    <dc:date>2022-01-19T08:30:00Z</dc:date>
+  </item>
+</rdf:RDF>"#;
+
+    #[test]
+    fn rss_items() {
+        let feed = FeedFormat::Rss.parse(URL, RSS).unwrap();
+
+        assert_eq!(feed.id, URL);
+        assert_eq!(feed.title, "Course news");
+        assert_eq!(feed.links[0].href, "https://example.com/news");
+        assert_eq!(feed.updated, datetime!(2022-01-21 10:00 UTC));
+        assert_eq!(feed.announcements.len(), 2);
+
+        let exam = &feed.announcements[0];
+        assert_eq!(exam.id, "https://example.com/news.xml#news-5");
+        assert_eq!(exam.title, "Exam info");
+        assert_eq!(exam.href(), "https://example.com/news/5");
+        assert_eq!(exam.published, datetime!(2022-01-20 10:00 UTC));
+        assert_eq!(exam.updated, exam.published);
+        assert_eq!(exam.author_name(), "jane@example.com (Jane Doe)");
+        // The full content wins over the summary
+        assert_eq!(exam.content.content, "<p>Exam on <b>Monday</b></p>");
+    }
+
+    #[test]
+    fn undated_item_without_identifier() {
+        let feed = FeedFormat::Rss.parse(URL, RSS).unwrap();
+        let welcome = &feed.announcements[1];
+
+        // Dated once it is known when it was first seen
+        assert_eq!(welcome.published, UNIX_EPOCH);
+        assert_eq!(welcome.updated, UNIX_EPOCH);
+        assert!(welcome.links.is_empty());
+        assert_eq!(welcome.content.content, "<p>Hello</p>");
+
+        // The same item gets the same id every poll
+        let again = FeedFormat::Rss.parse(URL, RSS).unwrap();
+        assert!(welcome
+            .id
+            .starts_with("https://example.com/news.xml#sha256:"));
+        assert_eq!(welcome.id, again.announcements[1].id);
+    }
+
+    #[test]
+    fn rdf_items() {
+        let feed = FeedFormat::Rdf.parse(URL, RDF).unwrap();
+
+        assert_eq!(feed.title, "Forum");
+        assert_eq!(feed.announcements.len(), 1);
+        // Without date on the channel the feed was updated with its newest item
+        assert_eq!(feed.updated, datetime!(2022-01-19 08:30 UTC));
+
+        let lab = &feed.announcements[0];
+        // Identified by `rdf:about` rather than by its link
+        assert_eq!(
+            lab.id,
+            "https://example.com/news.xml#https://moodle.example.com/mod/forum/post.php?id=12"
+        );
+        assert_eq!(
+            lab.href(),
+            "https://moodle.example.com/mod/forum/discuss.php?d=7"
+        );
+        assert_eq!(lab.title, "Lab moved");
+        assert_eq!(lab.author_name(), "John Doe");
+        assert_eq!(lab.published, datetime!(2022-01-19 08:30 UTC));
+        assert_eq!(lab.content.content, "<p>Room B</p>");
+    }
+
+    #[test]
+    fn dates() {
+        let date = Some(datetime!(2022-01-20 10:00 UTC).into());
+
+        assert_eq!(parse_date("Thu, 20 Jan 2022 10:00:00 +0000"), date);
+        assert_eq!(parse_date(" 2022-01-20T10:00:00Z "), date);
+        assert_eq!(parse_date("2022-01-20T11:00:00+01:00"), date);
+        assert_eq!(parse_date("yesterday"), None);
+    }
+}
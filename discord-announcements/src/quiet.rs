@@ -0,0 +1,176 @@
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+use time::OffsetDateTime;
+use time_tz::{timezones, OffsetDateTimeExt};
+
+use crate::digest::{local, parse_time};
+
+/// Time of the day a guild gets no announcements, they are held until it ends
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuietHours {
+    /// `HH:MM`
+    pub start: String,
+
+    /// `HH:MM`, the next day when it is before `start`, no quiet hours when it is `start`
+    pub end: String,
+
+    /// `Europe/Brussels`
+    pub timezone: String,
+}
+
+impl QuietHours {
+    /// Check the times and the timezone before they are stored
+    pub fn validate(&self) -> Result<(), String> {
+        for time in [&self.start, &self.end] {
+            parse_time(time).ok_or_else(|| format!("invalid time '{time}', use HH:MM"))?;
+        }
+        timezones::get_by_name(&self.timezone)
+            .ok_or_else(|| format!("unknown timezone '{}'", self.timezone))?;
+
+        Ok(())
+    }
+
+    /// End of the quiet hours when `now` is in them
+    ///
+    /// `None` outside of them and for quiet hours that can not be read.
+    pub fn until(&self, now: SystemTime) -> Option<SystemTime> {
+        let start = parse_time(&self.start)?;
+        let end = parse_time(&self.end)?;
+        let tz = timezones::get_by_name(&self.timezone)?;
+
+        let local_now = OffsetDateTime::from(now).to_timezone(tz);
+        let (date, time) = (local_now.date(), local_now.time());
+
+        let end_date = if start < end && start <= time && time < end {
+            date
+        } else if start > end && time >= start {
+            date.next_day()?
+        } else if start > end && time < end {
+            date
+        } else {
+            return None;
+        };
+
+        Some(local(end_date.with_time(end), tz).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    fn quiet(start: &str, end: &str) -> QuietHours {
+        QuietHours {
+            start: start.to_owned(),
+            end: end.to_owned(),
+            timezone: String::from("Europe/Brussels"),
+        }
+    }
+
+    fn at(datetime: OffsetDateTime) -> SystemTime {
+        datetime.into()
+    }
+
+    #[test]
+    fn same_day() {
+        let quiet = quiet("12:00", "14:00");
+
+        // Brussels is an hour ahead of UTC in the winter
+        assert_eq!(
+            quiet.until(at(datetime!(2022-01-20 11:30 UTC))),
+            Some(at(datetime!(2022-01-20 13:00 UTC)))
+        );
+        assert_eq!(quiet.until(at(datetime!(2022-01-20 10:59 UTC))), None);
+        assert_eq!(quiet.until(at(datetime!(2022-01-20 13:00 UTC))), None);
+    }
+
+    #[test]
+    fn across_midnight() {
+        let quiet = quiet("22:00", "07:00");
+
+        // Before midnight the quiet hours end the next day
+        assert_eq!(
+            quiet.until(at(datetime!(2022-01-20 21:00 UTC))),
+            Some(at(datetime!(2022-01-21 06:00 UTC)))
+        );
+        // After midnight they end the same day, 23:30 UTC is already the 21st in Brussels
+        assert_eq!(
+            quiet.until(at(datetime!(2022-01-20 23:30 UTC))),
+            Some(at(datetime!(2022-01-21 06:00 UTC)))
+        );
+        assert_eq!(
+            quiet.until(at(datetime!(2022-01-21 05:59 UTC))),
+            Some(at(datetime!(2022-01-21 06:00 UTC)))
+        );
+        assert_eq!(quiet.until(at(datetime!(2022-01-21 06:00 UTC))), None);
+        assert_eq!(quiet.until(at(datetime!(2022-01-21 12:00 UTC))), None);
+    }
+
+    #[test]
+    fn clocks_go_forward() {
+        let quiet = quiet("22:00", "07:00");
+
+        // The night the clocks go forward the quiet hours end at 07:00 summer time
+        assert_eq!(
+            quiet.until(at(datetime!(2022-03-26 21:30 UTC))),
+            Some(at(datetime!(2022-03-27 05:00 UTC)))
+        );
+        assert_eq!(
+            quiet.until(at(datetime!(2022-03-27 01:30 UTC))),
+            Some(at(datetime!(2022-03-27 05:00 UTC)))
+        );
+    }
+
+    #[test]
+    fn clocks_go_back() {
+        let quiet = quiet("22:00", "07:00");
+
+        assert_eq!(
+            quiet.until(at(datetime!(2022-10-29 20:30 UTC))),
+            Some(at(datetime!(2022-10-30 06:00 UTC)))
+        );
+    }
+
+    #[test]
+    fn end_in_the_repeated_hour() {
+        // 02:30 happens twice on the 30th, the quiet hours end the first time
+        let quiet = quiet("01:00", "02:30");
+
+        assert_eq!(
+            quiet.until(at(datetime!(2022-10-30 00:15 UTC))),
+            Some(at(datetime!(2022-10-30 00:30 UTC)))
+        );
+    }
+
+    #[test]
+    fn no_quiet_hours() {
+        let quiet = quiet("22:00", "22:00");
+
+        assert_eq!(quiet.until(at(datetime!(2022-01-20 21:00 UTC))), None);
+        assert_eq!(quiet.until(at(datetime!(2022-01-20 03:00 UTC))), None);
+    }
+
+    #[test]
+    fn validate() {
+        assert!(quiet("22:00", "07:00").validate().is_ok());
+        assert!(quiet("22:00", "7").validate().is_err());
+        assert!(QuietHours {
+            timezone: String::from("Nowhere"),
+            ..quiet("22:00", "07:00")
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn end_in_the_skipped_hour() {
+        // 02:30 does not exist on the 27th, the quiet hours end at 03:30 summer time
+        let quiet = quiet("23:00", "02:30");
+
+        assert_eq!(
+            quiet.until(at(datetime!(2022-03-26 23:00 UTC))),
+            Some(at(datetime!(2022-03-27 01:30 UTC)))
+        );
+    }
+}
@@ -1,3 +1,20 @@
+table! {
+    announcements (feed_id, id) {
+        id -> Varchar,
+        feed_id -> Int4,
+        title -> Varchar,
+        author -> Varchar,
+        link -> Varchar,
+        content -> Text,
+        published -> Timestamp,
+        updated -> Timestamp,
+        previous_title -> Nullable<Varchar>,
+        previous_content -> Nullable<Text>,
+        attachments -> Text,
+        sections -> Array<Text>,
+    }
+}
+
 table! {
     backup_feeds (id) {
         id -> Int4,
@@ -6,12 +23,44 @@ table! {
     }
 }
 
+table! {
+    deliveries (subscription_id, feed_id, announcement_id) {
+        subscription_id -> Int4,
+        feed_id -> Int4,
+        announcement_id -> Varchar,
+        edited -> Bool,
+        delivered -> Nullable<Timestamp>,
+        queued -> Timestamp,
+    }
+}
+
 table! {
     feeds (id) {
         id -> Int4,
         canvas_id -> Varchar,
         url -> Varchar,
         last_update -> Timestamp,
+        poll_interval -> Nullable<Int4>,
+        title -> Varchar,
+        last_error -> Nullable<Varchar>,
+        last_error_at -> Nullable<Timestamp>,
+        failures -> Int4,
+        last_success -> Nullable<Timestamp>,
+        last_status -> Nullable<Int4>,
+        disabled -> Bool,
+        format -> Varchar,
+        etag -> Nullable<Varchar>,
+        last_modified -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    guild_settings (server_id) {
+        server_id -> Varchar,
+        timezone -> Varchar,
+        quiet_start -> Nullable<Varchar>,
+        quiet_end -> Nullable<Varchar>,
+        held_until -> Nullable<Timestamp>,
     }
 }
 
@@ -21,10 +70,29 @@ table! {
         server_id -> Varchar,
         channel_id -> Varchar,
         feed_id -> Int4,
+        edits -> Bool,
+        sink_type -> Varchar,
+        sink_config -> Nullable<Text>,
+        filter -> Nullable<Text>,
+        mentions -> Nullable<Text>,
+        delivery_mode -> Nullable<Text>,
+        last_digest -> Nullable<Timestamp>,
+        sink_error -> Nullable<Varchar>,
+        sink_error_at -> Nullable<Timestamp>,
+        api_token -> Nullable<Varchar>,
     }
 }
 
+joinable!(announcements -> feeds (feed_id));
 joinable!(backup_feeds -> feeds (feed_id));
+joinable!(deliveries -> subscriptions (subscription_id));
 joinable!(subscriptions -> feeds (feed_id));
 
-allow_tables_to_appear_in_same_query!(backup_feeds, feeds, subscriptions,);
+allow_tables_to_appear_in_same_query!(
+    announcements,
+    backup_feeds,
+    deliveries,
+    feeds,
+    guild_settings,
+    subscriptions,
+);
@@ -0,0 +1,393 @@
+use reqwest::header::{HeaderMap, CONTENT_TYPE, RETRY_AFTER};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::discord::{DiscordMessage, Overflow};
+use crate::error::SinkError;
+use crate::http::HttpClient;
+use crate::mentions::Mentions;
+use crate::models::Announcement;
+
+use super::{check_status, DiscordConfig};
+
+/// Tries of a message that keeps getting rate limited
+const MAX_TRIES: usize = 5;
+
+/// Longest wait for a rate limit, a message that has to wait longer fails
+const MAX_WAIT: Duration = Duration::from_secs(60);
+
+/// Body of a `429 Too Many Requests` of discord
+#[derive(Debug, Default, Deserialize)]
+struct RateLimited {
+    /// Seconds to wait
+    retry_after: f64,
+
+    /// Every request of the application is limited, not only this bucket
+    #[serde(default)]
+    global: bool,
+}
+
+/// Rate limits discord told about
+#[derive(Debug, Default)]
+pub(super) struct Buckets(Mutex<Limits>);
+
+#[derive(Debug, Default)]
+struct Limits {
+    /// Bucket of a webhook url, webhooks in the same bucket share their limit
+    routes: HashMap<String, String>,
+
+    /// No requests in a bucket before this time
+    blocked: HashMap<String, Instant>,
+
+    /// No requests at all before this time
+    global: Option<Instant>,
+}
+
+impl Buckets {
+    fn limits(&self) -> std::sync::MutexGuard<'_, Limits> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Wait until the bucket of `url` is free again
+    async fn wait(&self, url: &str) -> Result<(), SinkError> {
+        let until = match self.limits().until(url) {
+            Some(until) if until > Instant::now() => until,
+            _ => return Ok(()),
+        };
+
+        if until - Instant::now() > MAX_WAIT {
+            return Err(SinkError::RateLimited);
+        }
+        tokio::time::sleep_until(until.into()).await;
+
+        Ok(())
+    }
+}
+
+impl Limits {
+    /// The bucket of `url`, the url itself until discord named it
+    fn key(&self, url: &str) -> String {
+        self.routes
+            .get(url)
+            .cloned()
+            .unwrap_or_else(|| url.to_owned())
+    }
+
+    fn until(&self, url: &str) -> Option<Instant> {
+        let blocked = self.blocked.get(&self.key(url)).copied();
+
+        blocked.max(self.global)
+    }
+
+    fn block(&mut self, url: &str, duration: Duration, global: bool) {
+        let until = Instant::now() + duration;
+
+        if global {
+            self.global = self.global.max(Some(until));
+        } else {
+            let blocked = self.blocked.entry(self.key(url)).or_insert(until);
+            *blocked = (*blocked).max(until);
+        }
+    }
+
+    /// Remember the bucket of `url` and block it when its limit is used up
+    fn observe(&mut self, url: &str, headers: &HeaderMap) {
+        if let Some(bucket) = header(headers, "X-RateLimit-Bucket") {
+            self.routes.insert(url.to_owned(), bucket.to_owned());
+        }
+
+        let remaining = header(headers, "X-RateLimit-Remaining");
+        let reset_after = header(headers, "X-RateLimit-Reset-After").and_then(secs);
+        if let (Some("0"), Some(reset_after)) = (remaining, reset_after) {
+            self.block(url, reset_after, false);
+        }
+    }
+}
+
+/// Post the announcement as one or more messages, the messages before a failure stay posted
+///
+/// The first message pings the mentions.
+/// A failure after the first message is `SinkError::Partial`, sending again would repeat those.
+pub(super) async fn send(
+    http: &HttpClient,
+    buckets: &Buckets,
+    config: &DiscordConfig,
+    announcement: &Announcement,
+    mentions: Option<&Mentions>,
+) -> Result<(), SinkError> {
+    let mut messages = announcement.render(Overflow::Split);
+    if let (Some(first), Some(mentions)) = (messages.first_mut(), mentions) {
+        first.mention(mentions);
+    }
+
+    for (sent, message) in messages.iter().enumerate() {
+        match post(http, buckets, &config.url, message).await {
+            Ok(()) => {}
+            Err(e) if sent > 0 => return Err(SinkError::Partial(sent, Box::new(e))),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Post a message, waiting for the rate limits of its bucket
+///
+/// Discord tells the limits itself, the client only sends it once
+/// and a message that was posted is never posted again.
+async fn post(
+    http: &HttpClient,
+    buckets: &Buckets,
+    url: &str,
+    message: &DiscordMessage,
+) -> Result<(), SinkError> {
+    for _ in 0..MAX_TRIES {
+        buckets.wait(url).await?;
+
+        let response = http
+            .send_once(
+                http.post(url)
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(message.to_json()),
+            )
+            .await?;
+        buckets.limits().observe(url, response.headers());
+
+        if response.status() != StatusCode::TOO_MANY_REQUESTS {
+            return check_status(&response);
+        }
+
+        let header = header(response.headers(), RETRY_AFTER.as_str()).and_then(secs);
+        let body = response.text().await.unwrap_or_default();
+        let limit: RateLimited = serde_json::from_str(&body).unwrap_or_default();
+
+        let retry_after = Duration::try_from_secs_f64(limit.retry_after)
+            .ok()
+            .filter(|retry_after| !retry_after.is_zero())
+            .or(header)
+            .unwrap_or(Duration::from_secs(1));
+        buckets.limits().block(url, retry_after, limit.global);
+    }
+
+    Err(SinkError::RateLimited)
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name)?.to_str().ok()
+}
+
+/// Seconds with a fraction, like discord sends them
+fn secs(value: &str) -> Option<Duration> {
+    Duration::try_from_secs_f64(value.trim().parse().ok()?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{announcement, http_client, Reply, TestServer};
+    use std::time::Instant;
+
+    async fn send_to(server: &TestServer, mentions: Option<&Mentions>) -> Result<(), SinkError> {
+        let config = DiscordConfig {
+            url: server.url("/api/webhooks/1/token"),
+        };
+        let announcement = announcement("Exam info", "<p>Exam on <b>Monday</b></p>");
+
+        send(
+            &http_client(),
+            &Buckets::default(),
+            &config,
+            &announcement,
+            mentions,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn posts_the_embed() {
+        let server = TestServer::start(vec![Reply::new(204, "")]).await;
+        let mentions = Mentions {
+            roles: vec![String::from("123")],
+            ..Default::default()
+        };
+
+        send_to(&server, Some(&mentions)).await.unwrap();
+
+        let received = server.received();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].method, "POST");
+        assert_eq!(received[0].path, "/api/webhooks/1/token");
+        assert_eq!(received[0].header("Content-Type"), Some("application/json"));
+
+        let message = received[0].json();
+        assert_eq!(message["content"], "<@&123>");
+        assert_eq!(message["allowed_mentions"]["roles"][0], "123");
+        assert_eq!(message["embeds"][0]["title"], "Exam info");
+        assert_eq!(message["embeds"][0]["description"], "Exam on **Monday**");
+    }
+
+    #[tokio::test]
+    async fn retries_after_a_rate_limit() {
+        let server = TestServer::start(vec![
+            Reply::new(429, r#"{"message": "You are being rate limited.", "retry_after": 0.2, "global": false}"#),
+            Reply::new(204, ""),
+        ])
+        .await;
+
+        let start = Instant::now();
+        send_to(&server, None).await.unwrap();
+
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        let received = server.received();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0].body, received[1].body);
+    }
+
+    #[tokio::test]
+    async fn retry_after_header() {
+        let server = TestServer::start(vec![
+            Reply::new(429, "").header("Retry-After", "0.2"),
+            Reply::new(204, ""),
+        ])
+        .await;
+
+        let start = Instant::now();
+        send_to(&server, None).await.unwrap();
+
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert_eq!(server.received().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn gives_up_when_rate_limited() {
+        let server = TestServer::start(vec![Reply::new(
+            429,
+            r#"{"retry_after": 0.01, "global": true}"#,
+        )])
+        .await;
+
+        let err = send_to(&server, None).await.unwrap_err();
+
+        assert!(matches!(err, SinkError::RateLimited), "{err:?}");
+        assert_eq!(server.received().len(), MAX_TRIES);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_posted_messages() {
+        let server = TestServer::start(vec![
+            Reply::new(204, ""),
+            Reply::new(500, ""),
+            Reply::new(204, ""),
+        ])
+        .await;
+        let config = DiscordConfig {
+            url: server.url("/api/webhooks/1/token"),
+        };
+        let paragraph = format!("<p>{}</p>", "Exam on Monday. ".repeat(60));
+        let announcement = announcement("Exam info", &paragraph.repeat(20));
+        assert!(announcement.render(Overflow::Split).len() > 2);
+
+        let err = send(
+            &http_client(),
+            &Buckets::default(),
+            &config,
+            &announcement,
+            None,
+        )
+        .await
+        .unwrap_err();
+
+        assert!(
+            matches!(&err, SinkError::Partial(1, e) if matches!(**e, SinkError::Status(500))),
+            "{err:?}"
+        );
+        assert_eq!(server.received().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_not_retried() {
+        let server = TestServer::start(vec![Reply::new(503, ""), Reply::new(204, "")]).await;
+        let http = HttpClient::new(crate::HttpConfig {
+            retries: 2,
+            retry_backoff: Duration::ZERO,
+            host_interval: Duration::ZERO,
+            ..Default::default()
+        })
+        .unwrap();
+        let config = DiscordConfig {
+            url: server.url("/api/webhooks/1/token"),
+        };
+        let announcement = announcement("Exam info", "<p>Exam</p>");
+
+        let err = send(&http, &Buckets::default(), &config, &announcement, None)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, SinkError::Status(503)), "{err:?}");
+        assert_eq!(server.received().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn deleted_webhook() {
+        let server = TestServer::start(vec![Reply::new(
+            404,
+            r#"{"message": "Unknown Webhook", "code": 10015}"#,
+        )])
+        .await;
+
+        let err = send_to(&server, None).await.unwrap_err();
+
+        assert!(matches!(err, SinkError::Gone(404)), "{err:?}");
+        assert_eq!(server.received().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn waits_for_a_used_up_bucket() {
+        let server = TestServer::start(vec![Reply::new(204, "")
+            .header("X-RateLimit-Bucket", "abcd")
+            .header("X-RateLimit-Remaining", "0")
+            .header("X-RateLimit-Reset-After", "0.2")])
+        .await;
+        let buckets = Buckets::default();
+        let config = DiscordConfig {
+            url: server.url("/api/webhooks/1/token"),
+        };
+        let announcement = announcement("Exam info", "<p>Exam</p>");
+
+        send(&http_client(), &buckets, &config, &announcement, None)
+            .await
+            .unwrap();
+        let start = Instant::now();
+        send(&http_client(), &buckets, &config, &announcement, None)
+            .await
+            .unwrap();
+
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        assert_eq!(buckets.limits().key(&config.url), "abcd");
+    }
+
+    #[test]
+    fn global_limit_blocks_every_bucket() {
+        let mut limits = Limits::default();
+        limits.block(
+            "https://discord.com/api/webhooks/1/token",
+            Duration::from_secs(5),
+            true,
+        );
+
+        assert!(limits
+            .until("https://discord.com/api/webhooks/2/token")
+            .is_some());
+    }
+
+    #[test]
+    fn seconds_with_a_fraction() {
+        assert_eq!(secs("1.5"), Some(Duration::from_millis(1500)));
+        assert_eq!(secs(" 2 "), Some(Duration::from_secs(2)));
+        assert_eq!(secs("-1"), None);
+        assert_eq!(secs("soon"), None);
+    }
+}
@@ -0,0 +1,97 @@
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use serde_json::json;
+use std::time::UNIX_EPOCH;
+
+use crate::error::SinkError;
+use crate::http::HttpClient;
+use crate::models::Announcement;
+
+use super::{check_status, html, http_url, title, MatrixConfig};
+
+pub(super) async fn send(
+    http: &HttpClient,
+    config: &MatrixConfig,
+    announcement: &Announcement,
+) -> Result<(), SinkError> {
+    // The same transaction for every try, the homeserver drops the copies
+    let updated = announcement
+        .updated
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let transaction = format!("{}-{updated}", announcement.id);
+
+    let mut url = http_url(&config.homeserver)?;
+    url.path_segments_mut()
+        .map_err(|_| SinkError::Config(format!("{}: not a base url", config.homeserver)))?
+        .pop_if_empty()
+        .extend([
+            "_matrix",
+            "client",
+            "v3",
+            "rooms",
+            &config.room_id,
+            "send",
+            "m.room.message",
+            &transaction,
+        ]);
+
+    let body = format!(
+        "{}\n\n{}",
+        title(announcement),
+        announcement.markdown().text
+    );
+    let event = json!({
+        "msgtype": "m.text",
+        "body": body,
+        "format": "org.matrix.custom.html",
+        "formatted_body": html(announcement),
+    });
+
+    let request = http
+        .put(url.as_str())
+        .header(AUTHORIZATION, format!("Bearer {}", config.access_token))
+        .header(CONTENT_TYPE, "application/json")
+        .body(event.to_string());
+
+    check_status(&http.send(request).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{announcement, http_client, Reply, TestServer};
+
+    #[tokio::test]
+    async fn sends_a_room_message() {
+        let server = TestServer::start(vec![Reply::new(200, r#"{"event_id": "$1"}"#)]).await;
+        let config = MatrixConfig {
+            homeserver: server.url("/"),
+            room_id: String::from("!room:example.com"),
+            access_token: String::from("secret"),
+        };
+        let announcement = announcement("Exam info", "<p>Exam on <b>Monday</b></p>");
+
+        send(&http_client(), &config, &announcement).await.unwrap();
+
+        let received = server.received();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].method, "PUT");
+        assert_eq!(
+            received[0].path,
+            "/_matrix/client/v3/rooms/!room:example.com/send/m.room.message/\
+             tag:canvas.example.com,2022-01-20:%2Fcourses%2F1%2Fdiscussion_topics%2F5-1642672800"
+        );
+        assert_eq!(received[0].header("Authorization"), Some("Bearer secret"));
+        assert_eq!(
+            received[0].json(),
+            json!({
+                "msgtype": "m.text",
+                "body": "Exam info\n\nExam on **Monday**",
+                "format": "org.matrix.custom.html",
+                "formatted_body": "<h3><a href=\"https://canvas.example.com/courses/1/discussion_topics/5\">Exam info</a></h3>\n\
+                                   <p>Exam on <b>Monday</b></p>",
+            })
+        );
+    }
+}
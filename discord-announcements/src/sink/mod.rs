@@ -0,0 +1,295 @@
+mod discord;
+mod email;
+mod matrix;
+mod slack;
+mod webhook;
+
+use reqwest::{Response, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::error::SinkError;
+use crate::http::HttpClient;
+use crate::mentions::Mentions;
+use crate::models::Announcement;
+
+pub use email::SmtpConfig;
+
+/// Where the announcements of a subscription go,
+/// stored as the `sink_type` and the json `sink_config` of the subscription
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(tag = "type", content = "config", rename_all = "snake_case")]
+pub enum Sink {
+    /// The bot receives them through `NewAnnouncements`
+    #[default]
+    Bot,
+
+    /// Discord webhook, posted to by the server
+    Discord(DiscordConfig),
+
+    /// Slack incoming webhook
+    Slack(SlackConfig),
+
+    /// Matrix room
+    Matrix(MatrixConfig),
+
+    /// Any url that takes the announcement as json
+    Webhook(WebhookConfig),
+
+    /// Mail to a list of addresses, through the smtp server of `SmtpConfig`
+    Email(EmailConfig),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DiscordConfig {
+    pub url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SlackConfig {
+    pub url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MatrixConfig {
+    /// Base url of the homeserver: `https://matrix.org`
+    pub homeserver: String,
+
+    /// `!abc:matrix.org`, the user of the token has to be in the room
+    pub room_id: String,
+
+    pub access_token: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+
+    /// Sent with every request: `Authorization`, ...
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct EmailConfig {
+    pub to: Vec<String>,
+}
+
+impl Sink {
+    /// Read the sink of a subscription, the config is json
+    pub fn from_db(sink_type: &str, sink_config: Option<&str>) -> Result<Self, SinkError> {
+        let config: Value = match sink_config {
+            Some(config) => serde_json::from_str(config)?,
+            None => Value::Null,
+        };
+
+        let mut sink = serde_json::Map::new();
+        sink.insert(String::from("type"), Value::from(sink_type));
+        if !config.is_null() {
+            sink.insert(String::from("config"), config);
+        }
+
+        serde_json::from_value(Value::Object(sink))
+            .map_err(|e| SinkError::Config(format!("{sink_type}: {e}")))
+    }
+
+    /// `sink_type` and `sink_config` to store
+    pub fn to_db(&self) -> (&'static str, Option<String>) {
+        let config = match serde_json::to_value(self) {
+            Ok(Value::Object(mut sink)) => sink.remove("config").map(|config| config.to_string()),
+            _ => None,
+        };
+
+        (self.as_str(), config)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Bot => "bot",
+            Self::Discord(_) => "discord",
+            Self::Slack(_) => "slack",
+            Self::Matrix(_) => "matrix",
+            Self::Webhook(_) => "webhook",
+            Self::Email(_) => "email",
+        }
+    }
+
+    /// Check the config before it is stored, the sink is not contacted
+    pub fn validate(&self) -> Result<(), SinkError> {
+        match self {
+            Self::Bot => Ok(()),
+            Self::Discord(DiscordConfig { url })
+            | Self::Slack(SlackConfig { url })
+            | Self::Webhook(WebhookConfig { url, .. }) => http_url(url).map(|_| ()),
+            Self::Matrix(config) => {
+                http_url(&config.homeserver)?;
+                if !config.room_id.starts_with('!') {
+                    return Err(SinkError::Config(String::from(
+                        "the room id starts with '!', not an alias",
+                    )));
+                }
+                if config.access_token.is_empty() {
+                    return Err(SinkError::Config(String::from("missing access token")));
+                }
+                Ok(())
+            }
+            Self::Email(config) => {
+                if config.to.is_empty() {
+                    return Err(SinkError::Config(String::from("no addresses to mail to")));
+                }
+                for to in &config.to {
+                    to.parse::<lettre::Address>()?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Sends announcements to sinks, clones share the clients and the rate limits
+#[derive(Debug, Clone)]
+pub struct Sinks {
+    http: HttpClient,
+
+    /// Rate limits of the discord webhooks
+    discord: Arc<discord::Buckets>,
+
+    /// `None` without an smtp server, mails fail then
+    mailer: Option<Arc<email::Mailer>>,
+}
+
+impl Sinks {
+    pub fn new(http: HttpClient, smtp: Option<SmtpConfig>) -> Result<Self, SinkError> {
+        Ok(Self {
+            http,
+            discord: Arc::default(),
+            mailer: smtp.map(email::Mailer::new).transpose()?.map(Arc::new),
+        })
+    }
+
+    /// Send a single announcement, the sink has its own rate limits
+    ///
+    /// Only discord pings the mentions, the role ids mean nothing to the other sinks.
+    pub async fn send(
+        &self,
+        sink: &Sink,
+        announcement: &Announcement,
+        mentions: Option<&Mentions>,
+    ) -> Result<(), SinkError> {
+        match sink {
+            Sink::Bot => Err(SinkError::Config(String::from(
+                "the bot delivers the announcements",
+            ))),
+            Sink::Discord(config) => {
+                discord::send(&self.http, &self.discord, config, announcement, mentions).await
+            }
+            Sink::Slack(config) => slack::send(&self.http, config, announcement).await,
+            Sink::Matrix(config) => matrix::send(&self.http, config, announcement).await,
+            Sink::Webhook(config) => webhook::send(&self.http, config, announcement).await,
+            Sink::Email(config) => match &self.mailer {
+                Some(mailer) => mailer.send(config, announcement).await,
+                None => Err(SinkError::Config(String::from("no smtp server configured"))),
+            },
+        }
+    }
+}
+
+fn http_url(url: &str) -> Result<Url, SinkError> {
+    let parsed = Url::parse(url).map_err(|e| SinkError::Config(format!("{url}: {e}")))?;
+
+    match parsed.scheme() {
+        "https" | "http" => Ok(parsed),
+        scheme => Err(SinkError::Config(format!(
+            "{url}: unsupported scheme {scheme}"
+        ))),
+    }
+}
+
+/// Turn an error status into an error, the other sinks share this
+fn check_status(response: &Response) -> Result<(), SinkError> {
+    let status = response.status();
+
+    match status {
+        status if status.is_success() => Ok(()),
+        StatusCode::NOT_FOUND
+        | StatusCode::UNAUTHORIZED
+        | StatusCode::FORBIDDEN
+        | StatusCode::GONE => Err(SinkError::Gone(status.as_u16())),
+        StatusCode::TOO_MANY_REQUESTS => Err(SinkError::RateLimited),
+        status => Err(SinkError::Status(status.as_u16())),
+    }
+}
+
+/// Title of an announcement as it is sent
+fn title(announcement: &Announcement) -> String {
+    match announcement.edit {
+        Some(_) => format!("[Edited] {}", announcement.title),
+        None => announcement.title.clone(),
+    }
+}
+
+/// The announcement as html: the title linking to it and the content
+fn html(announcement: &Announcement) -> String {
+    let title = escape_html(&title(announcement));
+    let heading = match announcement.href() {
+        "" => title,
+        href => format!("<a href=\"{}\">{title}</a>", escape_html(href)),
+    };
+
+    let content = match announcement.content.content_type.as_str() {
+        "text" => escape_html(&announcement.content.content).replace('\n', "<br>\n"),
+        _ => announcement.content.content.clone(),
+    };
+
+    format!("<h3>{heading}</h3>\n{content}")
+}
+
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{announcement, http_client, Reply, TestServer};
+
+    #[tokio::test]
+    async fn gone_sinks() {
+        let sinks = Sinks::new(http_client(), None).unwrap();
+        let announcement = announcement("Exam info", "<p>Exam</p>");
+
+        for status in [401, 403, 404, 410, 429, 500] {
+            let server = TestServer::start(vec![Reply::new(status, "")]).await;
+            let sink = Sink::Webhook(WebhookConfig {
+                url: server.url("/hook"),
+                headers: BTreeMap::new(),
+            });
+
+            let err = sinks.send(&sink, &announcement, None).await.unwrap_err();
+
+            match status {
+                401 | 403 | 404 | 410 => assert!(matches!(err, SinkError::Gone(s) if s == status)),
+                429 => assert!(matches!(err, SinkError::RateLimited)),
+                _ => assert!(matches!(err, SinkError::Status(500))),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn bot_sink() {
+        let sinks = Sinks::new(http_client(), None).unwrap();
+        let announcement = announcement("Exam info", "<p>Exam</p>");
+
+        let err = sinks
+            .send(&Sink::Bot, &announcement, None)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, SinkError::Config(_)), "{err:?}");
+    }
+}
@@ -0,0 +1,156 @@
+use reqwest::header::CONTENT_TYPE;
+use serde_json::json;
+
+use crate::error::SinkError;
+use crate::http::HttpClient;
+use crate::models::Announcement;
+
+use super::{check_status, title, SlackConfig};
+
+/// Characters in the text of a section block
+const SECTION_LIMIT: usize = 3000;
+
+pub(super) async fn send(
+    http: &HttpClient,
+    config: &SlackConfig,
+    announcement: &Announcement,
+) -> Result<(), SinkError> {
+    let title = title(announcement);
+    let heading = match announcement.href() {
+        "" => format!("*{}*", escape(&title)),
+        href => format!("*<{href}|{}>*", escape(&title)),
+    };
+    let text = format!("{heading}\n\n{}", mrkdwn(&announcement.markdown().text));
+
+    let payload = json!({
+        // Shown in notifications
+        "text": title,
+        "blocks": [{
+            "type": "section",
+            "text": { "type": "mrkdwn", "text": truncate(&text, SECTION_LIMIT) },
+        }],
+    });
+
+    let request = http
+        .post(&config.url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(payload.to_string());
+
+    check_status(&http.send(request).await?)
+}
+
+/// Slack only wants `&`, `<` and `>` escaped
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Turn the markdown of discord into the mrkdwn of slack:
+/// `**bold**` to `*bold*`, `*italic*` to `_italic_`, `[text](url)` to `<url|text>`
+///
+/// Slack has no underline and no escapes, those are dropped.
+fn mrkdwn(markdown: &str) -> String {
+    let mut out = String::with_capacity(markdown.len());
+    let mut rest = markdown;
+
+    while let Some(c) = rest.chars().next() {
+        let next = rest[c.len_utf8()..].chars().next();
+        let mut len = c.len_utf8();
+
+        match (c, next) {
+            ('\\', Some(escaped)) => {
+                out.push_str(&escape(&escaped.to_string()));
+                len += escaped.len_utf8();
+            }
+            ('*', Some('*')) => {
+                out.push('*');
+                len += 1;
+            }
+            ('*', _) => out.push('_'),
+            ('_', Some('_')) => len += 1,
+            ('~', Some('~')) => {
+                out.push('~');
+                len += 1;
+            }
+            ('[', _) => match link(rest) {
+                Some((text, url, link_len)) => {
+                    out.push_str(&format!("<{url}|{}>", mrkdwn(text)));
+                    len = link_len;
+                }
+                None => out.push('['),
+            },
+            ('&' | '<' | '>', _) => out.push_str(&escape(&c.to_string())),
+            _ => out.push(c),
+        }
+
+        rest = &rest[len..];
+    }
+
+    out
+}
+
+/// `[text](url)` at the start of `s`: the text, the url and the length of the link
+fn link(s: &str) -> Option<(&str, &str, usize)> {
+    let line = s.lines().next()?;
+    let close = line.find("](")?;
+    let end = close + line[close..].find(')')?;
+
+    let url = &line[close + 2..end];
+    if !url.starts_with("http") {
+        return None;
+    }
+
+    Some((&line[1..close], url, end + 1))
+}
+
+fn truncate(text: &str, limit: usize) -> String {
+    if text.chars().count() <= limit {
+        return text.to_owned();
+    }
+
+    let end = text
+        .char_indices()
+        .nth(limit - 1)
+        .map_or(text.len(), |(i, _)| i);
+    format!("{}…", &text[..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{announcement, http_client, Reply, TestServer};
+
+    #[tokio::test]
+    async fn posts_a_section() {
+        let server = TestServer::start(vec![Reply::new(200, "ok")]).await;
+        let config = SlackConfig {
+            url: server.url("/services/T0/B0/secret"),
+        };
+        let announcement = announcement(
+            "Exam <info>",
+            r#"<p>Exam on <b>Monday</b>, <i>bring</i> a <a href="https://example.com/pen">pen</a></p>"#,
+        );
+
+        send(&http_client(), &config, &announcement).await.unwrap();
+
+        let received = server.received();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].method, "POST");
+        assert_eq!(received[0].path, "/services/T0/B0/secret");
+        assert_eq!(
+            received[0].json(),
+            json!({
+                "text": "Exam <info>",
+                "blocks": [{
+                    "type": "section",
+                    "text": {
+                        "type": "mrkdwn",
+                        "text": "*<https://canvas.example.com/courses/1/discussion_topics/5|Exam &lt;info&gt;>*\n\n\
+                                 Exam on *Monday*, _bring_ a <https://example.com/pen|pen>",
+                    },
+                }],
+            })
+        );
+    }
+}
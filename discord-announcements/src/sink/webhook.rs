@@ -0,0 +1,115 @@
+use reqwest::header::CONTENT_TYPE;
+use serde::Serialize;
+use std::time::SystemTime;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::error::SinkError;
+use crate::http::HttpClient;
+use crate::models::{Announcement, Attachment};
+
+use super::{check_status, WebhookConfig};
+
+/// Json body of the request
+#[derive(Debug, Serialize)]
+struct Payload<'a> {
+    id: &'a str,
+    title: &'a str,
+    url: &'a str,
+    author: &'a str,
+
+    /// RFC 3339
+    published: String,
+    updated: String,
+
+    /// `html` or `text`
+    content_type: &'a str,
+    content: &'a str,
+    content_markdown: String,
+
+    attachments: &'a [Attachment],
+    sections: &'a [String],
+
+    /// Sent before, this is a new version
+    edited: bool,
+}
+
+pub(super) async fn send(
+    http: &HttpClient,
+    config: &WebhookConfig,
+    announcement: &Announcement,
+) -> Result<(), SinkError> {
+    let payload = Payload {
+        id: &announcement.id,
+        title: &announcement.title,
+        url: announcement.href(),
+        author: announcement.author_name(),
+        published: rfc3339(announcement.published),
+        updated: rfc3339(announcement.updated),
+        content_type: &announcement.content.content_type,
+        content: &announcement.content.content,
+        content_markdown: announcement.markdown().text,
+        attachments: &announcement.attachments,
+        sections: &announcement.sections,
+        edited: announcement.edit.is_some(),
+    };
+
+    let mut request = http
+        .post(&config.url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(serde_json::to_string(&payload)?);
+    for (name, value) in &config.headers {
+        request = request.header(name, value);
+    }
+
+    check_status(&http.send(request).await?)
+}
+
+fn rfc3339(time: SystemTime) -> String {
+    OffsetDateTime::from(time)
+        .format(&Rfc3339)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{announcement, http_client, Reply, TestServer};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn posts_the_payload() {
+        let server = TestServer::start(vec![Reply::new(202, "")]).await;
+        let config = WebhookConfig {
+            url: server.url("/hooks/canvas"),
+            headers: [(String::from("X-Token"), String::from("secret"))].into(),
+        };
+        let mut announcement = announcement("Exam info", "<p>Exam on <b>Monday</b></p>");
+        announcement.sections = vec![String::from("Group A")];
+
+        send(&http_client(), &config, &announcement).await.unwrap();
+
+        let received = server.received();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].method, "POST");
+        assert_eq!(received[0].path, "/hooks/canvas");
+        assert_eq!(received[0].header("X-Token"), Some("secret"));
+        assert_eq!(
+            received[0].json(),
+            json!({
+                "id": "tag:canvas.example.com,2022-01-20:/courses/1/discussion_topics/5",
+                "title": "Exam info",
+                "url": "https://canvas.example.com/courses/1/discussion_topics/5",
+                "author": "Jane Doe",
+                "published": "2022-01-20T10:00:00Z",
+                "updated": "2022-01-20T10:00:00Z",
+                "content_type": "html",
+                "content": "<p>Exam on <b>Monday</b></p>",
+                "content_markdown": "Exam on **Monday**",
+                "attachments": [],
+                "sections": ["Group A"],
+                "edited": false,
+            })
+        );
+    }
+}
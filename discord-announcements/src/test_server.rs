@@ -0,0 +1,199 @@
+//! Http server on a local port that answers with canned replies, for the tests of the clients
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::models::{Announcement, Author, Content, Link};
+use crate::{HttpClient, HttpConfig};
+
+/// A request the server received
+#[derive(Debug, Clone)]
+pub struct Received {
+    pub method: String,
+
+    /// Path with the query
+    pub path: String,
+
+    /// Names in lowercase
+    pub headers: Vec<(String, String)>,
+
+    pub body: String,
+}
+
+impl Received {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).unwrap()
+    }
+}
+
+/// Response to a request, `{base}` in the headers and the body becomes the url of the server
+#[derive(Debug, Clone)]
+pub struct Reply {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Reply {
+    pub fn new(status: u16, body: &str) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: body.to_owned(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+}
+
+pub struct TestServer {
+    base: String,
+    received: Arc<Mutex<Vec<Received>>>,
+}
+
+impl TestServer {
+    /// Answer the requests with `replies` in order, the last one again once they run out
+    pub async fn start(replies: Vec<Reply>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+
+        tokio::spawn(serve(listener, base.clone(), replies, received.clone()));
+
+        Self { base, received }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base)
+    }
+
+    pub fn received(&self) -> Vec<Received> {
+        self.received.lock().unwrap().clone()
+    }
+}
+
+/// Client without waits between requests and without retries
+pub fn http_client() -> HttpClient {
+    HttpClient::new(HttpConfig {
+        retries: 0,
+        retry_backoff: Duration::ZERO,
+        host_interval: Duration::ZERO,
+        ..Default::default()
+    })
+    .unwrap()
+}
+
+/// One request per connection, the replies say `Connection: close`
+async fn serve(
+    listener: TcpListener,
+    base: String,
+    replies: Vec<Reply>,
+    received: Arc<Mutex<Vec<Received>>>,
+) {
+    for i in 0.. {
+        let (mut stream, _) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(_) => return,
+        };
+        let request = match read_request(&mut stream).await {
+            Some(request) => request,
+            None => continue,
+        };
+        received.lock().unwrap().push(request);
+
+        let reply = &replies[i.min(replies.len() - 1)];
+        let body = reply.body.replace("{base}", &base);
+        let mut response = format!(
+            "HTTP/1.1 {} Test\r\nContent-Length: {}\r\nConnection: close\r\n",
+            reply.status,
+            body.len()
+        );
+        for (name, value) in &reply.headers {
+            response.push_str(&format!("{name}: {}\r\n", value.replace("{base}", &base)));
+        }
+        response.push_str("\r\n");
+        response.push_str(&body);
+
+        stream.write_all(response.as_bytes()).await.ok();
+        stream.shutdown().await.ok();
+    }
+}
+
+async fn read_request(stream: &mut TcpStream) -> Option<Received> {
+    let mut data = Vec::new();
+    let mut buf = [0; 4096];
+
+    let head_end = loop {
+        if let Some(end) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end;
+        }
+        let n = stream.read(&mut buf).await.ok().filter(|&n| n > 0)?;
+        data.extend_from_slice(&buf[..n]);
+    };
+
+    let head = String::from_utf8_lossy(&data[..head_end]).into_owned();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next()?.to_owned();
+    let path = request_line.next()?.to_owned();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_owned()))
+        .collect();
+
+    let length: usize = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .and_then(|(_, value)| value.parse().ok())
+        .unwrap_or_default();
+    let mut body = data[head_end + 4..].to_vec();
+    while body.len() < length {
+        let n = stream.read(&mut buf).await.ok().filter(|&n| n > 0)?;
+        body.extend_from_slice(&buf[..n]);
+    }
+
+    Some(Received {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
+
+/// Announcement with an html `content`, posted on 2022-01-20
+pub fn announcement(title: &str, content: &str) -> Announcement {
+    let published = UNIX_EPOCH + Duration::from_secs(1_642_672_800);
+
+    Announcement {
+        title: title.to_owned(),
+        id: String::from("tag:canvas.example.com,2022-01-20:/courses/1/discussion_topics/5"),
+        updated: published,
+        published,
+        links: vec![Link {
+            rel: String::from("alternate"),
+            href: String::from("https://canvas.example.com/courses/1/discussion_topics/5"),
+        }],
+        author: Some(Author {
+            name: String::from("Jane Doe"),
+        }),
+        content: Content {
+            content_type: String::from("html"),
+            content: content.to_owned(),
+        },
+        attachments: Vec::new(),
+        sections: Vec::new(),
+        edit: None,
+    }
+}
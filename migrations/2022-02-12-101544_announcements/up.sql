@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE announcements (
+	id		VARCHAR NOT NULL,
+	feed_id		INTEGER NOT NULL REFERENCES feeds (id),
+	title		VARCHAR NOT NULL,
+	author		VARCHAR NOT NULL,
+	link		VARCHAR NOT NULL,
+	content		TEXT NOT NULL,
+	published	TIMESTAMP NOT NULL,
+	updated		TIMESTAMP NOT NULL,
+	PRIMARY KEY (feed_id, id)
+);
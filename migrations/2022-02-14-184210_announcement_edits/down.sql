@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE subscriptions DROP COLUMN edits;
+ALTER TABLE announcements DROP COLUMN previous_content;
+ALTER TABLE announcements DROP COLUMN previous_title;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE announcements ADD COLUMN previous_title VARCHAR;
+ALTER TABLE announcements ADD COLUMN previous_content TEXT;
+ALTER TABLE subscriptions ADD COLUMN edits BOOLEAN NOT NULL DEFAULT TRUE;
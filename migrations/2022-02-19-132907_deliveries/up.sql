@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE deliveries (
+	subscription_id	INTEGER NOT NULL REFERENCES subscriptions (id),
+	feed_id		INTEGER NOT NULL,
+	announcement_id	VARCHAR NOT NULL,
+	edited		BOOLEAN NOT NULL DEFAULT FALSE,
+	delivered	TIMESTAMP,
+	PRIMARY KEY (subscription_id, feed_id, announcement_id),
+	FOREIGN KEY (feed_id, announcement_id) REFERENCES announcements (feed_id, id)
+);
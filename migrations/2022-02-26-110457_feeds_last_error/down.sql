@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE feeds DROP COLUMN last_error_at;
+ALTER TABLE feeds DROP COLUMN last_error;
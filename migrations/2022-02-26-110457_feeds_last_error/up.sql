@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE feeds ADD COLUMN last_error VARCHAR;
+ALTER TABLE feeds ADD COLUMN last_error_at TIMESTAMP;
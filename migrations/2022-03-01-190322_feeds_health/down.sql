@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE feeds DROP COLUMN disabled;
+ALTER TABLE feeds DROP COLUMN last_status;
+ALTER TABLE feeds DROP COLUMN last_success;
+ALTER TABLE feeds DROP COLUMN failures;
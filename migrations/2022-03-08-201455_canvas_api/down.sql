@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE announcements DROP COLUMN sections;
+ALTER TABLE announcements DROP COLUMN attachments;
+ALTER TABLE feeds DROP COLUMN api_token;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE feeds ADD COLUMN api_token VARCHAR;
+ALTER TABLE announcements ADD COLUMN attachments TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE announcements ADD COLUMN sections TEXT[] NOT NULL DEFAULT '{}';
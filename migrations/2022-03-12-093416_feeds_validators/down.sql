@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE feeds DROP COLUMN last_modified;
+ALTER TABLE feeds DROP COLUMN etag;
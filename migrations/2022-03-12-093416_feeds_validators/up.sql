@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE feeds ADD COLUMN etag VARCHAR;
+ALTER TABLE feeds ADD COLUMN last_modified VARCHAR;
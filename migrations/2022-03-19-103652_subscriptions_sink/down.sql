@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE subscriptions ADD COLUMN webhook_url VARCHAR;
+
+UPDATE subscriptions
+SET webhook_url = sink_config::JSON->>'url'
+WHERE sink_type = 'discord';
+
+ALTER TABLE subscriptions DROP COLUMN sink_config;
+ALTER TABLE subscriptions DROP COLUMN sink_type;
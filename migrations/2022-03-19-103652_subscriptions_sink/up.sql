@@ -0,0 +1,9 @@
+-- Your SQL goes here
+ALTER TABLE subscriptions ADD COLUMN sink_type VARCHAR NOT NULL DEFAULT 'bot';
+ALTER TABLE subscriptions ADD COLUMN sink_config TEXT;
+
+UPDATE subscriptions
+SET sink_type = 'discord', sink_config = json_build_object('url', webhook_url)::TEXT
+WHERE webhook_url IS NOT NULL;
+
+ALTER TABLE subscriptions DROP COLUMN webhook_url;
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE subscriptions ADD COLUMN filter TEXT;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE deliveries DROP COLUMN queued;
+ALTER TABLE subscriptions DROP COLUMN last_digest;
+ALTER TABLE subscriptions DROP COLUMN delivery_mode;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE subscriptions ADD COLUMN delivery_mode TEXT;
+ALTER TABLE subscriptions ADD COLUMN last_digest TIMESTAMP;
+ALTER TABLE deliveries ADD COLUMN queued TIMESTAMP NOT NULL DEFAULT (NOW() AT TIME ZONE 'UTC');
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+CREATE TABLE guild_settings (
+	server_id	VARCHAR PRIMARY KEY,
+	timezone	VARCHAR NOT NULL DEFAULT 'UTC',
+	quiet_start	VARCHAR,
+	quiet_end	VARCHAR,
+	held_until	TIMESTAMP
+);
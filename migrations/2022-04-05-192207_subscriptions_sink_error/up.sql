@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE subscriptions ADD COLUMN sink_error VARCHAR;
+ALTER TABLE subscriptions ADD COLUMN sink_error_at TIMESTAMP;
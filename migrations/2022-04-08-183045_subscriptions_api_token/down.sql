@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE feeds ADD COLUMN api_token VARCHAR;
+UPDATE feeds SET api_token = subscriptions.api_token FROM subscriptions WHERE subscriptions.feed_id = feeds.id AND subscriptions.api_token IS NOT NULL;
+ALTER TABLE subscriptions DROP COLUMN api_token;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE subscriptions ADD COLUMN api_token VARCHAR;
+UPDATE subscriptions SET api_token = feeds.api_token FROM feeds WHERE feeds.id = subscriptions.feed_id;
+ALTER TABLE feeds DROP COLUMN api_token;
@@ -0,0 +1,223 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use discord_announcements::{
+    Announcement, DbDelivery, DbSubscription, Mentions, PendingSink, Pool, SinkError, Sinks,
+};
+use tokio::task::JoinHandle;
+use tokio::time;
+
+use crate::scheduler::env_secs;
+
+/// Settings of the delivery to the sinks the server sends to itself
+#[derive(Debug, Clone)]
+pub struct DeliveryConfig {
+    /// Time between two looks for announcements to send
+    pub interval: Duration,
+}
+
+impl DeliveryConfig {
+    /// Read the config from `DELIVERY_INTERVAL` (in seconds)
+    ///
+    /// Returns `None` when `DELIVERY_INTERVAL` is not set or 0,
+    /// only the bot delivers announcements then
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let interval = env_secs("DELIVERY_INTERVAL")?.filter(|interval| !interval.is_zero());
+
+        Ok(interval.map(|interval| Self { interval }))
+    }
+}
+
+/// Sends the announcements of subscriptions with another sink than the bot
+pub struct Delivery {
+    pool: Pool,
+    sinks: Sinks,
+    config: DeliveryConfig,
+}
+
+impl Delivery {
+    pub fn new(pool: Pool, sinks: Sinks, config: DeliveryConfig) -> Arc<Self> {
+        Arc::new(Self {
+            pool,
+            sinks,
+            config,
+        })
+    }
+
+    /// Start sending in a background task
+    pub fn spawn(self: &Arc<Self>) -> JoinHandle<()> {
+        tokio::spawn(self.clone().run())
+    }
+
+    async fn run(self: Arc<Self>) {
+        let mut interval = time::interval(self.config.interval);
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            let pending = match PendingSink::get_all(&self.pool) {
+                Ok(pending) => pending,
+                Err(err) => {
+                    eprintln!("Delivery: failed to load deliveries: {err}");
+                    continue;
+                }
+            };
+
+            // Sinks side by side, the announcements of a sink one after the other
+            let tasks: Vec<_> = pending
+                .into_iter()
+                .map(|pending| tokio::spawn(self.clone().deliver(pending)))
+                .collect();
+            for task in tasks {
+                task.await.ok();
+            }
+        }
+    }
+
+    /// Send the announcements in order, the first one that fails and the ones after it
+    /// are left for the next round
+    ///
+    /// A digest is sent as one announcement, all of them are left when it fails.
+    async fn deliver(self: Arc<Self>, pending: PendingSink) {
+        if let Some(window_end) = pending.digest {
+            let scope = pending.subscription_id.to_string();
+            let digest = Announcement::digest(&scope, &pending.announcements, window_end);
+            let mentions = (!pending.channel.pings.is_empty()).then_some(&pending.channel.mentions);
+            if self.send(&pending, &digest, mentions).await {
+                for announcement in &pending.announcements {
+                    if !self.ack(&pending, announcement) {
+                        return;
+                    }
+                }
+            }
+            return;
+        }
+
+        for announcement in &pending.announcements {
+            let mentions = pending.channel.mentions_for(announcement);
+            if !self.send(&pending, announcement, mentions).await
+                || !self.ack(&pending, announcement)
+            {
+                return;
+            }
+        }
+    }
+
+    /// Send to the sink of `pending`, false when it failed and has to be sent again
+    async fn send(
+        &self,
+        pending: &PendingSink,
+        announcement: &Announcement,
+        mentions: Option<&Mentions>,
+    ) -> bool {
+        let err = match self.sinks.send(&pending.sink, announcement, mentions).await {
+            Ok(()) => return true,
+            Err(err) => err,
+        };
+
+        // Sending it again would repeat the messages that were sent, the rest is lost
+        if let SinkError::Partial(..) = err {
+            eprintln!(
+                "Delivery: only part of {} reached the {} sink of subscription {}: {err}",
+                announcement.id,
+                pending.sink.as_str(),
+                pending.subscription_id
+            );
+            return true;
+        }
+
+        // Skipped from now on, the subscription shows why until its sink is set again
+        if let SinkError::Gone(_) = err {
+            let error = err.to_string();
+            if let Err(err) =
+                DbSubscription::disable_sink(pending.subscription_id, &error, &self.pool)
+            {
+                eprintln!(
+                    "Delivery: failed to disable subscription {}: {err}",
+                    pending.subscription_id
+                );
+            }
+        }
+        eprintln!(
+            "Delivery: failed to send to the {} sink of subscription {}: {err}",
+            pending.sink.as_str(),
+            pending.subscription_id
+        );
+
+        false
+    }
+
+    fn ack(&self, pending: &PendingSink, announcement: &Announcement) -> bool {
+        match DbDelivery::ack_subscription(pending.subscription_id, &announcement.id, &self.pool) {
+            Ok(_) => true,
+            Err(err) => {
+                eprintln!("Delivery: failed to acknowledge {}: {err}", announcement.id);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::r2d2::ConnectionManager;
+    use discord_announcements::test_server::{announcement, http_client, Reply, TestServer};
+    use discord_announcements::{Channel, DiscordConfig, Sink};
+
+    /// Deliveries to a discord webhook on `server`, the db is never reached
+    fn delivery(server: &TestServer) -> (Delivery, PendingSink) {
+        let pool = Pool::builder()
+            .connection_timeout(Duration::from_millis(100))
+            .build_unchecked(ConnectionManager::new("postgres://localhost:1/unused"));
+        let delivery = Delivery {
+            pool,
+            sinks: Sinks::new(http_client(), None).unwrap(),
+            config: DeliveryConfig {
+                interval: Duration::from_secs(60),
+            },
+        };
+        let pending = PendingSink {
+            subscription_id: 1,
+            sink: Sink::Discord(DiscordConfig {
+                url: server.url("/api/webhooks/1/token"),
+            }),
+            channel: Channel::new(String::from("1"), String::from("2")),
+            announcements: Vec::new(),
+            digest: None,
+        };
+
+        (delivery, pending)
+    }
+
+    #[tokio::test]
+    async fn sent_announcement_is_done() {
+        let server = TestServer::start(vec![Reply::new(204, "")]).await;
+        let (delivery, pending) = delivery(&server);
+
+        let announcement = announcement("Exam info", "<p>Exam</p>");
+        assert!(delivery.send(&pending, &announcement, None).await);
+        assert_eq!(server.received().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_announcement_is_sent_again() {
+        let server = TestServer::start(vec![Reply::new(500, "")]).await;
+        let (delivery, pending) = delivery(&server);
+
+        let announcement = announcement("Exam info", "<p>Exam</p>");
+        assert!(!delivery.send(&pending, &announcement, None).await);
+    }
+
+    #[tokio::test]
+    async fn partly_sent_announcement_is_not_sent_again() {
+        let server = TestServer::start(vec![Reply::new(204, ""), Reply::new(500, "")]).await;
+        let (delivery, pending) = delivery(&server);
+
+        let paragraph = format!("<p>{}</p>", "Exam on Monday. ".repeat(60));
+        let announcement = announcement("Exam info", &paragraph.repeat(20));
+        assert!(delivery.send(&pending, &announcement, None).await);
+        assert_eq!(server.received().len(), 2);
+    }
+}
@@ -1,18 +1,35 @@
 use diesel::prelude::*;
 use diesel::r2d2::{self, ConnectionManager};
-use discord_announcements::{DbError, DbSubscription, Feed, Pool};
+use discord_announcements::{
+    Announcement, Channel, DbAnnouncement, DbDelivery, DbError, DbFeed, DbGuildSettings,
+    DbSubscription, DeliveryMode, DiscordMessage, Feed, Filter, HttpClient, HttpConfig, Markdown,
+    Mentions, Overflow, PendingDelivery, Pool, QuietHours, Sink, SinkError, Sinks, SmtpConfig,
+};
 use dotenv::dotenv;
+use std::sync::Arc;
 use std::time::SystemTime;
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::{transport::Server, Request, Response, Status};
 
+use delivery::{Delivery, DeliveryConfig};
 use discord_announcements::{FeedError, MyError};
 use proto_canvas_rss::canvas_rss_server::{CanvasRss, CanvasRssServer};
 use proto_canvas_rss::{
-    AnnouncementReply, FeedReply, HelloReply, HelloRequest, ListFeedsRequest,
-    NewAnnouncementsRequest, SubscribeRequest, SubscribeResponse, Subscriber,
+    delivery_mode, feed_failure, AckDeliveryRequest, AckDeliveryResponse, AnnouncementReply,
+    Attachment, Digest, FeedFailure, FeedReply, FeedStatusReply, FeedStatusRequest,
+    FeedStatusResponse, FilterReply, GetFilterRequest, GetQuietHoursRequest, HelloReply,
+    HelloRequest, ListAnnouncementsRequest, ListFeedsRequest, ListSubscriptionsRequest,
+    ListSubscriptionsResponse, NewAnnouncementsRequest, QuietHoursReply, SchedulerReply,
+    SetDeliveryModeRequest, SetEditsRequest, SetFilterRequest, SetMentionsRequest,
+    SetPollIntervalRequest, SetPollIntervalResponse, SetQuietHoursRequest, SetSchedulerRequest,
+    SetSinkRequest, SubscribeRequest, SubscribeResponse, Subscriber, SubscriptionReply,
+    WatchAnnouncementsRequest,
 };
+use scheduler::{Scheduler, SchedulerConfig};
+
+mod delivery;
+mod scheduler;
 
 pub mod proto_canvas_rss {
     tonic::include_proto!("canvasrss");
@@ -20,12 +37,19 @@ pub mod proto_canvas_rss {
 
 pub struct CanvasRssService {
     pool: Pool,
+
+    /// Shared by every request for a feed
+    http: HttpClient,
+
+    /// `None` when the scheduler is disabled
+    scheduler: Option<Arc<Scheduler>>,
 }
 
 #[tonic::async_trait]
 impl CanvasRss for CanvasRssService {
     type ListFeedsStream = ReceiverStream<Result<FeedReply, Status>>;
     type NewAnnouncementsStream = ReceiverStream<Result<FeedReply, Status>>;
+    type WatchAnnouncementsStream = ReceiverStream<Result<FeedReply, Status>>;
 
     async fn list_feeds(
         &self,
@@ -38,7 +62,7 @@ impl CanvasRss for CanvasRssService {
             },
             None => (false, SystemTime::UNIX_EPOCH),
         };
-        let feeds = if let Ok(feeds) = Feed::get_all(&self.pool).await {
+        let feeds = if let Ok(feeds) = Feed::get_all(&self.http, &self.pool).await {
             feeds
         } else {
             return Err(tonic::Status::new(
@@ -51,36 +75,31 @@ impl CanvasRss for CanvasRssService {
 
         if let Some(feeds) = feeds {
             tokio::spawn(async move {
-                //for rss_feed in &rss_feeds[..] {
-                for feed in feeds {
-                    //let mut feed = Feed::from_url(*rss_feed).await.unwrap();
-                    let mut feed = feed;
-                    if check_date {
-                        feed.after(after);
-                        if feed.announcements.is_empty() {
-                            continue;
-                        }
+                for outcome in feeds {
+                    if let Err(err) = outcome.recorded {
+                        eprintln!(
+                            "Failed to record the health of {}: {err}",
+                            outcome.db_feed.url
+                        );
                     }
 
-                    let mut announcements: Vec<AnnouncementReply> = Vec::new();
-
-                    for announcement in feed.announcements {
-                        announcements.push(AnnouncementReply {
-                            title: announcement.title,
-                            published: Some(announcement.published.into()),
-                            link: announcement.link.href,
-                            author: announcement.author.name,
-                            content: announcement.content.content,
-                        });
-                    }
+                    let reply = match outcome.result {
+                        Ok(mut feed) => {
+                            if check_date {
+                                feed.after(after);
+                                if feed.announcements.is_empty() {
+                                    continue;
+                                }
+                            }
 
-                    let feed_reply = FeedReply {
-                        id: feed.id,
-                        announcements,
-                        subscribers: Vec::new(),
+                            feed_reply(feed.id, feed.announcements, Vec::new())
+                        }
+                        Err(err) => failure_reply(outcome.db_feed.canvas_id, &err),
                     };
 
-                    tx.send(Ok(feed_reply.clone())).await.unwrap();
+                    if tx.send(Ok(reply)).await.is_err() {
+                        return;
+                    }
                 }
             });
         }
@@ -92,54 +111,199 @@ impl CanvasRss for CanvasRssService {
         &self,
         _request: tonic::Request<NewAnnouncementsRequest>,
     ) -> Result<tonic::Response<Self::NewAnnouncementsStream>, tonic::Status> {
-        let feeds = if let Ok(feeds) = Feed::get_new(&self.pool).await {
-            feeds
+        // Without scheduler the feeds are only polled on request
+        let mut failures = Vec::new();
+        if self.scheduler.is_none() {
+            let outcomes = if let Ok(outcomes) = Feed::get_new(&self.http, &self.pool).await {
+                outcomes.unwrap_or_default()
+            } else {
+                return Err(tonic::Status::new(
+                    tonic::Code::Internal,
+                    "Failed to retreive feeds",
+                ));
+            };
+
+            for outcome in outcomes {
+                if let Err(err) = outcome.recorded {
+                    eprintln!(
+                        "Failed to record the health of {}: {err}",
+                        outcome.db_feed.url
+                    );
+                }
+
+                if let Err(err) = outcome.result {
+                    failures.push(failure_reply(outcome.db_feed.canvas_id, &err));
+                }
+            }
+        }
+
+        let pending = if let Ok(pending) = PendingDelivery::get_all(&self.pool) {
+            pending
         } else {
             return Err(tonic::Status::new(
                 tonic::Code::Internal,
-                "Failed to retreive feeds",
+                "Failed to retreive pending announcements",
             ));
         };
 
         let (tx, rx) = mpsc::channel(4);
 
-        if let Some(feeds) = feeds {
-            tokio::spawn(async move {
-                for (feed, subscribers) in feeds {
-                    let mut announcements: Vec<AnnouncementReply> = Vec::new();
-
-                    for announcement in feed.announcements {
-                        announcements.push(AnnouncementReply {
-                            title: announcement.title,
-                            published: Some(announcement.published.into()),
-                            link: announcement.link.href,
-                            author: announcement.author.name,
-                            content: announcement.content.content,
-                        });
-                    }
-
-                    let subscribers = subscribers
-                        .iter()
-                        .map(|s| Subscriber {
-                            server_id: s.0.clone(),
-                            channel_id: s.1.clone(),
-                        })
-                        .collect();
-
-                    let feed_reply = FeedReply {
-                        id: feed.id,
-                        announcements,
-                        subscribers,
-                    };
+        tokio::spawn(async move {
+            for failure in failures {
+                if tx.send(Ok(failure)).await.is_err() {
+                    return;
+                }
+            }
 
-                    tx.send(Ok(feed_reply.clone())).await.unwrap();
+            for pending in pending {
+                if tx.send(Ok(pending_reply(pending))).await.is_err() {
+                    return;
                 }
-            });
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn ack_delivery(
+        &self,
+        request: tonic::Request<AckDeliveryRequest>,
+    ) -> Result<tonic::Response<AckDeliveryResponse>, tonic::Status> {
+        let ack_delivery_request = request.into_inner();
+        let subscriber = match ack_delivery_request.subscriber {
+            Some(x) => x,
+            None => Err(tonic::Status::new(
+                tonic::Code::InvalidArgument,
+                "No subscriber provided",
+            ))?,
+        };
+
+        if ack_delivery_request.feed.is_empty() {
+            Err(tonic::Status::new(
+                tonic::Code::InvalidArgument,
+                "No feed provided",
+            ))?
         }
 
+        let acknowledged = match DbDelivery::ack(
+            &subscriber.server_id,
+            &subscriber.channel_id,
+            &ack_delivery_request.feed,
+            &ack_delivery_request.announcements,
+            &self.pool,
+        ) {
+            Ok(n) => n as u32,
+            Err(_) => Err(tonic::Status::new(
+                tonic::Code::Internal,
+                "Failed to acknowledge the delivery",
+            ))?,
+        };
+
+        Ok(Response::new(AckDeliveryResponse { acknowledged }))
+    }
+
+    async fn watch_announcements(
+        &self,
+        request: tonic::Request<WatchAnnouncementsRequest>,
+    ) -> Result<tonic::Response<Self::WatchAnnouncementsStream>, tonic::Status> {
+        let scheduler = match &self.scheduler {
+            Some(scheduler) => scheduler,
+            None => Err(tonic::Status::new(
+                tonic::Code::FailedPrecondition,
+                "The scheduler is disabled",
+            ))?,
+        };
+
+        let mut cursor = request.into_inner().cursor;
+        let (history, mut live) = match scheduler.watch(cursor) {
+            Some(watch) => watch,
+            None => Err(tonic::Status::new(
+                tonic::Code::OutOfRange,
+                "Cursor is too old to resume from",
+            ))?,
+        };
+
+        let (tx, rx) = mpsc::channel(4);
+
+        tokio::spawn(async move {
+            for feed_reply in history {
+                cursor = feed_reply.cursor;
+                if tx.send(Ok(feed_reply)).await.is_err() {
+                    return;
+                }
+            }
+
+            loop {
+                match live.recv().await {
+                    // Already sent from the history
+                    Ok(feed_reply) if feed_reply.cursor <= cursor => continue,
+                    Ok(feed_reply) => {
+                        cursor = feed_reply.cursor;
+                        if tx.send(Ok(feed_reply)).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        // The client can resume from the last cursor it received
+                        let _ = tx
+                            .send(Err(tonic::Status::new(
+                                tonic::Code::ResourceExhausted,
+                                "Watcher fell behind, reconnect to resume",
+                            )))
+                            .await;
+                        return;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        });
+
         Ok(Response::new(ReceiverStream::new(rx)))
     }
 
+    async fn list_announcements(
+        &self,
+        request: tonic::Request<ListAnnouncementsRequest>,
+    ) -> Result<tonic::Response<FeedReply>, tonic::Status> {
+        let list_announcements_request = request.into_inner();
+        let after = match list_announcements_request.after {
+            Some(after) => SystemTime::try_from(after).unwrap_or(SystemTime::UNIX_EPOCH),
+            None => SystemTime::UNIX_EPOCH,
+        };
+
+        let db_feed = match DbFeed::get_by_canvas_id(&list_announcements_request.feed, &self.pool) {
+            Ok(Some(db_feed)) => db_feed,
+            Ok(None) => Err(tonic::Status::new(
+                tonic::Code::NotFound,
+                "There is no feed with that id",
+            ))?,
+            Err(_) => Err(tonic::Status::new(
+                tonic::Code::Internal,
+                "Failed to retreive feed",
+            ))?,
+        };
+
+        let announcements = match DbAnnouncement::get_by_feed_id(db_feed.id, after, &self.pool) {
+            Ok(announcements) => announcements,
+            Err(_) => Err(tonic::Status::new(
+                tonic::Code::Internal,
+                "Failed to retreive announcements",
+            ))?,
+        };
+
+        Ok(Response::new(FeedReply {
+            id: db_feed.canvas_id,
+            announcements: announcements
+                .into_iter()
+                .map(|announcement| announcement_reply(announcement.into()))
+                .collect(),
+            subscribers: Vec::new(),
+            cursor: 0,
+            error: None,
+            digest: None,
+        }))
+    }
+
     async fn say_hello(
         &self,
         request: Request<HelloRequest>,
@@ -168,14 +332,38 @@ impl CanvasRss for CanvasRssService {
             ))?,
         };
 
+        let sink = match parse_sink(&subscribe_request.sink_type, &subscribe_request.sink_config) {
+            Ok(sink) => sink,
+            Err(err) => {
+                return Ok(Response::new(SubscribeResponse {
+                    success: false,
+                    message: format!("Looks like you passed an invalid sink: {err}"),
+                }))
+            }
+        };
+
         let subscribe_response = match DbSubscription::add(
             &subscriber.server_id,
             &subscriber.channel_id,
             &subscribe_request.feed,
+            !subscribe_request.skip_edits,
+            Some(subscribe_request.canvas_token.as_str()).filter(|token| !token.is_empty()),
+            &self.http,
             &self.pool,
         )
         .await
-        {
+        .and_then(|title| {
+            if sink != Sink::Bot {
+                DbSubscription::set_sink(
+                    &subscriber.server_id,
+                    &subscriber.channel_id,
+                    &subscribe_request.feed,
+                    &sink,
+                    &self.pool,
+                )?;
+            }
+            Ok(title)
+        }) {
             Ok(title) => SubscribeResponse {
                 success: true,
                 message: format!("Placed a subscription for \'{title}\'"),
@@ -184,6 +372,10 @@ impl CanvasRss for CanvasRssService {
                 success: false,
                 message: String::from("Looks like you passed an invalid feed url"),
             },
+            Err(MyError::Feed(FeedError::Status(status))) => SubscribeResponse {
+                success: false,
+                message: format!("The feed url responded with http status {status}"),
+            },
             Err(MyError::Feed(FeedError::De(_))) => SubscribeResponse {
                 success: false,
                 message: String::from("Failed to read the url as a announcement feed"),
@@ -192,6 +384,32 @@ impl CanvasRss for CanvasRssService {
                 success: false,
                 message: String::from("This channel is already subscribed to that feed"),
             },
+            Err(MyError::Feed(FeedError::Web(_))) => SubscribeResponse {
+                success: false,
+                message: String::from("Could not reach the feed url, try again later"),
+            },
+            Err(MyError::Feed(FeedError::UnknownFormat(_))) => SubscribeResponse {
+                success: false,
+                message: String::from("The url is not an RSS or Atom feed"),
+            },
+            Err(MyError::Feed(FeedError::TooLarge(max))) => SubscribeResponse {
+                success: false,
+                message: format!("The feed is larger than the limit of {max} bytes"),
+            },
+            Err(MyError::Feed(FeedError::MissingId)) => SubscribeResponse {
+                success: false,
+                message: String::from(
+                    "The feed has no id, it can not be told apart from other feeds",
+                ),
+            },
+            Err(MyError::Db(DbError::Conflict(_))) => SubscribeResponse {
+                success: false,
+                message: String::from("That url already belongs to another feed"),
+            },
+            Err(MyError::Db(DbError::Connection(_))) => SubscribeResponse {
+                success: false,
+                message: String::from("The database is unavailable, try again later"),
+            },
             Err(_) => SubscribeResponse {
                 success: false,
                 message: String::from("Oops something went wrong"),
@@ -200,6 +418,801 @@ impl CanvasRss for CanvasRssService {
 
         Ok(Response::new(subscribe_response))
     }
+
+    async fn unsubscribe(
+        &self,
+        request: tonic::Request<SubscribeRequest>,
+    ) -> Result<tonic::Response<SubscribeResponse>, tonic::Status> {
+        let unsubscribe_request = request.into_inner();
+        let subscriber = match unsubscribe_request.subscriber {
+            Some(x) => x,
+            None => Err(tonic::Status::new(
+                tonic::Code::InvalidArgument,
+                "No subscriber provided",
+            ))?,
+        };
+
+        let unsubscribe_response = match DbSubscription::remove(
+            &subscriber.server_id,
+            &subscriber.channel_id,
+            &unsubscribe_request.feed,
+            &self.pool,
+        ) {
+            Ok(_) => SubscribeResponse {
+                success: true,
+                message: String::from("Removed the subscription"),
+            },
+            Err(DbError::NotFound) => SubscribeResponse {
+                success: false,
+                message: String::from("This channel is not subscribed to that feed"),
+            },
+            Err(_) => SubscribeResponse {
+                success: false,
+                message: String::from("Oops something went wrong"),
+            },
+        };
+
+        Ok(Response::new(unsubscribe_response))
+    }
+
+    async fn list_subscriptions(
+        &self,
+        request: tonic::Request<ListSubscriptionsRequest>,
+    ) -> Result<tonic::Response<ListSubscriptionsResponse>, tonic::Status> {
+        let list_subscriptions_request = request.into_inner();
+        let server_id = Some(list_subscriptions_request.server_id).filter(|id| !id.is_empty());
+        let channel_id = Some(list_subscriptions_request.channel_id).filter(|id| !id.is_empty());
+
+        let subscriptions =
+            match DbSubscription::list(server_id.as_deref(), channel_id.as_deref(), &self.pool) {
+                Ok(subscriptions) => subscriptions,
+                Err(_) => Err(tonic::Status::new(
+                    tonic::Code::Internal,
+                    "Failed to retreive subscriptions",
+                ))?,
+            };
+
+        let subscriptions = subscriptions
+            .into_iter()
+            .map(|(subscription, db_feed)| SubscriptionReply {
+                filter: subscription.filter().ok().map(filter_message),
+                delivery_mode: Some(delivery_mode_message(subscription.delivery_mode())),
+                sink_error: subscription.sink_error.clone().unwrap_or_default(),
+                subscriber: Some(Subscriber {
+                    mentions: Some(mentions_message(subscription.mentions(), Vec::new())),
+                    server_id: subscription.server_id,
+                    channel_id: subscription.channel_id,
+                }),
+                feed: db_feed.url,
+                id: db_feed.canvas_id,
+                title: db_feed.title,
+                last_update: Some(db_feed.last_update.into()),
+                edits: subscription.edits,
+                format: db_feed.format,
+                sink_type: subscription.sink_type,
+            })
+            .collect();
+
+        Ok(Response::new(ListSubscriptionsResponse { subscriptions }))
+    }
+
+    async fn feed_status(
+        &self,
+        request: tonic::Request<FeedStatusRequest>,
+    ) -> Result<tonic::Response<FeedStatusResponse>, tonic::Status> {
+        let feed_status_request = request.into_inner();
+        let server_id = Some(feed_status_request.server_id).filter(|id| !id.is_empty());
+        let channel_id = Some(feed_status_request.channel_id).filter(|id| !id.is_empty());
+
+        let subscriptions =
+            match DbSubscription::list(server_id.as_deref(), channel_id.as_deref(), &self.pool) {
+                Ok(subscriptions) => subscriptions,
+                Err(_) => Err(tonic::Status::new(
+                    tonic::Code::Internal,
+                    "Failed to retreive subscriptions",
+                ))?,
+            };
+
+        let feeds = subscriptions
+            .into_iter()
+            .filter(|(subscription, db_feed)| {
+                !feed_status_request.failing
+                    || db_feed.failures > 0
+                    || subscription.sink_error.is_some()
+            })
+            .map(|(subscription, db_feed)| FeedStatusReply {
+                sink_error: subscription.sink_error.unwrap_or_default(),
+                sink_error_at: subscription.sink_error_at.map(Into::into),
+                subscriber: Some(Subscriber {
+                    server_id: subscription.server_id,
+                    channel_id: subscription.channel_id,
+                    mentions: None,
+                }),
+                feed: db_feed.url,
+                id: db_feed.canvas_id,
+                title: db_feed.title,
+                failures: db_feed.failures as u32,
+                last_success: db_feed.last_success.map(Into::into),
+                last_error: db_feed.last_error.unwrap_or_default(),
+                last_error_at: db_feed.last_error_at.map(Into::into),
+                last_status: db_feed.last_status.unwrap_or_default() as u32,
+                disabled: db_feed.disabled,
+            })
+            .collect();
+
+        Ok(Response::new(FeedStatusResponse { feeds }))
+    }
+
+    async fn set_edits(
+        &self,
+        request: tonic::Request<SetEditsRequest>,
+    ) -> Result<tonic::Response<SubscribeResponse>, tonic::Status> {
+        let set_edits_request = request.into_inner();
+        let subscriber = match set_edits_request.subscriber {
+            Some(x) => x,
+            None => Err(tonic::Status::new(
+                tonic::Code::InvalidArgument,
+                "No subscriber provided",
+            ))?,
+        };
+
+        let set_edits_response = match DbSubscription::set_edits(
+            &subscriber.server_id,
+            &subscriber.channel_id,
+            &set_edits_request.feed,
+            !set_edits_request.skip_edits,
+            &self.pool,
+        ) {
+            Ok(()) if set_edits_request.skip_edits => SubscribeResponse {
+                success: true,
+                message: String::from("Edited announcements will no longer be sent"),
+            },
+            Ok(()) => SubscribeResponse {
+                success: true,
+                message: String::from("Edited announcements will be sent again"),
+            },
+            Err(DbError::NotFound) => SubscribeResponse {
+                success: false,
+                message: String::from("This channel is not subscribed to that feed"),
+            },
+            Err(_) => SubscribeResponse {
+                success: false,
+                message: String::from("Oops something went wrong"),
+            },
+        };
+
+        Ok(Response::new(set_edits_response))
+    }
+
+    async fn set_filter(
+        &self,
+        request: tonic::Request<SetFilterRequest>,
+    ) -> Result<tonic::Response<SubscribeResponse>, tonic::Status> {
+        let set_filter_request = request.into_inner();
+        let subscriber = match set_filter_request.subscriber {
+            Some(x) => x,
+            None => Err(tonic::Status::new(
+                tonic::Code::InvalidArgument,
+                "No subscriber provided",
+            ))?,
+        };
+
+        let filter = set_filter_request
+            .filter
+            .map(filter_from_message)
+            .unwrap_or_default();
+        if let Err(err) = filter.validate() {
+            return Ok(Response::new(SubscribeResponse {
+                success: false,
+                message: format!("Looks like you passed an invalid regex: {err}"),
+            }));
+        }
+
+        let set_filter_response = match DbSubscription::set_filter(
+            &subscriber.server_id,
+            &subscriber.channel_id,
+            &set_filter_request.feed,
+            &filter,
+            &self.pool,
+        ) {
+            Ok(()) if filter.is_empty() => SubscribeResponse {
+                success: true,
+                message: String::from("All announcements will be sent again"),
+            },
+            Ok(()) => SubscribeResponse {
+                success: true,
+                message: String::from("Only announcements that pass the filter will be sent"),
+            },
+            Err(DbError::NotFound) => SubscribeResponse {
+                success: false,
+                message: String::from("This channel is not subscribed to that feed"),
+            },
+            Err(_) => SubscribeResponse {
+                success: false,
+                message: String::from("Oops something went wrong"),
+            },
+        };
+
+        Ok(Response::new(set_filter_response))
+    }
+
+    async fn get_filter(
+        &self,
+        request: tonic::Request<GetFilterRequest>,
+    ) -> Result<tonic::Response<FilterReply>, tonic::Status> {
+        let get_filter_request = request.into_inner();
+        let subscriber = match get_filter_request.subscriber {
+            Some(x) => x,
+            None => Err(tonic::Status::new(
+                tonic::Code::InvalidArgument,
+                "No subscriber provided",
+            ))?,
+        };
+
+        let filter_reply = match DbSubscription::find(
+            &subscriber.server_id,
+            &subscriber.channel_id,
+            &get_filter_request.feed,
+            &self.pool,
+        ) {
+            Ok(Some(subscription)) => match subscription.filter() {
+                Ok(filter) => FilterReply {
+                    success: true,
+                    message: String::new(),
+                    filter: Some(filter_message(filter)),
+                },
+                Err(_) => FilterReply {
+                    success: false,
+                    message: String::from("The stored filter is broken, set it again"),
+                    filter: None,
+                },
+            },
+            Ok(None) => FilterReply {
+                success: false,
+                message: String::from("This channel is not subscribed to that feed"),
+                filter: None,
+            },
+            Err(_) => FilterReply {
+                success: false,
+                message: String::from("Oops something went wrong"),
+                filter: None,
+            },
+        };
+
+        Ok(Response::new(filter_reply))
+    }
+
+    async fn set_delivery_mode(
+        &self,
+        request: tonic::Request<SetDeliveryModeRequest>,
+    ) -> Result<tonic::Response<SubscribeResponse>, tonic::Status> {
+        let set_delivery_mode_request = request.into_inner();
+        let subscriber = match set_delivery_mode_request.subscriber {
+            Some(x) => x,
+            None => Err(tonic::Status::new(
+                tonic::Code::InvalidArgument,
+                "No subscriber provided",
+            ))?,
+        };
+
+        let delivery_mode = match set_delivery_mode_request.delivery_mode {
+            Some(delivery_mode) => delivery_mode_from_message(delivery_mode),
+            None => DeliveryMode::Immediate,
+        };
+        if let Err(err) = delivery_mode.validate() {
+            return Ok(Response::new(SubscribeResponse {
+                success: false,
+                message: format!("Looks like you passed an {err}"),
+            }));
+        }
+
+        let set_delivery_mode_response = match DbSubscription::set_delivery_mode(
+            &subscriber.server_id,
+            &subscriber.channel_id,
+            &set_delivery_mode_request.feed,
+            &delivery_mode,
+            &self.pool,
+        ) {
+            Ok(()) => SubscribeResponse {
+                success: true,
+                message: match &delivery_mode {
+                    DeliveryMode::Immediate => {
+                        String::from("Announcements will be sent as soon as they are found")
+                    }
+                    DeliveryMode::Hourly => {
+                        String::from("Announcements will be sent as a digest every hour")
+                    }
+                    DeliveryMode::Daily { at, timezone } => format!(
+                        "Announcements will be sent as a digest every day at {at} ({timezone})"
+                    ),
+                },
+            },
+            Err(DbError::NotFound) => SubscribeResponse {
+                success: false,
+                message: String::from("This channel is not subscribed to that feed"),
+            },
+            Err(_) => SubscribeResponse {
+                success: false,
+                message: String::from("Oops something went wrong"),
+            },
+        };
+
+        Ok(Response::new(set_delivery_mode_response))
+    }
+
+    async fn set_quiet_hours(
+        &self,
+        request: tonic::Request<SetQuietHoursRequest>,
+    ) -> Result<tonic::Response<SubscribeResponse>, tonic::Status> {
+        let set_quiet_hours_request = request.into_inner();
+        let quiet_hours = set_quiet_hours_request
+            .quiet_hours
+            .map(quiet_hours_from_message);
+        if let Some(Err(err)) = quiet_hours.as_ref().map(QuietHours::validate) {
+            return Ok(Response::new(SubscribeResponse {
+                success: false,
+                message: format!("Looks like you passed an {err}"),
+            }));
+        }
+
+        let set_quiet_hours_response = match DbGuildSettings::set_quiet_hours(
+            &set_quiet_hours_request.server_id,
+            quiet_hours.as_ref(),
+            &self.pool,
+        ) {
+            Ok(()) => SubscribeResponse {
+                success: true,
+                message: match &quiet_hours {
+                    Some(quiet) => format!(
+                        "Announcements will be held from {} to {} ({})",
+                        quiet.start, quiet.end, quiet.timezone
+                    ),
+                    None => String::from("Announcements will be sent at any time of the day"),
+                },
+            },
+            Err(_) => SubscribeResponse {
+                success: false,
+                message: String::from("Oops something went wrong"),
+            },
+        };
+
+        Ok(Response::new(set_quiet_hours_response))
+    }
+
+    async fn get_quiet_hours(
+        &self,
+        request: tonic::Request<GetQuietHoursRequest>,
+    ) -> Result<tonic::Response<QuietHoursReply>, tonic::Status> {
+        let get_quiet_hours_request = request.into_inner();
+
+        let settings = match DbGuildSettings::get(&get_quiet_hours_request.server_id, &self.pool) {
+            Ok(settings) => settings,
+            Err(_) => Err(tonic::Status::new(
+                tonic::Code::Internal,
+                "Failed to retreive quiet hours",
+            ))?,
+        };
+
+        let quiet_hours_reply = match settings {
+            Some(settings) => QuietHoursReply {
+                until: settings.quiet_until(SystemTime::now()).map(Into::into),
+                quiet_hours: settings.quiet_hours().map(quiet_hours_message),
+            },
+            None => QuietHoursReply::default(),
+        };
+
+        Ok(Response::new(quiet_hours_reply))
+    }
+
+    async fn set_mentions(
+        &self,
+        request: tonic::Request<SetMentionsRequest>,
+    ) -> Result<tonic::Response<SubscribeResponse>, tonic::Status> {
+        let set_mentions_request = request.into_inner();
+        let subscriber = match set_mentions_request.subscriber {
+            Some(x) => x,
+            None => Err(tonic::Status::new(
+                tonic::Code::InvalidArgument,
+                "No subscriber provided",
+            ))?,
+        };
+
+        let mentions = set_mentions_request
+            .mentions
+            .map(mentions_from_message)
+            .unwrap_or_default();
+        if let Err(err) = mentions.validate() {
+            return Ok(Response::new(SubscribeResponse {
+                success: false,
+                message: format!("Looks like you passed an {err}"),
+            }));
+        }
+
+        let set_mentions_response = match DbSubscription::set_mentions(
+            &subscriber.server_id,
+            &subscriber.channel_id,
+            &set_mentions_request.feed,
+            &mentions,
+            &self.pool,
+        ) {
+            Ok(()) if mentions.is_empty() => SubscribeResponse {
+                success: true,
+                message: String::from("Announcements will be posted without a ping"),
+            },
+            Ok(()) if mentions.only_keywords => SubscribeResponse {
+                success: true,
+                message: String::from(
+                    "Announcements with a keyword of the filter will ping the mentions",
+                ),
+            },
+            Ok(()) => SubscribeResponse {
+                success: true,
+                message: String::from("Announcements will ping the mentions"),
+            },
+            Err(DbError::NotFound) => SubscribeResponse {
+                success: false,
+                message: String::from("This channel is not subscribed to that feed"),
+            },
+            Err(_) => SubscribeResponse {
+                success: false,
+                message: String::from("Oops something went wrong"),
+            },
+        };
+
+        Ok(Response::new(set_mentions_response))
+    }
+
+    async fn set_sink(
+        &self,
+        request: tonic::Request<SetSinkRequest>,
+    ) -> Result<tonic::Response<SubscribeResponse>, tonic::Status> {
+        let set_sink_request = request.into_inner();
+        let subscriber = match set_sink_request.subscriber {
+            Some(x) => x,
+            None => Err(tonic::Status::new(
+                tonic::Code::InvalidArgument,
+                "No subscriber provided",
+            ))?,
+        };
+
+        let sink = match parse_sink(&set_sink_request.sink_type, &set_sink_request.sink_config) {
+            Ok(sink) => sink,
+            Err(err) => {
+                return Ok(Response::new(SubscribeResponse {
+                    success: false,
+                    message: format!("Looks like you passed an invalid sink: {err}"),
+                }))
+            }
+        };
+
+        let set_sink_response = match DbSubscription::set_sink(
+            &subscriber.server_id,
+            &subscriber.channel_id,
+            &set_sink_request.feed,
+            &sink,
+            &self.pool,
+        ) {
+            Ok(()) if sink == Sink::Bot => SubscribeResponse {
+                success: true,
+                message: String::from("Announcements will be sent by the bot again"),
+            },
+            Ok(()) => SubscribeResponse {
+                success: true,
+                message: format!("Announcements will be sent to {}", sink.as_str()),
+            },
+            Err(DbError::NotFound) => SubscribeResponse {
+                success: false,
+                message: String::from("This channel is not subscribed to that feed"),
+            },
+            Err(_) => SubscribeResponse {
+                success: false,
+                message: String::from("Oops something went wrong"),
+            },
+        };
+
+        Ok(Response::new(set_sink_response))
+    }
+
+    async fn set_scheduler(
+        &self,
+        request: tonic::Request<SetSchedulerRequest>,
+    ) -> Result<tonic::Response<SchedulerReply>, tonic::Status> {
+        let scheduler = match &self.scheduler {
+            Some(scheduler) => scheduler,
+            None => {
+                return Ok(Response::new(SchedulerReply {
+                    enabled: false,
+                    paused: false,
+                }))
+            }
+        };
+
+        if request.into_inner().paused {
+            scheduler.pause();
+        } else {
+            scheduler.resume();
+        }
+
+        Ok(Response::new(SchedulerReply {
+            enabled: true,
+            paused: scheduler.is_paused(),
+        }))
+    }
+
+    async fn set_poll_interval(
+        &self,
+        request: tonic::Request<SetPollIntervalRequest>,
+    ) -> Result<tonic::Response<SetPollIntervalResponse>, tonic::Status> {
+        let set_poll_interval_request = request.into_inner();
+        let interval = match set_poll_interval_request.seconds {
+            0 => None,
+            secs => match i32::try_from(secs) {
+                Ok(secs) => Some(secs),
+                Err(_) => Err(tonic::Status::new(
+                    tonic::Code::InvalidArgument,
+                    "Poll interval is too large",
+                ))?,
+            },
+        };
+
+        let set_poll_interval_response = match DbFeed::set_poll_interval(
+            &set_poll_interval_request.feed,
+            interval,
+            &self.pool,
+        ) {
+            Ok(()) => SetPollIntervalResponse {
+                success: true,
+                message: String::from("Changed the poll interval"),
+            },
+            Err(DbError::NotFound) => SetPollIntervalResponse {
+                success: false,
+                message: String::from("There is no feed with that id"),
+            },
+            Err(_) => SetPollIntervalResponse {
+                success: false,
+                message: String::from("Oops something went wrong"),
+            },
+        };
+
+        Ok(Response::new(set_poll_interval_response))
+    }
+}
+
+fn filter_message(filter: Filter) -> proto_canvas_rss::Filter {
+    proto_canvas_rss::Filter {
+        include: filter.include,
+        exclude: filter.exclude,
+        title_regex: filter.title_regex.unwrap_or_default(),
+        content_regex: filter.content_regex.unwrap_or_default(),
+        authors: filter.authors,
+    }
+}
+
+/// Blank keywords and authors and empty regexes are left out
+fn filter_from_message(filter: proto_canvas_rss::Filter) -> Filter {
+    let non_empty = |strings: Vec<String>| {
+        strings
+            .into_iter()
+            .map(|s| s.trim().to_owned())
+            .filter(|s| !s.is_empty())
+            .collect()
+    };
+
+    Filter {
+        include: non_empty(filter.include),
+        exclude: non_empty(filter.exclude),
+        title_regex: Some(filter.title_regex).filter(|regex| !regex.is_empty()),
+        content_regex: Some(filter.content_regex).filter(|regex| !regex.is_empty()),
+        authors: non_empty(filter.authors),
+    }
+}
+
+fn quiet_hours_message(quiet_hours: QuietHours) -> proto_canvas_rss::QuietHours {
+    proto_canvas_rss::QuietHours {
+        start: quiet_hours.start,
+        end: quiet_hours.end,
+        timezone: quiet_hours.timezone,
+    }
+}
+
+fn quiet_hours_from_message(quiet_hours: proto_canvas_rss::QuietHours) -> QuietHours {
+    QuietHours {
+        start: quiet_hours.start.trim().to_owned(),
+        end: quiet_hours.end.trim().to_owned(),
+        timezone: quiet_hours.timezone.trim().to_owned(),
+    }
+}
+
+fn delivery_mode_message(delivery_mode: DeliveryMode) -> proto_canvas_rss::DeliveryMode {
+    let (mode, at, timezone) = match delivery_mode {
+        DeliveryMode::Immediate => (delivery_mode::Mode::Immediate, String::new(), String::new()),
+        DeliveryMode::Hourly => (delivery_mode::Mode::Hourly, String::new(), String::new()),
+        DeliveryMode::Daily { at, timezone } => (delivery_mode::Mode::Daily, at, timezone),
+    };
+
+    proto_canvas_rss::DeliveryMode {
+        mode: mode as i32,
+        at,
+        timezone,
+    }
+}
+
+/// An unknown mode is immediate
+fn delivery_mode_from_message(delivery_mode: proto_canvas_rss::DeliveryMode) -> DeliveryMode {
+    match delivery_mode::Mode::from_i32(delivery_mode.mode) {
+        Some(delivery_mode::Mode::Hourly) => DeliveryMode::Hourly,
+        Some(delivery_mode::Mode::Daily) => DeliveryMode::Daily {
+            at: delivery_mode.at.trim().to_owned(),
+            timezone: delivery_mode.timezone.trim().to_owned(),
+        },
+        _ => DeliveryMode::Immediate,
+    }
+}
+
+/// Mentions of a subscription, `announcements` are the ones that ping
+fn mentions_message(mentions: Mentions, announcements: Vec<String>) -> proto_canvas_rss::Mentions {
+    proto_canvas_rss::Mentions {
+        role_ids: mentions.roles,
+        user_ids: mentions.users,
+        here: mentions.here,
+        only_keywords: mentions.only_keywords,
+        announcements,
+    }
+}
+
+/// Blank role and user ids are left out
+fn mentions_from_message(mentions: proto_canvas_rss::Mentions) -> Mentions {
+    let ids = |ids: Vec<String>| {
+        ids.into_iter()
+            .map(|id| id.trim().to_owned())
+            .filter(|id| !id.is_empty())
+            .collect()
+    };
+
+    Mentions {
+        roles: ids(mentions.role_ids),
+        users: ids(mentions.user_ids),
+        here: mentions.here,
+        only_keywords: mentions.only_keywords,
+    }
+}
+
+/// The sink of a request, the bot when no type is given
+fn parse_sink(sink_type: &str, sink_config: &str) -> Result<Sink, SinkError> {
+    if sink_type.is_empty() {
+        return Ok(Sink::Bot);
+    }
+
+    let sink = Sink::from_db(
+        sink_type,
+        Some(sink_config).filter(|config| !config.is_empty()),
+    )?;
+    sink.validate()?;
+
+    Ok(sink)
+}
+
+/// Convert the announcements of a feed and their subscribers into a reply
+pub fn feed_reply(
+    id: String,
+    announcements: Vec<Announcement>,
+    subscribers: Vec<Channel>,
+) -> FeedReply {
+    let announcements = announcements.into_iter().map(announcement_reply).collect();
+
+    let subscribers = subscribers
+        .into_iter()
+        .map(|s| Subscriber {
+            mentions: Some(mentions_message(s.mentions, s.pings)),
+            server_id: s.server_id,
+            channel_id: s.channel_id,
+        })
+        .collect();
+
+    FeedReply {
+        id,
+        announcements,
+        subscribers,
+        cursor: 0,
+        error: None,
+        digest: None,
+    }
+}
+
+/// Reply for announcements waiting for their delivery, with the digest of the window of a digest
+pub fn pending_reply(pending: PendingDelivery) -> FeedReply {
+    let digest = pending.digest.map(|window_end| Digest {
+        window_end: Some(window_end.into()),
+        messages: Announcement::digest(&pending.feed_id, &pending.announcements, window_end)
+            .render(Overflow::Split)
+            .iter()
+            .map(DiscordMessage::to_json)
+            .collect(),
+    });
+
+    FeedReply {
+        digest,
+        ..feed_reply(pending.feed_id, pending.announcements, pending.channels)
+    }
+}
+
+/// Reply for a feed that could not be retrieved
+pub fn failure_reply(id: String, err: &MyError) -> FeedReply {
+    let (kind, status) = match err {
+        MyError::Feed(FeedError::De(_)) => (feed_failure::Kind::Deserialization, 0),
+        MyError::Feed(FeedError::Web(_)) => (feed_failure::Kind::Web, 0),
+        MyError::Feed(FeedError::InvalidFeedUrl(_)) => (feed_failure::Kind::InvalidUrl, 0),
+        MyError::Feed(FeedError::Status(status)) => (feed_failure::Kind::Status, *status),
+        MyError::Db(_) => (feed_failure::Kind::Database, 0),
+        _ => (feed_failure::Kind::Generic, 0),
+    };
+
+    FeedReply {
+        id,
+        announcements: Vec::new(),
+        subscribers: Vec::new(),
+        cursor: 0,
+        error: Some(FeedFailure {
+            kind: kind as i32,
+            message: err.to_string(),
+            status: status as u32,
+        }),
+        digest: None,
+    }
+}
+
+pub fn announcement_reply(announcement: Announcement) -> AnnouncementReply {
+    let link = announcement.href().to_owned();
+    let author = announcement.author_name().to_owned();
+
+    let markdown = announcement.markdown();
+    let base = announcement.base();
+    let messages = announcement
+        .render(Overflow::Split)
+        .iter()
+        .map(DiscordMessage::to_json)
+        .collect();
+
+    let (edited, previous_title, previous_content, previous_content_markdown) =
+        match announcement.edit {
+            Some(edit) => {
+                let previous = Markdown::from_content(
+                    &announcement.content.content_type,
+                    &edit.content,
+                    base.as_ref(),
+                );
+                (true, edit.title, edit.content, previous.text)
+            }
+            None => (false, String::new(), String::new(), String::new()),
+        };
+
+    AnnouncementReply {
+        title: announcement.title,
+        published: Some(announcement.published.into()),
+        link,
+        author,
+        content: announcement.content.content,
+        id: announcement.id,
+        updated: Some(announcement.updated.into()),
+        edited,
+        previous_title,
+        previous_content,
+        attachments: announcement
+            .attachments
+            .into_iter()
+            .map(|attachment| Attachment {
+                name: attachment.name,
+                url: attachment.url,
+                content_type: attachment.content_type,
+                size: attachment.size,
+            })
+            .collect(),
+        sections: announcement.sections,
+        content_markdown: markdown.text,
+        images: markdown.images,
+        previous_content_markdown,
+        messages,
+    }
 }
 
 #[tokio::main]
@@ -209,9 +1222,25 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     let manager = ConnectionManager::<PgConnection>::new(database_url);
     let pool: Pool = r2d2::Pool::builder().build(manager).unwrap();
+    let http = HttpClient::new(HttpConfig::from_env()?)?;
+
+    let scheduler = SchedulerConfig::from_env()?.map(|config| {
+        let scheduler = Scheduler::new(pool.clone(), http.clone(), config);
+        scheduler.spawn();
+        scheduler
+    });
+
+    if let Some(config) = DeliveryConfig::from_env()? {
+        let sinks = Sinks::new(http.clone(), SmtpConfig::from_env()?)?;
+        Delivery::new(pool.clone(), sinks, config).spawn();
+    }
 
     let addr = "[::1]:50051".parse()?;
-    let canvas_rss = CanvasRssService { pool };
+    let canvas_rss = CanvasRssService {
+        pool,
+        http,
+        scheduler,
+    };
 
     Server::builder()
         .add_service(CanvasRssServer::new(canvas_rss))
@@ -0,0 +1,519 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use discord_announcements::{DbFeed, Feed, HttpClient, PendingDelivery, Pool};
+use rand::Rng;
+use tokio::sync::{broadcast, watch};
+use tokio::task::JoinHandle;
+use tokio::time;
+
+use crate::proto_canvas_rss::FeedReply;
+use crate::{failure_reply, feed_reply, pending_reply};
+
+/// How often the list of feeds is reloaded from the db
+/// and the digests and the announcements held by quiet hours are looked for
+const SYNC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Longest time between two polls of a failing feed
+const MAX_BACKOFF: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Number of found feeds kept around for watchers that reconnect
+const HISTORY_SIZE: usize = 1024;
+
+/// Settings of the scheduler
+#[derive(Debug, Clone)]
+pub struct SchedulerConfig {
+    /// Time between two polls of a feed without its own `poll_interval`
+    pub interval: Duration,
+
+    /// Maximum random delay added to every poll
+    pub jitter: Duration,
+}
+
+impl SchedulerConfig {
+    /// Read the config from `POLL_INTERVAL` and `POLL_JITTER` (in seconds)
+    ///
+    /// Returns `None` when `POLL_INTERVAL` is not set or 0, this disables the scheduler
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let interval = match env_secs("POLL_INTERVAL")?.filter(|interval| !interval.is_zero()) {
+            Some(interval) => interval,
+            None => return Ok(None),
+        };
+        let jitter = env_secs("POLL_JITTER")?.unwrap_or(Duration::ZERO);
+
+        Ok(Some(Self { interval, jitter }))
+    }
+
+    /// Time between two polls of a feed with its own `poll_interval` in seconds
+    fn interval(&self, poll_interval: Option<i32>) -> Duration {
+        match poll_interval {
+            Some(secs) if secs > 0 => Duration::from_secs(secs as u64),
+            _ => self.interval,
+        }
+    }
+
+    /// Random delay of at most `jitter`
+    fn jitter(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return Duration::ZERO;
+        }
+
+        let max = self.jitter.as_millis() as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(0..=max))
+    }
+}
+
+/// Seconds in `key`, `None` when it is not set
+pub(crate) fn env_secs(key: &str) -> Result<Option<Duration>, String> {
+    let secs = match std::env::var(key) {
+        Ok(secs) => secs,
+        Err(_) => return Ok(None),
+    };
+    let secs: u64 = secs
+        .parse()
+        .map_err(|_| format!("{key} must be a number of seconds"))?;
+
+    Ok(Some(Duration::from_secs(secs)))
+}
+
+/// Feeds found by the scheduler, numbered by their cursor
+///
+/// Only kept in memory: after a restart or once a feed dropped out, watchers can not resume
+/// from their cursor and catch up with the deliveries that were not acknowledged instead.
+struct History {
+    /// Cursor of the next found feed
+    next_cursor: u64,
+
+    /// Highest cursor that dropped out of `replies`, the first cursor of this process
+    /// before that so cursors of a previous run are out of range
+    dropped: u64,
+
+    replies: VecDeque<FeedReply>,
+}
+
+/// Polls every feed in the background and hands the new announcements to watchers
+pub struct Scheduler {
+    pool: Pool,
+    http: HttpClient,
+    config: SchedulerConfig,
+    paused: watch::Sender<bool>,
+    history: Mutex<History>,
+    live: broadcast::Sender<FeedReply>,
+}
+
+impl Scheduler {
+    pub fn new(pool: Pool, http: HttpClient, config: SchedulerConfig) -> Arc<Self> {
+        let (paused, _) = watch::channel(false);
+        let (live, _) = broadcast::channel(64);
+
+        // Start counting at the current time, this keeps cursors increasing across restarts
+        let start = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+
+        Arc::new(Self {
+            pool,
+            http,
+            config,
+            paused,
+            history: Mutex::new(History {
+                next_cursor: start,
+                dropped: start,
+                replies: VecDeque::new(),
+            }),
+            live,
+        })
+    }
+
+    /// Start polling in a background task
+    pub fn spawn(self: &Arc<Self>) -> JoinHandle<()> {
+        tokio::spawn(self.clone().run())
+    }
+
+    pub fn pause(&self) {
+        self.paused.send_replace(true);
+    }
+
+    pub fn resume(&self) {
+        self.paused.send_replace(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Get everything found after `cursor` and a receiver for what is found next
+    ///
+    /// `cursor` 0 skips the history. Returns `None` when feeds found after
+    /// `cursor` are no longer in the history, `PendingDelivery` still has what was missed.
+    pub fn watch(&self, cursor: u64) -> Option<(Vec<FeedReply>, broadcast::Receiver<FeedReply>)> {
+        // Subscribe while holding the lock so no feed is missed or sent twice
+        let history = self.history.lock().unwrap();
+        let live = self.live.subscribe();
+
+        if cursor == 0 {
+            return Some((Vec::new(), live));
+        }
+
+        if cursor < history.dropped {
+            return None;
+        }
+
+        let replies = history
+            .replies
+            .iter()
+            .filter(|reply| reply.cursor > cursor)
+            .cloned()
+            .collect();
+
+        Some((replies, live))
+    }
+
+    /// Hand a found feed to everyone waiting for it
+    fn publish(&self, mut reply: FeedReply) {
+        let mut history = self.history.lock().unwrap();
+
+        reply.cursor = history.next_cursor;
+        history.next_cursor += 1;
+
+        history.replies.push_back(reply.clone());
+        if history.replies.len() > HISTORY_SIZE {
+            if let Some(dropped) = history.replies.pop_front() {
+                history.dropped = dropped.cursor;
+            }
+        }
+
+        // Only fails when nobody is watching
+        let _ = self.live.send(reply);
+    }
+
+    /// Keep a polling task running for every feed in the db
+    async fn run(self: Arc<Self>) {
+        let mut tasks: HashMap<i32, JoinHandle<()>> = HashMap::new();
+        let mut sync = time::interval(SYNC_INTERVAL);
+
+        loop {
+            sync.tick().await;
+
+            if !self.is_paused() {
+                self.publish_digests();
+                self.publish_released();
+            }
+
+            let db_feeds = match DbFeed::get_active(&self.pool) {
+                Ok(db_feeds) => db_feeds.unwrap_or_default(),
+                Err(err) => {
+                    eprintln!("Scheduler: failed to load feeds: {err}");
+                    continue;
+                }
+            };
+
+            // Stop polling feeds that are gone or disabled,
+            // forget finished tasks so enabled feeds are polled again
+            tasks.retain(|id, task| {
+                let exists = db_feeds.iter().any(|db_feed| db_feed.id == *id);
+                if !exists {
+                    task.abort();
+                }
+                exists && !task.is_finished()
+            });
+
+            for db_feed in db_feeds {
+                tasks
+                    .entry(db_feed.id)
+                    .or_insert_with(|| tokio::spawn(self.clone().poll(db_feed.id)));
+            }
+        }
+    }
+
+    /// Hand the digests of the windows that ended to the watchers
+    fn publish_digests(&self) {
+        match PendingDelivery::take_digests(&self.pool) {
+            Ok(digests) => {
+                for pending in digests {
+                    self.publish(pending_reply(pending));
+                }
+            }
+            Err(err) => eprintln!("Scheduler: failed to load digests: {err}"),
+        }
+    }
+
+    /// Hand the announcements held by quiet hours that ended to the watchers
+    fn publish_released(&self) {
+        match PendingDelivery::take_released(&self.pool) {
+            Ok(released) => {
+                for pending in released {
+                    self.publish(pending_reply(pending));
+                }
+            }
+            Err(err) => eprintln!("Scheduler: failed to load released announcements: {err}"),
+        }
+    }
+
+    /// Poll a single feed forever
+    async fn poll(self: Arc<Self>, id: i32) {
+        // Spread the first polls so not every feed is fetched at once
+        time::sleep(self.jitter()).await;
+
+        loop {
+            self.wait_resumed().await;
+
+            // Reload the feed every time for a fresh `last_update` and `poll_interval`
+            let db_feed = match DbFeed::get_by_id(id, &self.pool) {
+                Ok(Some(db_feed)) => db_feed,
+                Ok(None) => return,
+                Err(err) => {
+                    eprintln!("Scheduler: failed to load feed {id}: {err}");
+                    time::sleep(self.config.interval + self.jitter()).await;
+                    continue;
+                }
+            };
+
+            let outcome = Feed::get_new_for(&db_feed, &self.http, &self.pool).await;
+            if let Err(err) = outcome.recorded {
+                eprintln!(
+                    "Scheduler: failed to record the health of {}: {err}",
+                    db_feed.url
+                );
+            }
+
+            match outcome.result {
+                Ok(found) => {
+                    for (feed, channels) in found {
+                        self.publish(feed_reply(feed.id, feed.announcements, channels));
+                    }
+                }
+                Err(err) => {
+                    eprintln!("Scheduler: failed to poll {}: {err}", db_feed.url);
+                    self.publish(failure_reply(db_feed.canvas_id.clone(), &err));
+                }
+            }
+
+            // Reload the feed for the health of this poll
+            let db_feed = match DbFeed::get_by_id(id, &self.pool) {
+                Ok(Some(db_feed)) => db_feed,
+                Ok(None) => return,
+                Err(_) => db_feed,
+            };
+
+            if db_feed.disabled {
+                eprintln!(
+                    "Scheduler: disabled {} after {} failures",
+                    db_feed.url, db_feed.failures
+                );
+                return;
+            }
+
+            time::sleep(self.backoff(&db_feed) + self.jitter()).await;
+        }
+    }
+
+    async fn wait_resumed(&self) {
+        let mut paused = self.paused.subscribe();
+
+        while *paused.borrow_and_update() {
+            if paused.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    fn interval(&self, db_feed: &DbFeed) -> Duration {
+        self.config.interval(db_feed.poll_interval)
+    }
+
+    fn backoff(&self, db_feed: &DbFeed) -> Duration {
+        backoff(self.interval(db_feed), db_feed.failures)
+    }
+
+    fn jitter(&self) -> Duration {
+        self.config.jitter()
+    }
+}
+
+/// Double `interval` for every failure in a row, up to `MAX_BACKOFF`
+fn backoff(interval: Duration, failures: i32) -> Duration {
+    if failures <= 0 {
+        return interval;
+    }
+
+    let factor = 1u32 << failures.min(16);
+    interval
+        .saturating_mul(factor)
+        .min(MAX_BACKOFF.max(interval))
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::r2d2::ConnectionManager;
+    use discord_announcements::test_server::http_client;
+    use std::collections::HashSet;
+    use tokio_stream::StreamExt;
+    use tonic::{Code, Request};
+
+    use super::*;
+    use crate::proto_canvas_rss::canvas_rss_server::CanvasRss;
+    use crate::proto_canvas_rss::WatchAnnouncementsRequest;
+    use crate::CanvasRssService;
+
+    fn config(interval: u64, jitter: u64) -> SchedulerConfig {
+        SchedulerConfig {
+            interval: Duration::from_secs(interval),
+            jitter: Duration::from_secs(jitter),
+        }
+    }
+
+    #[test]
+    fn interval_of_the_feed() {
+        let config = config(300, 0);
+
+        assert_eq!(config.interval(Some(60)), Duration::from_secs(60));
+        assert_eq!(config.interval(None), Duration::from_secs(300));
+        assert_eq!(config.interval(Some(0)), Duration::from_secs(300));
+        assert_eq!(config.interval(Some(-5)), Duration::from_secs(300));
+    }
+
+    #[test]
+    fn backoff_doubles_per_failure() {
+        let interval = Duration::from_secs(300);
+
+        assert_eq!(backoff(interval, 0), interval);
+        assert_eq!(backoff(interval, -1), interval);
+        assert_eq!(backoff(interval, 1), Duration::from_secs(600));
+        assert_eq!(backoff(interval, 3), Duration::from_secs(2400));
+    }
+
+    #[test]
+    fn backoff_up_to_a_day() {
+        let interval = Duration::from_secs(300);
+
+        assert_eq!(backoff(interval, 9), MAX_BACKOFF);
+        assert_eq!(backoff(interval, 1000), MAX_BACKOFF);
+
+        // Never shorter than the interval itself
+        let weekly = Duration::from_secs(7 * 24 * 60 * 60);
+        assert_eq!(backoff(weekly, 5), weekly);
+    }
+
+    #[test]
+    fn jitter_within_the_maximum() {
+        assert_eq!(config(300, 0).jitter(), Duration::ZERO);
+
+        let config = config(300, 2);
+        for _ in 0..1000 {
+            assert!(config.jitter() <= Duration::from_secs(2));
+        }
+    }
+
+    #[test]
+    fn jitter_spreads_the_polls() {
+        let config = config(300, 30);
+
+        let jitters: HashSet<Duration> = (0..100).map(|_| config.jitter()).collect();
+
+        assert!(jitters.len() > 1);
+    }
+
+    fn scheduler() -> Arc<Scheduler> {
+        let pool = Pool::builder()
+            .connection_timeout(Duration::from_millis(100))
+            .build_unchecked(ConnectionManager::new("postgres://localhost:1/unused"));
+
+        Scheduler::new(pool, http_client(), config(300, 0))
+    }
+
+    fn found(id: &str) -> FeedReply {
+        FeedReply {
+            id: id.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn cursors(scheduler: &Scheduler) -> Vec<u64> {
+        let history = scheduler.history.lock().unwrap();
+
+        history.replies.iter().map(|reply| reply.cursor).collect()
+    }
+
+    #[test]
+    fn resumes_after_the_cursor() {
+        let scheduler = scheduler();
+        for id in ["a", "b", "c"] {
+            scheduler.publish(found(id));
+        }
+        let cursors = cursors(&scheduler);
+
+        let (replies, _) = scheduler.watch(cursors[0]).unwrap();
+        let ids: Vec<&str> = replies.iter().map(|reply| reply.id.as_str()).collect();
+        assert_eq!(ids, ["b", "c"]);
+
+        let (replies, _) = scheduler.watch(0).unwrap();
+        assert!(replies.is_empty());
+    }
+
+    #[test]
+    fn cursor_of_a_previous_run_is_out_of_range() {
+        let scheduler = scheduler();
+        scheduler.publish(found("a"));
+
+        assert!(scheduler.watch(1).is_none());
+    }
+
+    #[test]
+    fn dropped_cursor_is_out_of_range() {
+        let scheduler = scheduler();
+        for _ in 0..HISTORY_SIZE + 2 {
+            scheduler.publish(found("a"));
+        }
+        let cursors = cursors(&scheduler);
+
+        assert_eq!(cursors.len(), HISTORY_SIZE);
+        assert!(scheduler.watch(cursors[0] - 2).is_none());
+        assert_eq!(
+            scheduler.watch(cursors[0]).unwrap().0.len(),
+            HISTORY_SIZE - 1
+        );
+    }
+
+    fn service(scheduler: &Arc<Scheduler>) -> CanvasRssService {
+        CanvasRssService {
+            pool: scheduler.pool.clone(),
+            http: http_client(),
+            scheduler: Some(scheduler.clone()),
+        }
+    }
+
+    #[tokio::test]
+    async fn watch_with_a_stale_cursor() {
+        let scheduler = scheduler();
+        scheduler.publish(found("a"));
+
+        let status = service(&scheduler)
+            .watch_announcements(Request::new(WatchAnnouncementsRequest { cursor: 1 }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::OutOfRange);
+    }
+
+    #[tokio::test]
+    async fn watcher_that_fell_behind() {
+        let scheduler = scheduler();
+        let mut stream = service(&scheduler)
+            .watch_announcements(Request::new(WatchAnnouncementsRequest { cursor: 0 }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        // Nothing is read until the live channel overflowed
+        for _ in 0..100 {
+            scheduler.publish(found("a"));
+        }
+
+        let status = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert!(stream.next().await.is_none());
+    }
+}